@@ -1,6 +1,4 @@
 
-use std::iter::FromIterator;
-
 use std::mem;
 use std::ptr;
 
@@ -61,11 +59,8 @@ impl<T> Node<T> {
     fn insert_back(&mut self, v: T) {
         let mut node = Node::new(v);
         node.prev = Rawlink::some(self);
-        match self.next {
-            Some(_) => {
-                mem::swap(&mut self.next, &mut node.next);
-            }
-            None => {}
+        if self.next.is_some() {
+            mem::swap(&mut self.next, &mut node.next);
         }
         self.next = Some(Box::new(node));
     }
@@ -74,8 +69,12 @@ impl<T> Node<T> {
 impl<T> Iterator for Node<T> {
     type Item = Box<Node<T>>;
 
+    /// Unlinks and returns the node following this one.
     fn next(&mut self) -> Option<Box<Node<T>>> {
-        self.next
+        self.next.take().map(|mut node| {
+            self.next = node.next.take();
+            node
+        })
     }
 }
 
@@ -125,14 +124,9 @@ mod tests {
     #[test]
     fn insert_test() {
         let mut node = Node::new(1);
-        println!("{:?}", node);
         node.insert_back(2);
-        println!("{:?}", node);
         node.insert_back(3);
         let v: Vec<_> = node.map(|x| x.value).collect();
-        println!("{:?}", v);
-        //        let next = &node.next.unwrap();
-        //        assert_eq!(next.value, 3);
-        //        assert_eq!(next.prev.resolve().unwrap().value, 1);
+        assert_eq!(v, vec![3, 2]);
     }
 }
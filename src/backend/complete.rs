@@ -0,0 +1,116 @@
+use std::collections::HashMap;
+
+use super::{CharClass, Editor, char_class};
+
+/// How a candidate word was found: its distance in rows from the main caret
+/// when it is in the buffer, and how often it appears in all of them.
+struct Found {
+    distance: Option<usize>,
+    count: usize,
+}
+
+impl Editor {
+    pub fn is_word_char(&self, c: char) -> bool {
+        char_class(&self.word_chars, c) == CharClass::Word
+    }
+    /// The word characters right before the main caret.
+    pub fn word_prefix(&self) -> String {
+        let caret = self.carets.first().expect("Caret not found!");
+        let line = self.buffer.get(caret.row).expect("Line out of bounds!");
+        let mut start = caret.col;
+        while start > 0 && self.is_word_char(line[start - 1]) {
+            start -= 1;
+        }
+        line.range(start..caret.col).cloned().collect()
+    }
+    /// Words of this buffer and the others which complete the prefix, best
+    /// first: words nearer the main caret come first, then words only in the
+    /// other buffers; among equally near words, the more frequent ones. The
+    /// word being typed at the caret doesn't count.
+    pub fn completions(&self, prefix: &str, others: &[&Editor]) -> Vec<String> {
+        let mut found: HashMap<String, Found> = HashMap::new();
+        if prefix.is_empty() {
+            return Vec::new();
+        }
+        let caret = self.carets.first().expect("Caret not found!");
+        for (idx, editor) in Some(self).into_iter().chain(others.iter().cloned()).enumerate() {
+            for row in 0..editor.len() {
+                let line = editor.get(row).expect("Line out of bounds!");
+                let mut col = 0;
+                while col < line.len() {
+                    if !editor.is_word_char(line[col]) {
+                        col += 1;
+                        continue;
+                    }
+                    let start = col;
+                    while col < line.len() && editor.is_word_char(line[col]) {
+                        col += 1;
+                    }
+                    if idx == 0 && row == caret.row && start < caret.col && caret.col <= col {
+                        continue;
+                    }
+                    let word: String = line.range(start..col).cloned().collect();
+                    if word.len() <= prefix.len() || !word.starts_with(prefix) {
+                        continue;
+                    }
+                    let entry = found.entry(word).or_insert(Found {
+                        distance: None,
+                        count: 0,
+                    });
+                    entry.count += 1;
+                    if idx == 0 {
+                        let distance = row.abs_diff(caret.row);
+                        if entry.distance.is_none_or(|nearest| distance < nearest) {
+                            entry.distance = Some(distance);
+                        }
+                    }
+                }
+            }
+        }
+        let mut words: Vec<(String, Found)> = found.into_iter().collect();
+        words.sort_by(|a, b| {
+            let distance = |found: &Found| found.distance.unwrap_or(usize::MAX);
+            distance(&a.1)
+                .cmp(&distance(&b.1))
+                .then(b.1.count.cmp(&a.1.count))
+                .then(a.0.cmp(&b.0))
+        });
+        words.into_iter().map(|(word, _)| word).collect()
+    }
+    /// Completes the prefix typed before every caret to the word, as one
+    /// undoable change.
+    pub fn complete(&mut self, prefix: &str, word: &str) {
+        let rest: String = word.chars().skip(prefix.chars().count()).collect();
+        if !rest.is_empty() {
+            self.mode_normal();
+            self.insert(rest);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Editor;
+
+    #[test]
+    fn completions_test() {
+        let mut other = Editor::new();
+        other.set_text("format forget format fork");
+        let mut editor = Editor::new();
+        editor.set_text("for_each\nfo\n\nforeach forever forever");
+        editor.place_caret(1, 2);
+        assert_eq!(editor.word_prefix(), "fo");
+        assert_eq!(editor.completions("fo", &[&other]),
+                   vec!["for_each", "forever", "foreach", "format", "forget", "fork"]);
+        assert_eq!(editor.completions("forev", &[]), vec!["forever"]);
+        assert!(editor.completions("", &[&other]).is_empty());
+
+        // Every caret gets the word.
+        editor.set_text("fo\nfo");
+        editor.set_carets(&[(0, 2), (1, 2)]);
+        editor.complete("fo", "forever");
+        assert_eq!(editor.get_all(), "forever\nforever");
+        editor.undo();
+        assert_eq!(editor.get_all(), "fo\nfo");
+    }
+}
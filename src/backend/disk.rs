@@ -0,0 +1,162 @@
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+use std::time::SystemTime;
+
+use super::Editor;
+
+/// What the file looked like on disk when it was last read or written.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Stamp {
+    modified: Option<SystemTime>,
+    len: u64,
+    hash: u64,
+}
+
+impl Stamp {
+    /// Stamps the file at the path, which holds `bytes`.
+    fn of(path: &Path, bytes: &[u8]) -> Stamp {
+        Stamp {
+            modified: fs::metadata(path).and_then(|meta| meta.modified()).ok(),
+            len: bytes.len() as u64,
+            hash: hash(bytes),
+        }
+    }
+}
+
+/// A new version of the buffer's file: its text, and the bytes on disk it
+/// was decoded from, which are what the file is stamped with.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DiskChange {
+    pub text: String,
+    bytes: Vec<u8>,
+}
+
+fn hash(bytes: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    bytes.hash(&mut hasher);
+    hasher.finish()
+}
+
+impl Editor {
+    /// Remembers the state of the buffer's file, which holds `bytes` after
+    /// being read or written.
+    pub fn stamp_file(&mut self, path: &Path, bytes: &[u8]) {
+        self.disk = Some(Stamp::of(path, bytes));
+    }
+    /// Reads the file again if it was changed by someone else since it was last
+    /// read or saved. A file which is gone or merely touched does not count.
+    pub fn changed_on_disk(&mut self) -> Option<DiskChange> {
+        let (path, stamp) = match (self.path.as_ref(), self.disk.as_ref()) {
+            (Some(path), Some(stamp)) => (path.clone(), stamp.clone()),
+            _ => return None,
+        };
+        let meta = match fs::metadata(&path) {
+            Ok(meta) => meta,
+            Err(_) => return None,
+        };
+        if meta.len() == stamp.len && meta.modified().ok() == stamp.modified {
+            return None;
+        }
+        let bytes = match fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(_) => return None,
+        };
+        if bytes.len() as u64 == stamp.len && hash(&bytes) == stamp.hash {
+            self.stamp_file(&path, &bytes);
+            return None;
+        }
+        let text = match self.encoding.decode(&bytes) {
+            Ok(text) => text,
+            Err(_) => String::from_utf8_lossy(&bytes).into_owned(),
+        };
+        Some(DiskChange {
+            text,
+            bytes,
+        })
+    }
+    /// Accepts the file as changed on disk as its state, so the change is not
+    /// reported again.
+    pub fn accept_disk(&mut self, change: &DiskChange) {
+        if let Some(path) = self.path.clone() {
+            self.stamp_file(&path, &change.bytes);
+        }
+    }
+    /// Replaces the buffer with the file's new text, keeping the carets where
+    /// they were as far as the new text allows. The undo history is dropped,
+    /// as it no longer applies.
+    pub fn reload(&mut self, change: &DiskChange) {
+        let carets: Vec<(usize, usize)> = self.carets
+                                              .iter()
+                                              .map(|caret| (caret.row, caret.col))
+                                              .collect();
+        self.set_text(&change.text);
+        self.set_carets(&carets);
+        self.undo_pool.borrow_mut().clear();
+        self.redo_pool.borrow_mut().clear();
+        self.accept_disk(change);
+        self.run_hooks();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::process;
+    use super::super::{Editor, Encoding};
+
+    #[test]
+    fn reload_test() {
+        let path = env::temp_dir().join(format!("mal-disk-test-{}", process::id()));
+        fs::write(&path, "first\nsecond\nthird\n").unwrap();
+        let mut editor = Editor::new();
+        editor.read_file(&path).unwrap();
+        assert_eq!(editor.changed_on_disk(), None);
+        editor.place_caret(2, 4);
+        editor.insert(String::from("x"));
+
+        fs::write(&path, "first\nsecond\nthird\n").unwrap();
+        assert_eq!(editor.changed_on_disk(), None);
+        fs::write(&path, "one\ntwo\n").unwrap();
+        let change = editor.changed_on_disk().unwrap();
+        assert_eq!(change.text, "one\ntwo\n");
+        editor.reload(&change);
+        assert_eq!(editor.get_all(), "one\ntwo");
+        assert_eq!(editor.carets.len(), 1);
+        assert_eq!((editor.carets[0].row, editor.carets[0].col), (1, 3));
+        assert!(!editor.is_modified());
+        assert!(!editor.undo());
+        assert_eq!(editor.changed_on_disk(), None);
+
+        // Saving takes the file over again.
+        fs::write(&path, "changed").unwrap();
+        editor.save().unwrap();
+        assert_eq!(editor.changed_on_disk(), None);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn accept_encoded_test() {
+        let path = env::temp_dir().join(format!("mal-disk-encoded-test-{}", process::id()));
+        fs::write(&path, b"caf\xe9\n").unwrap();
+        let mut editor = Editor::new();
+        editor.set_encoding(Encoding::Latin1);
+        editor.read_file(&path).unwrap();
+        editor.insert(String::from("x"));
+        fs::write(&path, b"th\xe9\n").unwrap();
+        let change = editor.changed_on_disk().unwrap();
+        assert_eq!(change.text, "thé\n");
+        editor.accept_disk(&change);
+        assert_eq!(editor.changed_on_disk(), None);
+
+        // Bytes the encoding can't read are taken as they are.
+        editor.set_encoding(Encoding::Utf8);
+        fs::write(&path, b"\xff\xfe\n").unwrap();
+        let change = editor.changed_on_disk().unwrap();
+        editor.reload(&change);
+        assert_eq!(editor.changed_on_disk(), None);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,66 @@
+/// Character encodings files are read and written in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Encoding {
+    Utf8,
+    /// ISO-8859-1, where every byte is the character of the same code.
+    Latin1,
+}
+
+impl Encoding {
+    pub fn parse(name: &str) -> Option<Encoding> {
+        match &name.to_lowercase()[..] {
+            "utf-8" | "utf8" => Some(Encoding::Utf8),
+            "latin1" | "latin-1" | "iso-8859-1" | "iso8859-1" => Some(Encoding::Latin1),
+            _ => None,
+        }
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "utf-8",
+            Encoding::Latin1 => "latin1",
+        }
+    }
+    pub fn decode(self, bytes: &[u8]) -> Result<String, String> {
+        match self {
+            Encoding::Utf8 => {
+                String::from_utf8(bytes.to_vec()).map_err(|why| {
+                    format!("Invalid utf-8 at byte {}", why.utf8_error().valid_up_to())
+                })
+            }
+            Encoding::Latin1 => Ok(bytes.iter().map(|&byte| byte as char).collect()),
+        }
+    }
+    pub fn encode(self, text: &str) -> Result<Vec<u8>, String> {
+        match self {
+            Encoding::Utf8 => Ok(text.as_bytes().to_vec()),
+            Encoding::Latin1 => {
+                text.chars()
+                    .map(|c| {
+                        if (c as u32) < 0x100 {
+                            Ok(c as u8)
+                        } else {
+                            Err(format!("'{}' can't be written in latin1", c))
+                        }
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encoding_test() {
+        assert_eq!(Encoding::parse("UTF-8"), Some(Encoding::Utf8));
+        assert_eq!(Encoding::parse("ISO-8859-1"), Some(Encoding::Latin1));
+        assert_eq!(Encoding::parse("ebcdic"), None);
+        assert_eq!(Encoding::Latin1.decode(b"caf\xe9"), Ok(String::from("café")));
+        assert_eq!(Encoding::Latin1.encode("café"), Ok(b"caf\xe9".to_vec()));
+        assert!(Encoding::Latin1.encode("ä€").is_err());
+        assert!(Encoding::Utf8.decode(b"caf\xe9").is_err());
+        assert_eq!(Encoding::Utf8.encode("é"), Ok(vec![0xc3, 0xa9]));
+    }
+}
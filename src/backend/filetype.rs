@@ -0,0 +1,73 @@
+use std::path::Path;
+
+const EXTENSIONS: &[(&str, &str)] = &[("c", "c"),
+                                                              ("h", "c"),
+                                                              ("cc", "cpp"),
+                                                              ("cpp", "cpp"),
+                                                              ("hpp", "cpp"),
+                                                              ("css", "css"),
+                                                              ("go", "go"),
+                                                              ("html", "html"),
+                                                              ("java", "java"),
+                                                              ("js", "javascript"),
+                                                              ("json", "json"),
+                                                              ("lisp", "lisp"),
+                                                              ("lua", "lua"),
+                                                              ("md", "markdown"),
+                                                              ("py", "python"),
+                                                              ("rb", "ruby"),
+                                                              ("rs", "rust"),
+                                                              ("sh", "sh"),
+                                                              ("toml", "toml"),
+                                                              ("ts", "typescript"),
+                                                              ("txt", "text"),
+                                                              ("yaml", "yaml"),
+                                                              ("yml", "yaml")];
+
+const INTERPRETERS: &[(&str, &str)] = &[("bash", "sh"),
+                                                                ("node", "javascript"),
+                                                                ("python", "python"),
+                                                                ("ruby", "ruby"),
+                                                                ("sh", "sh"),
+                                                                ("zsh", "sh")];
+
+/// Guesses the file type from the file name, or from a `#!` line.
+pub fn detect(filename: &str, first_line: &str) -> &'static str {
+    let path = Path::new(filename);
+    if path.file_name().is_some_and(|name| name == "Makefile") {
+        return "make";
+    }
+    if let Some(ext) = path.extension().and_then(|ext| ext.to_str()) {
+        let ext = ext.to_lowercase();
+        if let Some(&(_, name)) = EXTENSIONS.iter().find(|&&(known, _)| known == ext) {
+            return name;
+        }
+    }
+    if let Some(line) = first_line.strip_prefix("#!") {
+        let mut words = line.split_whitespace();
+        let mut command = words.next().unwrap_or("").rsplit('/').next().unwrap_or("");
+        if command == "env" {
+            command = words.next().unwrap_or("");
+        }
+        let command = command.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+        if let Some(&(_, name)) = INTERPRETERS.iter().find(|&&(known, _)| known == command) {
+            return name;
+        }
+    }
+    "text"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_test() {
+        assert_eq!(detect("src/main.rs", ""), "rust");
+        assert_eq!(detect("README.MD", ""), "markdown");
+        assert_eq!(detect("Makefile", ""), "make");
+        assert_eq!(detect("run", "#!/usr/bin/env python3"), "python");
+        assert_eq!(detect("run", "#!/bin/bash -e"), "sh");
+        assert_eq!(detect("Untitled", "hello"), "text");
+    }
+}
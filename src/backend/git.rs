@@ -0,0 +1,353 @@
+use std::cmp;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use super::{Editor, EditableLine};
+
+/// Largest middle section, in old lines times new lines, diffed line by line.
+/// Anything bigger is reported as a single modified hunk.
+const MAX_DIFF_CELLS: usize = 4_000_000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HunkKind {
+    Added,
+    Modified,
+    Deleted,
+}
+
+/// A run of lines replaced between the committed and the current text.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Hunk {
+    pub old_start: usize,
+    pub old_len: usize,
+    pub new_start: usize,
+    pub new_len: usize,
+}
+
+impl Hunk {
+    pub fn kind(&self) -> HunkKind {
+        if self.old_len == 0 {
+            HunkKind::Added
+        } else if self.new_len == 0 {
+            HunkKind::Deleted
+        } else {
+            HunkKind::Modified
+        }
+    }
+    /// The row the hunk is marked on. Deletions are marked on the line above
+    /// them, or on the first line.
+    pub fn row(&self) -> usize {
+        if self.new_len == 0 {
+            self.new_start.saturating_sub(1)
+        } else {
+            self.new_start
+        }
+    }
+    /// Whether the row is in the hunk. A deletion also covers the line after
+    /// it, where the caret is left after deleting.
+    fn contains(&self, row: usize) -> bool {
+        if self.new_len == 0 {
+            self.row() <= row && row <= self.new_start
+        } else {
+            self.new_start <= row && row < self.new_start + self.new_len
+        }
+    }
+}
+
+/// Reads the committed version of the file with `git show HEAD:file`.
+/// Returns `None` outside a repository or for untracked files.
+pub fn committed_text<P: AsRef<Path>>(path: P) -> Option<String> {
+    let path = path.as_ref();
+    let name = match path.file_name() {
+        Some(name) => name.to_string_lossy().into_owned(),
+        None => return None,
+    };
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let output = Command::new("git")
+                     .current_dir(dir)
+                     .arg("show")
+                     .arg(format!("HEAD:./{}", name))
+                     .stdin(Stdio::null())
+                     .stderr(Stdio::null())
+                     .output();
+    match output {
+        Ok(output) => {
+            if output.status.success() {
+                String::from_utf8(output.stdout).ok()
+            } else {
+                None
+            }
+        }
+        Err(_) => None,
+    }
+}
+
+/// Finds the hunks turning `old` into `new`, in order.
+pub fn diff_lines(old: &[String], new: &[String]) -> Vec<Hunk> {
+    let prefix = old.iter().zip(new).take_while(|&(a, b)| a == b).count();
+    let suffix = old[prefix..]
+                     .iter()
+                     .rev()
+                     .zip(new[prefix..].iter().rev())
+                     .take_while(|&(a, b)| a == b)
+                     .count();
+    let old = &old[prefix..old.len() - suffix];
+    let new = &new[prefix..new.len() - suffix];
+    let (n, m) = (old.len(), new.len());
+    if n == 0 && m == 0 {
+        return Vec::new();
+    }
+    if n == 0 || m == 0 || n * m > MAX_DIFF_CELLS {
+        return vec![Hunk {
+                        old_start: prefix,
+                        old_len: n,
+                        new_start: prefix,
+                        new_len: m,
+                    }];
+    }
+    // lcs[i * (m + 1) + j] is the longest common subsequence of old[i..] and
+    // new[j..].
+    let width = m + 1;
+    let mut lcs = vec![0u32; (n + 1) * width];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i * width + j] = if old[i] == new[j] {
+                lcs[(i + 1) * width + j + 1] + 1
+            } else {
+                cmp::max(lcs[(i + 1) * width + j], lcs[i * width + j + 1])
+            };
+        }
+    }
+    let mut hunks = Vec::new();
+    let mut current: Option<Hunk> = None;
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && old[i] == new[j] {
+            if let Some(hunk) = current.take() {
+                hunks.push(hunk);
+            }
+            i += 1;
+            j += 1;
+            continue;
+        }
+        let hunk = current.get_or_insert(Hunk {
+            old_start: prefix + i,
+            old_len: 0,
+            new_start: prefix + j,
+            new_len: 0,
+        });
+        if j < m && (i == n || lcs[i * width + j + 1] >= lcs[(i + 1) * width + j]) {
+            hunk.new_len += 1;
+            j += 1;
+        } else {
+            hunk.old_len += 1;
+            i += 1;
+        }
+    }
+    if let Some(hunk) = current {
+        hunks.push(hunk);
+    }
+    hunks
+}
+
+impl Editor {
+    /// Sets the text the buffer is compared against for change markers, or
+    /// `None` when the file is not tracked.
+    pub fn set_base(&mut self, text: Option<&str>) {
+        self.base = text.map(|text| text.lines().map(String::from).collect());
+        *self.diffed.borrow_mut() = None;
+    }
+    pub fn has_base(&self) -> bool {
+        self.base.is_some()
+    }
+    /// Hunks changed since the base text. The diff is kept until the text
+    /// changes, as the view asks for it on every frame.
+    pub fn hunks(&self) -> Vec<Hunk> {
+        let base = match self.base {
+            Some(ref base) => base,
+            None => return Vec::new(),
+        };
+        if let Some((version, ref hunks)) = *self.diffed.borrow() {
+            if version == self.version {
+                return hunks.clone();
+            }
+        }
+        let lines: Vec<String> = self.buffer.lines.iter().map(|line| line.extract()).collect();
+        let hunks = diff_lines(base, &lines);
+        *self.diffed.borrow_mut() = Some((self.version, hunks.clone()));
+        hunks
+    }
+    /// Moves the main caret to the next hunk, wrapping around at the end.
+    pub fn next_hunk(&mut self) -> bool {
+        let row = self.carets.first().expect("Caret not found!").row;
+        let hunks = self.hunks();
+        let target = hunks.iter()
+                          .map(|hunk| hunk.row())
+                          .find(|&start| start > row)
+                          .or_else(|| hunks.first().map(|hunk| hunk.row()));
+        self.jump_to_row(target)
+    }
+    /// Moves the main caret to the previous hunk, wrapping around at the top.
+    pub fn prev_hunk(&mut self) -> bool {
+        let row = self.carets.first().expect("Caret not found!").row;
+        let hunks = self.hunks();
+        let target = hunks.iter()
+                          .map(|hunk| hunk.row())
+                          .rev()
+                          .find(|&start| start < row)
+                          .or_else(|| hunks.last().map(|hunk| hunk.row()));
+        self.jump_to_row(target)
+    }
+    fn jump_to_row(&mut self, row: Option<usize>) -> bool {
+        match row {
+            Some(row) => {
+                self.place_caret(row, 0);
+                true
+            }
+            None => false,
+        }
+    }
+    /// Restores the committed lines of the hunk under the main caret.
+    pub fn revert_hunk(&mut self) -> bool {
+        let row = self.carets.first().expect("Caret not found!").row;
+        let hunk = match self.hunks().into_iter().find(|hunk| hunk.contains(row)) {
+            Some(hunk) => hunk,
+            None => return false,
+        };
+        let old = {
+            let base = self.base.as_ref().expect("Base not found!");
+            base[hunk.old_start..hunk.old_start + hunk.old_len].join("\n")
+        };
+        let (first, end) = (hunk.new_start, hunk.new_start + hunk.new_len);
+        self.begin_change();
+        self.mode_normal();
+        if end < self.buffer.len() {
+            // Replace whole lines, newlines included.
+            self.apply_delete((first, 0), (end, 0));
+            if hunk.old_len > 0 {
+                self.apply_insert(first, 0, &(old + "\n"));
+            }
+        } else if first > 0 {
+            // The hunk runs to the end of the buffer; take the newline before it.
+            let last = self.buffer.len() - 1;
+            let start = (first - 1, self.buffer.get(first - 1).expect("Line out of bounds!").len());
+            let last_len = self.buffer.get(last).expect("Line out of bounds!").len();
+            self.apply_delete(start, (last, last_len));
+            if hunk.old_len > 0 {
+                self.apply_insert(start.0, start.1, &(String::from("\n") + &old[..]));
+            }
+        } else {
+            let last = self.buffer.len() - 1;
+            let last_len = self.buffer.get(last).expect("Line out of bounds!").len();
+            self.apply_delete((0, 0), (last, last_len));
+            self.apply_insert(0, 0, &old);
+        }
+        let row = if first < self.buffer.len() { first } else { self.buffer.len() - 1 };
+        self.place_caret(row, 0);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::Editor;
+    use super::super::tests::editor_with;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.split('\n').map(String::from).collect()
+    }
+
+    fn hunk(old_start: usize, old_len: usize, new_start: usize, new_len: usize) -> Hunk {
+        Hunk {
+            old_start,
+            old_len,
+            new_start,
+            new_len,
+        }
+    }
+
+    #[test]
+    fn diff_test() {
+        assert_eq!(diff_lines(&lines("a\nb\nc"), &lines("a\nb\nc")), vec![]);
+        assert_eq!(diff_lines(&lines("a\nc"), &lines("a\nb\nc")), vec![hunk(1, 0, 1, 1)]);
+        assert_eq!(diff_lines(&lines("a\nb\nc"), &lines("a\nc")), vec![hunk(1, 1, 1, 0)]);
+        assert_eq!(diff_lines(&lines("a\nb\nc"), &lines("a\nx\nc")), vec![hunk(1, 1, 1, 1)]);
+        assert_eq!(diff_lines(&lines("a\nb\nc\nd\ne"), &lines("x\nb\nc\ne\nf")),
+                   vec![hunk(0, 1, 0, 1), hunk(3, 1, 3, 0), hunk(5, 0, 4, 1)]);
+        assert_eq!(hunk(1, 0, 1, 1).kind(), HunkKind::Added);
+        assert_eq!(hunk(1, 1, 1, 0).kind(), HunkKind::Deleted);
+        assert_eq!(hunk(1, 1, 1, 0).row(), 0);
+        assert_eq!(hunk(0, 1, 0, 0).row(), 0);
+        assert_eq!(hunk(1, 2, 1, 1).kind(), HunkKind::Modified);
+    }
+
+    fn based(base: &str, text: &str) -> Editor {
+        let mut editor = editor_with(text);
+        editor.set_base(Some(base));
+        editor
+    }
+
+    fn caret_row(editor: &Editor) -> usize {
+        editor.carets.first().unwrap().row
+    }
+
+    #[test]
+    fn hunk_motion_test() {
+        let mut editor = based("a\nb\nc\nd\ne", "x\nb\nc\ne\nf");
+        assert!(editor.next_hunk());
+        assert_eq!(caret_row(&editor), 2);
+        assert!(editor.next_hunk());
+        assert_eq!(caret_row(&editor), 4);
+        assert!(editor.next_hunk());
+        assert_eq!(caret_row(&editor), 0);
+        assert!(editor.prev_hunk());
+        assert_eq!(caret_row(&editor), 4);
+        assert!(editor.prev_hunk());
+        assert_eq!(caret_row(&editor), 2);
+        let mut editor = based("a", "a");
+        assert!(!editor.next_hunk());
+        assert!(!editor.revert_hunk());
+    }
+
+    #[test]
+    fn hunks_follow_edits_test() {
+        let mut editor = based("a\nb", "a\nb");
+        assert!(editor.hunks().is_empty());
+        editor.move_end();
+        editor.insert(String::from("!"));
+        assert_eq!(editor.hunks(), vec![hunk(1, 1, 1, 1)]);
+        assert_eq!(editor.hunks(), vec![hunk(1, 1, 1, 1)]);
+        editor.set_base(Some("a\nb!"));
+        assert!(editor.hunks().is_empty());
+        editor.undo();
+        assert_eq!(editor.hunks(), vec![hunk(1, 1, 1, 1)]);
+    }
+
+    #[test]
+    fn revert_hunk_test() {
+        let mut editor = based("a\nb\nc\nd\ne", "x\nb\nc\ne\nf");
+        assert!(editor.revert_hunk());
+        assert_eq!(editor.get_all(), "a\nb\nc\ne\nf");
+        editor.place_caret(2, 1);
+        assert!(editor.revert_hunk());
+        assert_eq!(editor.get_all(), "a\nb\nc\nd\ne\nf");
+        editor.place_caret(5, 0);
+        assert!(editor.revert_hunk());
+        assert_eq!(editor.get_all(), "a\nb\nc\nd\ne");
+        assert_eq!(caret_row(&editor), 4);
+        assert!(editor.hunks().is_empty());
+        editor.undo();
+        assert_eq!(editor.get_all(), "a\nb\nc\nd\ne\nf");
+        let mut editor = based("a\nb", "x");
+        assert!(editor.revert_hunk());
+        assert_eq!(editor.get_all(), "a\nb");
+        let mut editor = based("a\nb", "a\nb\nc\nd");
+        editor.place_caret(3, 0);
+        assert!(editor.revert_hunk());
+        assert_eq!(editor.get_all(), "a\nb");
+    }
+}
@@ -0,0 +1,129 @@
+use std::cmp;
+
+use super::{Editor, Position};
+
+/// Target of a go-to command.
+#[derive(Debug, PartialEq)]
+pub enum Goto {
+    /// 1-based line and optional 1-based column.
+    Line(usize, Option<usize>),
+    /// Lines relative to the main caret.
+    Relative(isize),
+    /// Percentage through the buffer.
+    Percent(usize),
+}
+
+impl Goto {
+    /// Parses `line`, `line:col`, `+N`, `-N` or `N%`.
+    pub fn parse(spec: &str) -> Option<Goto> {
+        let spec = spec.trim();
+        if let Some(percent) = spec.strip_suffix('%') {
+            return percent.parse().ok().map(Goto::Percent);
+        }
+        if spec.starts_with('+') || spec.starts_with('-') {
+            return spec.parse().ok().map(Goto::Relative);
+        }
+        let mut parts = spec.splitn(2, ':');
+        let line = parts.next().and_then(|line| line.parse().ok())?;
+        match parts.next() {
+            None => Some(Goto::Line(line, None)),
+            Some(col) => col.parse().ok().map(|col| Goto::Line(line, Some(col))),
+        }
+    }
+}
+
+/// Splits a `path:line[:col]` argument into the path and its target.
+pub fn split_location(arg: &str) -> (&str, Option<Goto>) {
+    let mut path = arg;
+    let mut numbers = Vec::new();
+    while numbers.len() < 2 {
+        let number = match path.rfind(':') {
+            Some(idx) if idx > 0 => path[idx + 1..].parse::<usize>().ok().map(|n| (idx, n)),
+            _ => None,
+        };
+        match number {
+            Some((idx, n)) => {
+                numbers.push(n);
+                path = &path[..idx];
+            }
+            None => break,
+        }
+    }
+    match numbers.len() {
+        0 => (arg, None),
+        1 => (path, Some(Goto::Line(numbers[0], None))),
+        _ => (path, Some(Goto::Line(numbers[1], Some(numbers[0])))),
+    }
+}
+
+impl Editor {
+    /// Moves the main caret to the target and drops the other carets.
+    pub fn goto(&mut self, target: &Goto) {
+        let last = self.buffer.len() - 1;
+        let (row, col) = {
+            let main_caret = self.carets.first().expect("Caret not found!");
+            match *target {
+                Goto::Line(line, col) => {
+                    (line.saturating_sub(1), col.map(|col| col.saturating_sub(1)).unwrap_or(0))
+                }
+                Goto::Relative(delta) => {
+                    let row = (main_caret.row as isize).saturating_add(delta);
+                    (if row < 0 { 0 } else { row as usize }, main_caret.col)
+                }
+                Goto::Percent(percent) => (last * cmp::min(percent, 100) / 100, 0),
+            }
+        };
+        let row = if row > last { last } else { row };
+        let len = self.buffer.get(row).expect("Line out of bounds!").len();
+        self.mode_normal();
+        self.carets.truncate(1);
+        self.carets[0] = Position::new(row, if col > len { len } else { col });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::Editor;
+
+    #[test]
+    fn parse_test() {
+        assert_eq!(Goto::parse("120"), Some(Goto::Line(120, None)));
+        assert_eq!(Goto::parse("120:5"), Some(Goto::Line(120, Some(5))));
+        assert_eq!(Goto::parse("+3"), Some(Goto::Relative(3)));
+        assert_eq!(Goto::parse("-3"), Some(Goto::Relative(-3)));
+        assert_eq!(Goto::parse("50%"), Some(Goto::Percent(50)));
+        assert_eq!(Goto::parse("x"), None);
+        assert_eq!(Goto::parse("1:x"), None);
+    }
+
+    #[test]
+    fn split_location_test() {
+        assert_eq!(split_location("file.rs"), ("file.rs", None));
+        assert_eq!(split_location("file.rs:120"), ("file.rs", Some(Goto::Line(120, None))));
+        assert_eq!(split_location("file.rs:120:5"),
+                   ("file.rs", Some(Goto::Line(120, Some(5)))));
+        assert_eq!(split_location("a:b:7"), ("a:b", Some(Goto::Line(7, None))));
+        assert_eq!(split_location(":7"), (":7", None));
+    }
+
+    #[test]
+    fn goto_test() {
+        let mut editor = Editor::new();
+        editor.insert(String::from("one\ntwo\nthree\nfour\nfive"));
+        editor.goto(&Goto::Line(2, Some(3)));
+        assert_eq!((editor.carets[0].row, editor.carets[0].col), (1, 2));
+        editor.goto(&Goto::Relative(2));
+        assert_eq!((editor.carets[0].row, editor.carets[0].col), (3, 2));
+        editor.goto(&Goto::Relative(-10));
+        assert_eq!(editor.carets[0].row, 0);
+        editor.goto(&Goto::Percent(50));
+        assert_eq!((editor.carets[0].row, editor.carets[0].col), (2, 0));
+        editor.goto(&Goto::Percent(usize::MAX));
+        assert_eq!(editor.carets[0].row, 4);
+        editor.goto(&Goto::Relative(isize::MAX));
+        assert_eq!(editor.carets[0].row, 4);
+        editor.goto(&Goto::Line(99, Some(99)));
+        assert_eq!((editor.carets[0].row, editor.carets[0].col), (4, 4));
+    }
+}
@@ -0,0 +1,173 @@
+use std::cell::RefCell;
+use std::mem;
+use std::rc::Rc;
+
+use super::{Editor, EditType};
+
+/// Something that happened to a buffer, passed to every hook.
+#[derive(Clone, Debug, PartialEq)]
+pub enum HookEvent {
+    /// A file or other text was read into the buffer, or replaced its whole
+    /// text, as reloading it or restoring a swap file does.
+    Opened,
+    /// Text was inserted or deleted, undone or redone. For an insertion the
+    /// range is where the text now is; for a deletion, where it was.
+    Changed {
+        start: (usize, usize),
+        end: (usize, usize),
+        text: String,
+        inserted: bool,
+    },
+    /// The buffer is about to be written to its file. Changes the hooks make
+    /// are saved along.
+    BeforeSave,
+    AfterSave,
+    ModeChanged(EditType),
+    /// A key was pressed, named as in key scripts, like `<C-s>`. Reported by
+    /// the view before it handles the key.
+    KeyPressed(String),
+}
+
+pub type Hook = Rc<dyn Fn(&mut Editor, &HookEvent)>;
+
+/// Most rounds of events the hooks set off with their own changes before the
+/// rest are dropped, so hooks which keep answering each other still end.
+const MAX_HOOK_ROUNDS: usize = 8;
+
+/// Callbacks for the events of the buffers they are set on. Clones share the
+/// callbacks, so one set of hooks can serve every buffer.
+#[derive(Clone, Default)]
+pub struct Hooks {
+    hooks: Rc<RefCell<Vec<Hook>>>,
+}
+
+impl Hooks {
+    pub fn new() -> Hooks {
+        Hooks::default()
+    }
+    pub fn add<F>(&self, hook: F)
+        where F: Fn(&mut Editor, &HookEvent) + 'static
+    {
+        self.hooks.borrow_mut().push(Rc::new(hook));
+    }
+    pub fn is_empty(&self) -> bool {
+        self.hooks.borrow().is_empty()
+    }
+}
+
+impl Editor {
+    /// Sets the hooks called for the events of this buffer.
+    pub fn set_hooks(&mut self, hooks: Hooks) {
+        self.hooks = hooks;
+    }
+    /// Queues the event for the hooks, unless there are none to see it.
+    pub fn notify(&mut self, event: HookEvent) {
+        if !self.hooks.is_empty() {
+            self.events.push(event);
+        }
+    }
+    /// Calls the hooks for each event queued since the last call. The buffer
+    /// queues events as they happen and runs the hooks for opening and saving
+    /// right away; the view runs them after each input event, once the
+    /// command is done with the buffer. Events caused by the hooks themselves,
+    /// like a formatter's changes, go to every hook in the next round, for up
+    /// to `MAX_HOOK_ROUNDS` rounds.
+    pub fn run_hooks(&mut self) {
+        for _ in 0..MAX_HOOK_ROUNDS {
+            let events = mem::take(&mut self.events);
+            if events.is_empty() {
+                return;
+            }
+            // Hooks may add hooks.
+            let hooks: Vec<Hook> = self.hooks.hooks.borrow().clone();
+            for event in events.iter() {
+                for hook in hooks.iter() {
+                    hook(self, event);
+                }
+            }
+        }
+        self.events.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::env;
+    use std::fs;
+    use std::process;
+    use std::rc::Rc;
+    use super::*;
+
+    #[test]
+    fn hooks_test() {
+        let path = env::temp_dir().join(format!("mal-hooks-test-{}", process::id()));
+        fs::write(&path, "ab\n").unwrap();
+        let hooks = Hooks::new();
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let log = seen.clone();
+        hooks.add(move |_, event| log.borrow_mut().push(event.clone()));
+        // A formatter of sorts, whose own changes are not reported.
+        hooks.add(|editor, event| {
+            if *event == HookEvent::BeforeSave {
+                editor.move_end();
+                editor.insert(String::from("!"));
+            }
+        });
+        let mut editor = Editor::new();
+        editor.set_hooks(hooks.clone());
+        editor.read_file(&path).unwrap();
+        assert_eq!(*seen.borrow(), vec![HookEvent::Opened]);
+
+        seen.borrow_mut().clear();
+        editor.place_caret(0, 1);
+        editor.mode_select();
+        editor.move_right();
+        editor.insert(String::from("x\ny"));
+        editor.undo();
+        editor.run_hooks();
+        let changed = |start, end, text: &str, inserted| {
+            HookEvent::Changed {
+                start,
+                end,
+                text: String::from(text),
+                inserted,
+            }
+        };
+        assert_eq!(*seen.borrow(),
+                   vec![HookEvent::ModeChanged(EditType::Select),
+                        changed((0, 1), (0, 2), "b", false),
+                        HookEvent::ModeChanged(EditType::Normal),
+                        changed((0, 1), (1, 1), "x\ny", true),
+                        changed((0, 1), (1, 1), "x\ny", false),
+                        changed((0, 1), (0, 2), "b", true)]);
+
+        // The other hooks see the formatter's change.
+        seen.borrow_mut().clear();
+        editor.save().unwrap();
+        assert_eq!(*seen.borrow(),
+                   vec![HookEvent::BeforeSave,
+                        changed((0, 2), (0, 3), "!", true),
+                        HookEvent::AfterSave]);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "ab!\n");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn hook_rounds_test() {
+        let hooks = Hooks::new();
+        // Answers every change with another one, forever.
+        hooks.add(|editor, event| {
+            if let HookEvent::Changed { .. } = *event {
+                editor.insert(String::from("x"));
+            }
+        });
+        let mut editor = Editor::new();
+        editor.set_hooks(hooks);
+        editor.insert(String::from("a"));
+        editor.run_hooks();
+        assert_eq!(editor.get_all(), format!("a{}", "x".repeat(MAX_HOOK_ROUNDS)));
+        editor.run_hooks();
+        assert_eq!(editor.get_all().len(), MAX_HOOK_ROUNDS + 1);
+    }
+}
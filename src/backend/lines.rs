@@ -0,0 +1,307 @@
+use std::cmp::Ordering;
+use std::collections::HashSet;
+
+use super::{Editor, EditableLine};
+
+fn numeric_key(line: &str) -> f64 {
+    let trimmed = line.trim_start();
+    let end = trimmed.char_indices()
+                     .take_while(|&(idx, c)| {
+                         c.is_ascii_digit() || c == '.' || (idx == 0 && (c == '-' || c == '+'))
+                     })
+                     .map(|(idx, c)| idx + c.len_utf8())
+                     .last()
+                     .unwrap_or(0);
+    trimmed[..end].parse().unwrap_or(0.0)
+}
+
+impl Editor {
+    fn line_text(&self, row: usize) -> String {
+        self.buffer.get(row).expect("Line out of bounds!").extract()
+    }
+    /// Rows covered by the caret's selection, or just the caret's row.
+    fn line_block(&self, idx: usize) -> (usize, usize) {
+        match self.selection(idx) {
+            None => {
+                let row = self.carets.get(idx).expect("Caret not found!").row;
+                (row, row)
+            }
+            Some((start, end)) => {
+                if end.1 == 0 && end.0 > start.0 {
+                    (start.0, end.0 - 1)
+                } else {
+                    (start.0, end.0)
+                }
+            }
+        }
+    }
+    /// Calls `f` once with the line block of every caret, top to bottom or
+    /// bottom to top. Carets inside a block already handled are skipped.
+    fn each_line_block<F>(&mut self, bottom_up: bool, mut f: F)
+        where F: FnMut(&mut Editor, usize, usize)
+    {
+        let mut order: Vec<usize> = (0..self.carets.len()).collect();
+        order.sort_by_key(|&idx| self.caret_at(idx));
+        if bottom_up {
+            order.reverse();
+        }
+        let mut done = vec![false; self.carets.len()];
+        for idx in order {
+            if done[idx] {
+                continue;
+            }
+            let (first, last) = self.line_block(idx);
+            for (other, caret) in self.carets.iter().enumerate() {
+                if first <= caret.row && caret.row <= last {
+                    done[other] = true;
+                }
+            }
+            f(self, first, last);
+        }
+    }
+    /// Replaces rows `first..=last` with `lines`, moving carets inside the span
+    /// to the row given by `row_map`.
+    fn replace_lines<F>(&mut self, first: usize, last: usize, lines: &[String], row_map: F)
+        where F: Fn(usize) -> usize
+    {
+        let inside: Vec<(usize, usize, usize)> = self.carets
+                                                     .iter()
+                                                     .enumerate()
+                                                     .filter(|&(_, caret)| {
+                                                         first <= caret.row && caret.row <= last
+                                                     })
+                                                     .map(|(idx, caret)| (idx, caret.row, caret.col))
+                                                     .collect();
+        let end = (last, self.buffer.get(last).expect("Line out of bounds!").len());
+        self.apply_delete((first, 0), end);
+        self.apply_insert(first, 0, &lines.join("\n"));
+        for (idx, row, col) in inside {
+            let row = row_map(row);
+            let len = self.buffer.get(row).expect("Line out of bounds!").len();
+            let caret = self.carets.get_mut(idx).expect("Caret not found!");
+            caret.row = row;
+            caret.col = if len < col { len } else { col };
+        }
+    }
+    /// Duplicates the selection after itself, or the caret's line below it.
+    pub fn duplicate(&mut self) {
+        self.begin_change();
+        for idx in 0..self.carets.len() {
+            match self.selection(idx) {
+                Some((start, end)) => {
+                    let text = self.buffer.slice(start, end);
+                    self.apply_insert(end.0, end.1, &text);
+                }
+                None => {
+                    let (row, col) = self.caret_at(idx);
+                    let line = self.line_text(row);
+                    let len = self.buffer.get(row).expect("Line out of bounds!").len();
+                    self.apply_insert(row, len, &format!("\n{}", line));
+                    let caret = self.carets.get_mut(idx).expect("Caret not found!");
+                    caret.row = row + 1;
+                    caret.col = col;
+                }
+            }
+        }
+        self.merge_carets();
+    }
+    pub fn move_lines_up(&mut self) {
+        self.begin_change();
+        self.each_line_block(false, |editor, first, last| {
+            if first == 0 {
+                return;
+            }
+            let mut lines: Vec<String> = (first..last + 1).map(|row| editor.line_text(row)).collect();
+            lines.push(editor.line_text(first - 1));
+            editor.replace_lines(first - 1, last, &lines, move |row| {
+                if row == first - 1 { last } else { row - 1 }
+            });
+        });
+    }
+    pub fn move_lines_down(&mut self) {
+        self.begin_change();
+        self.each_line_block(true, |editor, first, last| {
+            if last + 1 >= editor.buffer.len() {
+                return;
+            }
+            let mut lines = vec![editor.line_text(last + 1)];
+            lines.extend((first..last + 1).map(|row| editor.line_text(row)));
+            editor.replace_lines(first, last + 1, &lines, move |row| {
+                if row == last + 1 { first } else { row + 1 }
+            });
+        });
+    }
+    /// Joins the next line onto the caret's line, or all selected lines into
+    /// one, replacing the whitespace around each join with a single space.
+    pub fn join_lines(&mut self) {
+        self.begin_change();
+        self.each_line_block(false, |editor, first, last| {
+            let joins = if last > first { last - first } else { 1 };
+            for _ in 0..joins {
+                if first + 1 >= editor.buffer.len() {
+                    break;
+                }
+                let (start, end, space) = {
+                    let line = editor.buffer.get(first).expect("Line out of bounds!");
+                    let next = editor.buffer.get(first + 1).expect("Line out of bounds!");
+                    let trailing = line.iter().rev().take_while(|c| c.is_whitespace()).count();
+                    let leading = next.iter().take_while(|c| c.is_whitespace()).count();
+                    let start = (first, line.len() - trailing);
+                    (start, (first + 1, leading), start.1 > 0 && leading < next.len())
+                };
+                editor.apply_delete(start, end);
+                if space {
+                    editor.apply_insert(start.0, start.1, " ");
+                }
+            }
+        });
+        self.mode_normal();
+        self.merge_carets();
+    }
+    /// Replaces the selected lines of every caret, or the whole buffer when
+    /// nothing is selected, with `f` applied to them.
+    fn transform_lines<F>(&mut self, f: F)
+        where F: Fn(Vec<String>) -> Vec<String>
+    {
+        self.begin_change();
+        if (0..self.carets.len()).all(|idx| self.selection(idx).is_none()) {
+            let last = self.buffer.len() - 1;
+            let lines = f((0..last + 1).map(|row| self.line_text(row)).collect());
+            let count = lines.len();
+            self.replace_lines(0, last, &lines, move |row| if row < count { row } else { count - 1 });
+            self.merge_carets();
+            return;
+        }
+        self.each_line_block(false, |editor, first, last| {
+            let lines = f((first..last + 1).map(|row| editor.line_text(row)).collect());
+            let count = lines.len();
+            editor.replace_lines(first, last, &lines, move |row| {
+                if row < first + count { row } else { first + count - 1 }
+            });
+            let range = lines.iter().map(|line| line.chars().count() + 1).sum::<usize>() - 1;
+            for caret in editor.carets.iter_mut() {
+                if first <= caret.row && caret.row < first + count {
+                    caret.row = first;
+                    caret.col = 0;
+                    caret.range = range as isize;
+                }
+            }
+        });
+        self.merge_carets();
+    }
+    /// Sorts lines, comparing leading numbers when `numeric` is set.
+    pub fn sort_lines(&mut self, numeric: bool, ignore_case: bool) {
+        self.transform_lines(|mut lines| {
+            lines.sort_by(|a, b| {
+                let by_number = if numeric {
+                    numeric_key(a).partial_cmp(&numeric_key(b)).unwrap_or(Ordering::Equal)
+                } else {
+                    Ordering::Equal
+                };
+                by_number.then_with(|| {
+                    if ignore_case {
+                        a.to_lowercase().cmp(&b.to_lowercase())
+                    } else {
+                        a.cmp(b)
+                    }
+                })
+            });
+            lines
+        });
+    }
+    /// Removes repeated lines, keeping the first occurrence.
+    pub fn unique_lines(&mut self, ignore_case: bool) {
+        self.transform_lines(|lines| {
+            let mut seen = HashSet::new();
+            lines.into_iter()
+                 .filter(|line| {
+                     seen.insert(if ignore_case { line.to_lowercase() } else { line.clone() })
+                 })
+                 .collect()
+        });
+    }
+    pub fn reverse_lines(&mut self) {
+        self.transform_lines(|mut lines| {
+            lines.reverse();
+            lines
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use super::super::tests::editor_with;
+
+    fn select_rows(editor: &mut Editor, first: usize, last: usize) {
+        editor.move_top();
+        for _ in 0..first {
+            editor.move_down();
+        }
+        editor.mode_select();
+        for _ in first..last {
+            editor.move_down();
+        }
+        editor.move_line_end();
+    }
+
+    #[test]
+    fn duplicate_test() {
+        let mut editor = editor_with("abc\ndef");
+        editor.move_right();
+        editor.duplicate();
+        assert_eq!(editor.get_all(), "abc\nabc\ndef");
+        assert_eq!((editor.carets[0].row, editor.carets[0].col), (1, 1));
+        editor.mode_select();
+        editor.move_right();
+        editor.move_right();
+        editor.duplicate();
+        assert_eq!(editor.get_all(), "abc\nabcbc\ndef");
+    }
+
+    #[test]
+    fn move_lines_test() {
+        let mut editor = editor_with("a\nb\nc\nd");
+        editor.move_down();
+        editor.move_lines_down();
+        assert_eq!(editor.get_all(), "a\nc\nb\nd");
+        assert_eq!(editor.carets[0].row, 2);
+        editor.move_lines_down();
+        editor.move_lines_down();
+        assert_eq!(editor.get_all(), "a\nc\nd\nb");
+        select_rows(&mut editor, 1, 2);
+        editor.move_lines_up();
+        assert_eq!(editor.get_all(), "c\nd\na\nb");
+        assert_eq!(editor.selection(0), Some(((0, 0), (1, 1))));
+        editor.move_lines_up();
+        assert_eq!(editor.get_all(), "c\nd\na\nb");
+    }
+
+    #[test]
+    fn join_lines_test() {
+        let mut editor = editor_with("foo   \n    bar\n\nbaz");
+        editor.join_lines();
+        assert_eq!(editor.get_all(), "foo bar\n\nbaz");
+        assert_eq!((editor.carets[0].row, editor.carets[0].col), (0, 0));
+        select_rows(&mut editor, 0, 2);
+        editor.join_lines();
+        assert_eq!(editor.get_all(), "foo bar baz");
+    }
+
+    #[test]
+    fn sort_lines_test() {
+        let mut editor = editor_with("b\n10 x\nA\n9 y\na");
+        editor.sort_lines(false, false);
+        assert_eq!(editor.get_all(), "10 x\n9 y\nA\na\nb");
+        editor.sort_lines(true, false);
+        assert_eq!(editor.get_all(), "A\na\nb\n9 y\n10 x");
+        select_rows(&mut editor, 0, 2);
+        editor.sort_lines(false, true);
+        assert_eq!(editor.get_all(), "A\na\nb\n9 y\n10 x");
+        editor.reverse_lines();
+        assert_eq!(editor.get_all(), "b\na\nA\n9 y\n10 x");
+        editor.unique_lines(true);
+        assert_eq!(editor.get_all(), "b\na\n9 y\n10 x");
+        assert_eq!(editor.selection(0), Some(((0, 0), (1, 1))));
+    }
+}
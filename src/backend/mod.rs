@@ -3,18 +3,33 @@
 #![allow(unused_variables)]
 #![allow(non_snake_case)]
 
-use std::ops::{AddAssign, SubAssign};
-use std::error::Error;
-use std::fs::File;
-use std::path::Path;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use std::cell::RefCell;
-use std::cell::Cell;
-use std::collections::VecDeque;
+use std::collections::{BTreeSet, VecDeque};
+use std::mem;
 use std::iter::FromIterator;
-use std::io::Read;
+use std::io::{self, Read, Write};
+use std::process;
 
 mod buffer;
+mod complete;
+mod disk;
+mod encoding;
+mod filetype;
+pub mod git;
+mod goto;
+mod hooks;
+mod lines;
+mod search;
+pub mod swap;
+
+pub use self::encoding::Encoding;
+pub use self::git::HunkKind;
+pub use self::goto::{Goto, split_location};
+pub use self::hooks::{HookEvent, Hooks};
+pub use self::search::RangeEdit;
 
 pub type LineBuffer = VecDeque<char>;
 
@@ -59,19 +74,52 @@ impl EditableLine for LineBuffer {
         len
     }
     fn extract(&self) -> String {
-        String::from_iter(self.into_iter().cloned())
+        String::from_iter(self.iter().cloned())
+    }
+}
+
+/// Rows changed since the view last drew the buffer.
+pub struct Dirty {
+    pub rows: BTreeSet<usize>,
+    /// Set when the line count changed; every row from here on moved.
+    pub below: Option<usize>,
+}
+
+impl Dirty {
+    fn new() -> Dirty {
+        Dirty {
+            rows: BTreeSet::new(),
+            below: None,
+        }
+    }
+    fn mark_below(&mut self, row: usize) {
+        self.below = Some(match self.below {
+            Some(below) if below < row => below,
+            _ => row,
+        });
+    }
+    pub fn contains(&self, row: usize) -> bool {
+        self.rows.contains(&row) ||
+        match self.below {
+            Some(below) => below <= row,
+            None => false,
+        }
     }
 }
 
 pub struct TextBuffer {
     lines: VecDeque<LineBuffer>,
+    dirty: Dirty,
 }
 
 impl TextBuffer {
     fn new() -> TextBuffer {
         let mut lines = VecDeque::new();
         lines.push_back(EditableLine::new());
-        TextBuffer { lines: lines }
+        TextBuffer {
+            lines,
+            dirty: Dirty::new(),
+        }
     }
     fn new_line(&mut self, index: usize, line: LineBuffer) {
         self.lines.insert(index, line);
@@ -91,6 +139,91 @@ impl TextBuffer {
     fn len(&self) -> usize {
         self.lines.len()
     }
+    /// Inserts `text` at the position and returns the position just after it.
+    fn insert_text(&mut self, row: usize, col: usize, text: &str) -> (usize, usize) {
+        if text.contains('\n') {
+            self.dirty.mark_below(row);
+        } else {
+            self.dirty.rows.insert(row);
+        }
+        let mut tail = self.get_mut(row)
+                           .expect("Line out of bounds!")
+                           .split_off(col);
+        let mut segments = text.split('\n');
+        let mut row = row;
+        let mut col = col;
+        if let Some(first) = segments.next() {
+            col += self.get_mut(row)
+                       .expect("Line out of bounds!")
+                       .input_back(String::from(first));
+        }
+        for segment in segments {
+            let mut line: LineBuffer = EditableLine::new();
+            col = line.input_back(String::from(segment));
+            row += 1;
+            self.new_line(row, line);
+        }
+        self.get_mut(row).expect("Line out of bounds!").append(&mut tail);
+        (row, col)
+    }
+    /// Removes the text between two positions and returns it.
+    fn delete(&mut self, start: (usize, usize), end: (usize, usize)) -> String {
+        let (srow, scol) = start;
+        let (erow, ecol) = end;
+        if srow == erow {
+            self.dirty.rows.insert(srow);
+            return self.get_mut(srow)
+                       .expect("Line out of bounds!")
+                       .drain(scol..ecol)
+                       .collect();
+        }
+        self.dirty.mark_below(srow);
+        let mut tail = self.get_mut(erow)
+                           .expect("Line out of bounds!")
+                           .split_off(ecol);
+        let mut text: String = self.get_mut(srow)
+                                   .expect("Line out of bounds!")
+                                   .split_off(scol)
+                                   .into_iter()
+                                   .collect();
+        for _ in srow..erow {
+            let line = self.remove(srow + 1).expect("Line out of bounds!");
+            text.push('\n');
+            text.push_str(&line.extract());
+        }
+        self.get_mut(srow).expect("Line out of bounds!").append(&mut tail);
+        text
+    }
+    /// Returns the text between two positions.
+    fn slice(&self, start: (usize, usize), end: (usize, usize)) -> String {
+        let mut text = String::new();
+        for row in start.0..end.0 + 1 {
+            let line = self.get(row).expect("Line out of bounds!");
+            let from = if row == start.0 { start.1 } else { 0 };
+            let to = if row == end.0 { end.1 } else { line.len() };
+            if row > start.0 {
+                text.push('\n');
+            }
+            text.extend(line.range(from..to));
+        }
+        text
+    }
+    /// Character offset of a position, counting line breaks as one character.
+    fn offset_of(&self, row: usize, col: usize) -> usize {
+        self.lines.iter().take(row).map(|line| line.len() + 1).sum::<usize>() + col
+    }
+    /// Position of a character offset, clamped to the buffer.
+    fn position_of(&self, offset: usize) -> (usize, usize) {
+        let mut offset = offset;
+        for (row, line) in self.lines.iter().enumerate() {
+            if offset <= line.len() {
+                return (row, offset);
+            }
+            offset -= line.len() + 1;
+        }
+        let row = self.len() - 1;
+        (row, self.get(row).expect("Line out of bounds!").len())
+    }
     fn extract(&self) -> String {
         let mut view = String::new();
         let mut iter = self.lines.iter();
@@ -109,26 +242,193 @@ impl TextBuffer {
     }
 }
 
+#[derive(Clone)]
 pub struct Position {
     pub row: usize,
     pub col: usize,
+    /// Selection length in characters from the caret, line breaks included.
+    /// Negative when the selection extends backwards.
     pub range: isize,
+    /// Column that vertical moves try to return to after passing shorter lines.
+    pub preferred_col: Option<usize>,
+}
+
+impl Position {
+    pub fn new(row: usize, col: usize) -> Position {
+        Position {
+            row,
+            col,
+            range: 0,
+            preferred_col: None,
+        }
+    }
+}
+
+#[derive(PartialEq)]
+enum CharClass {
+    Space,
+    Word,
+    Punct,
+}
+
+fn char_class(word_chars: &str, c: char) -> CharClass {
+    if c.is_whitespace() {
+        CharClass::Space
+    } else if c.is_alphanumeric() || word_chars.contains(c) {
+        CharClass::Word
+    } else {
+        CharClass::Punct
+    }
 }
 
-enum EditType {
+fn is_blank(line: &LineBuffer) -> bool {
+    line.iter().all(|c| c.is_whitespace())
+}
+
+/// Whether motions move the carets or extend their selections.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EditType {
     Normal,
     Select,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+}
+
+impl LineEnding {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            LineEnding::Lf => "LF",
+            LineEnding::CrLf => "CRLF",
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
 enum ChangeType {
     Insert,
     Delete,
 }
 
 pub struct Change {
+    /// Carets before the command that made this change, restored on undo.
     pos: Vec<Position>,
+    row: usize,
+    col: usize,
     text: String,
     ctype: ChangeType,
+    /// Changes made by one command share a sequence number and are undone
+    /// together.
+    seq: usize,
+}
+
+/// Returns the position just after `text` inserted at `row`/`col`.
+fn text_end(row: usize, col: usize, text: &str) -> (usize, usize) {
+    match text.rfind('\n') {
+        None => (row, col + text.chars().count()),
+        Some(idx) => (row + text.matches('\n').count(), text[idx + 1..].chars().count()),
+    }
+}
+
+fn shift_for_insert(caret: &mut Position, start: (usize, usize), end: (usize, usize)) {
+    if (caret.row, caret.col) < start {
+        return;
+    }
+    if caret.row == start.0 {
+        caret.col = end.1 + caret.col - start.1;
+    }
+    caret.row += end.0 - start.0;
+}
+
+fn shift_for_delete(caret: &mut Position, start: (usize, usize), end: (usize, usize)) {
+    if (caret.row, caret.col) <= start {
+        return;
+    }
+    if (caret.row, caret.col) <= end {
+        caret.row = start.0;
+        caret.col = start.1;
+    } else if caret.row == end.0 {
+        caret.row = start.0;
+        caret.col = start.1 + caret.col - end.1;
+    } else {
+        caret.row -= end.0 - start.0;
+    }
+}
+
+fn word_left_of(buffer: &TextBuffer, word_chars: &str, row: usize, col: usize) -> (usize, usize) {
+    if col == 0 {
+        if row > 0 {
+            return (row - 1, buffer.get(row - 1).expect("Line out of bounds!").len());
+        }
+        return (row, col);
+    }
+    let line = buffer.get(row).expect("Line out of bounds!");
+    let mut col = col;
+    while col > 0 && char_class(word_chars, line[col - 1]) == CharClass::Space {
+        col -= 1;
+    }
+    if col > 0 {
+        let class = char_class(word_chars, line[col - 1]);
+        while col > 0 && char_class(word_chars, line[col - 1]) == class {
+            col -= 1;
+        }
+    }
+    (row, col)
+}
+
+fn word_right_of(buffer: &TextBuffer, word_chars: &str, row: usize, col: usize) -> (usize, usize) {
+    let line = buffer.get(row).expect("Line out of bounds!");
+    let len = line.len();
+    if col >= len {
+        if row < buffer.len() - 1 {
+            return (row + 1, 0);
+        }
+        return (row, len);
+    }
+    let mut col = col;
+    let class = char_class(word_chars, line[col]);
+    if class != CharClass::Space {
+        while col < len && char_class(word_chars, line[col]) == class {
+            col += 1;
+        }
+    }
+    while col < len && char_class(word_chars, line[col]) == CharClass::Space {
+        col += 1;
+    }
+    (row, col)
+}
+
+/// Writes the file by way of a temporary file beside it, so a failed write
+/// leaves the old contents in place. The file keeps its permissions, and a
+/// link is written through rather than replaced.
+fn write_bytes(path: &Path, bytes: &[u8]) -> Result<(), String> {
+    let target = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let name = target.file_name().map_or(String::new(), |name| name.to_string_lossy().into_owned());
+    let tmp = target.with_file_name(format!(".{}.{}.tmp", name, process::id()));
+    let result = match File::create(&tmp) {
+        Ok(mut file) => {
+            let written = file.write_all(bytes)
+                              .and_then(|()| match fs::metadata(&target) {
+                                  Ok(metadata) => file.set_permissions(metadata.permissions()),
+                                  Err(_) => Ok(()),
+                              })
+                              .and_then(|()| file.sync_all())
+                              .and_then(|()| fs::rename(&tmp, &target));
+            if written.is_err() {
+                let _ = fs::remove_file(&tmp);
+            }
+            written
+        }
+        // A directory we may not create files in: write in place.
+        Err(ref why) if why.kind() == io::ErrorKind::PermissionDenied => {
+            File::create(&target).and_then(|mut file| file.write_all(bytes))
+        }
+        Err(why) => Err(why),
+    };
+    result.map_err(|why| format!("Couldn't write {}: {}", path.display(), why))
 }
 
 pub struct Editor {
@@ -138,253 +438,838 @@ pub struct Editor {
     redo_pool: Rc<RefCell<Vec<Change>>>,
     modified: bool,
     filename: String,
+    /// Where the buffer is saved, unset for a new buffer.
+    path: Option<PathBuf>,
+    /// Whether the last line ends with a newline in the file.
+    final_newline: bool,
     edit_type: EditType,
+    word_chars: String,
+    change_seq: usize,
+    change_carets: Vec<Position>,
+    /// Committed lines the change markers are computed against.
+    base: Option<Vec<String>>,
+    /// Hunks last diffed against the base, and the version they are of.
+    diffed: RefCell<Option<(usize, Vec<git::Hunk>)>>,
+    line_ending: LineEnding,
+    /// Bumped on every change to the text.
+    version: usize,
+    /// State of the file when it was last read or saved.
+    disk: Option<disk::Stamp>,
+    encoding: Encoding,
+    /// Whether saving over the file is refused.
+    readonly: bool,
+    hooks: Hooks,
+    /// Events waiting for the hooks.
+    events: Vec<HookEvent>,
 }
 
 impl Editor {
     pub fn new() -> Editor {
-        let mut carets = Vec::new();
-        carets.push(Position {
-            row: 0,
-            col: 0,
-            range: 0,
-        });
+        let carets = vec![Position::new(0, 0)];
         Editor {
             buffer: TextBuffer::new(),
-            carets: carets,
+            carets,
             undo_pool: Rc::new(RefCell::new(Vec::new())),
             redo_pool: Rc::new(RefCell::new(Vec::new())),
             modified: false,
             filename: String::from("Untitled"),
+            path: None,
+            final_newline: true,
             edit_type: EditType::Normal,
+            word_chars: String::from("_"),
+            change_seq: 0,
+            change_carets: Vec::new(),
+            base: None,
+            diffed: RefCell::new(None),
+            line_ending: LineEnding::Lf,
+            version: 0,
+            disk: None,
+            encoding: Encoding::Utf8,
+            readonly: false,
+            hooks: Hooks::new(),
+            events: Vec::new(),
         }
     }
+    /// Sets the characters which are treated as part of a word in addition to
+    /// alphanumerics.
+    pub fn set_word_chars(&mut self, chars: &str) {
+        self.word_chars = String::from(chars);
+    }
     pub fn mode_select(&mut self) {
-        self.edit_type = EditType::Select;
+        self.set_edit_type(EditType::Select);
     }
     pub fn mode_normal(&mut self) {
-        self.edit_type = EditType::Normal;
+        self.set_edit_type(EditType::Normal);
+        for caret in self.carets.iter_mut() {
+            caret.range = 0;
+        }
+    }
+    pub fn is_selecting(&self) -> bool {
+        match self.edit_type {
+            EditType::Select => true,
+            EditType::Normal => false,
+        }
+    }
+    /// Returns the ordered start and end of the caret's selection, if any.
+    pub fn selection(&self, idx: usize) -> Option<((usize, usize), (usize, usize))> {
+        let caret = self.carets.get(idx).expect("Caret not found!");
+        if caret.range == 0 {
+            return None;
+        }
+        let head = self.head_of(caret);
+        if caret.range > 0 {
+            Some(((caret.row, caret.col), head))
+        } else {
+            Some((head, (caret.row, caret.col)))
+        }
     }
-    pub fn read_file<P: AsRef<Path>>(&mut self, path: P) {
-        let mut file = match File::open(path.as_ref()) {
-            Err(why) => {
-                panic!("Couldn't open {}: {}",
-                       path.as_ref().display(),
-                       why.description())
+    /// Leaves a single caret at the position, clamped to the buffer.
+    pub fn place_caret(&mut self, row: usize, col: usize) {
+        let last = self.buffer.len() - 1;
+        let row = if row > last { last } else { row };
+        let len = self.buffer.get(row).expect("Line out of bounds!").len();
+        self.mode_normal();
+        self.carets.truncate(1);
+        self.carets[0] = Position::new(row, if col > len { len } else { col });
+    }
+    /// Puts carets at the positions, moved into the text where they fall
+    /// outside it. The first is the main caret.
+    pub fn set_carets(&mut self, carets: &[(usize, usize)]) {
+        let last = self.buffer.len() - 1;
+        let mut placed = Vec::new();
+        for &(row, col) in carets.iter() {
+            let row = if row > last { last } else { row };
+            let len = self.buffer.get(row).expect("Line out of bounds!").len();
+            placed.push(Position::new(row, if col > len { len } else { col }));
+        }
+        if placed.is_empty() {
+            placed.push(Position::new(0, 0));
+        }
+        self.mode_normal();
+        self.carets = placed;
+        self.merge_carets();
+    }
+    /// Selects from the main caret to the position.
+    pub fn select_to(&mut self, row: usize, col: usize) {
+        let (anchor_row, anchor_col) = self.caret_at(0);
+        let offset = {
+            let last = self.buffer.len() - 1;
+            let row = if row > last { last } else { row };
+            let len = self.buffer.get(row).expect("Line out of bounds!").len();
+            self.buffer.offset_of(row, if col > len { len } else { col })
+        };
+        self.mode_select();
+        self.carets[0].range = offset as isize - self.buffer.offset_of(anchor_row, anchor_col) as isize;
+    }
+    /// Selects the word, whitespace run or punctuation run at the position.
+    pub fn select_word_at(&mut self, row: usize, col: usize) {
+        self.place_caret(row, col);
+        let (row, col) = self.caret_at(0);
+        let (start, end) = {
+            let line = self.buffer.get(row).expect("Line out of bounds!");
+            if line.is_empty() {
+                return;
+            }
+            let col = if col < line.len() { col } else { col - 1 };
+            let class = char_class(&self.word_chars, line[col]);
+            let mut start = col;
+            while start > 0 && char_class(&self.word_chars, line[start - 1]) == class {
+                start -= 1;
+            }
+            let mut end = col + 1;
+            while end < line.len() && char_class(&self.word_chars, line[end]) == class {
+                end += 1;
             }
-            Ok(file) => file,
+            (start, end)
         };
-        let mut tmp = String::new();
-        match file.read_to_string(&mut tmp) {
-            Err(why) => {
-                panic!("Couldn't read {}: {}",
-                       path.as_ref().display(),
-                       why.description())
+        self.carets[0].col = start;
+        self.select_to(row, end);
+    }
+    /// Selects the whole line including its line break.
+    pub fn select_line(&mut self, row: usize) {
+        self.place_caret(row, 0);
+        let row = self.caret_at(0).0;
+        if row + 1 < self.buffer.len() {
+            self.select_to(row + 1, 0);
+        } else {
+            let len = self.buffer.get(row).expect("Line out of bounds!").len();
+            self.select_to(row, len);
+        }
+    }
+    fn head_of(&self, caret: &Position) -> (usize, usize) {
+        let offset = self.buffer.offset_of(caret.row, caret.col) as isize + caret.range;
+        self.buffer.position_of(if offset < 0 { 0 } else { offset as usize })
+    }
+    /// In Select mode, puts every caret on its selection head so that a motion
+    /// moves the head, and returns the anchors to restore in `end_motion`.
+    fn begin_motion(&mut self) -> Option<Vec<(usize, usize)>> {
+        if !self.is_selecting() {
+            return None;
+        }
+        let mut anchors = Vec::new();
+        for idx in 0..self.carets.len() {
+            let head = self.head_of(self.carets.get(idx).expect("Caret not found!"));
+            let caret = self.carets.get_mut(idx).expect("Caret not found!");
+            anchors.push((caret.row, caret.col));
+            caret.row = head.0;
+            caret.col = head.1;
+            caret.range = 0;
+        }
+        Some(anchors)
+    }
+    fn end_motion(&mut self, anchors: Option<Vec<(usize, usize)>>) {
+        if let Some(anchors) = anchors {
+            for (caret, anchor) in self.carets.iter_mut().zip(anchors) {
+                let head = self.buffer.offset_of(caret.row, caret.col) as isize;
+                caret.range = head - self.buffer.offset_of(anchor.0, anchor.1) as isize;
+                caret.row = anchor.0;
+                caret.col = anchor.1;
             }
-            Ok(_) => {}
         }
-        let mut lines = tmp.lines();
+    }
+    pub fn edit_type(&self) -> EditType {
+        self.edit_type
+    }
+    fn set_edit_type(&mut self, edit_type: EditType) {
+        if self.edit_type != edit_type {
+            self.edit_type = edit_type;
+            self.notify(HookEvent::ModeChanged(edit_type));
+        }
+    }
+    /// Reads the file in the buffer's encoding and makes it the buffer's
+    /// file.
+    pub fn read_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), String> {
+        let path = path.as_ref();
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(why) => return Err(format!("Couldn't read {}: {}", path.display(), why)),
+        };
+        self.load(&bytes).map_err(|why| format!("Couldn't read {}: {}", path.display(), why))?;
+        self.filename = path.display().to_string();
+        self.path = Some(path.to_path_buf());
+        self.stamp_file(path, &bytes);
+        self.opened();
+        Ok(())
+    }
+    /// Reads the text from any source, such as standard input. The buffer is
+    /// left without a file.
+    pub fn read_from<R: Read>(&mut self, mut reader: R) -> Result<(), String> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).map_err(|why| format!("Couldn't read: {}", why))?;
+        self.load(&bytes)?;
+        self.opened();
+        Ok(())
+    }
+    /// Writes the text as it would be saved to any destination, such as
+    /// standard output.
+    pub fn write_to<W: Write>(&self, mut writer: W) -> Result<(), String> {
+        let bytes = self.encoding.encode(&self.file_text())?;
+        writer.write_all(&bytes)
+              .and_then(|()| writer.flush())
+              .map_err(|why| format!("Couldn't write: {}", why))
+    }
+    fn load(&mut self, bytes: &[u8]) -> Result<(), String> {
+        let text = self.encoding.decode(bytes)?;
+        self.set_text(&text);
+        Ok(())
+    }
+    /// Tells the hooks about the text just read, dropping the events of
+    /// whatever the buffer held before.
+    fn opened(&mut self) {
+        self.events.clear();
+        self.notify(HookEvent::Opened);
+        self.run_hooks();
+    }
+    /// Writes the buffer to its file.
+    pub fn save(&mut self) -> Result<(), String> {
+        let path = match self.path {
+            Some(ref path) => path.clone(),
+            None => return Err(String::from("No file name")),
+        };
+        if self.readonly {
+            return Err(format!("{} is read-only; save it under another name", self.filename));
+        }
+        self.write_file(&path)
+    }
+    /// Writes the buffer to the path and makes it the buffer's file.
+    pub fn save_as<P: AsRef<Path>>(&mut self, path: P) -> Result<(), String> {
+        self.write_file(path.as_ref())?;
+        self.filename = path.as_ref().display().to_string();
+        self.path = Some(path.as_ref().to_path_buf());
+        self.readonly = false;
+        Ok(())
+    }
+    /// Writes the buffer to another file, leaving the buffer's own file and
+    /// modified state alone.
+    pub fn save_copy<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let path = path.as_ref();
+        write_bytes(path, &self.file_bytes(path)?)
+    }
+    fn write_file(&mut self, path: &Path) -> Result<(), String> {
+        self.notify(HookEvent::BeforeSave);
+        self.run_hooks();
+        let bytes = self.file_bytes(path)?;
+        write_bytes(path, &bytes)?;
+        self.modified = false;
+        self.stamp_file(path, &bytes);
+        self.notify(HookEvent::AfterSave);
+        self.run_hooks();
+        Ok(())
+    }
+    /// The text encoded as written to the path.
+    fn file_bytes(&self, path: &Path) -> Result<Vec<u8>, String> {
+        self.encoding
+            .encode(&self.file_text())
+            .map_err(|why| format!("Couldn't write {}: {}", path.display(), why))
+    }
+    /// The text as written to disk, with the file's line endings.
+    fn file_text(&self) -> String {
+        let ending = match self.line_ending {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+        };
+        let mut text = self.get_all().replace('\n', ending);
+        if self.final_newline {
+            text.push_str(ending);
+        }
+        text
+    }
+    /// Replaces the whole buffer with the text and puts a single caret at the
+    /// top.
+    pub fn set_text(&mut self, text: &str) {
         self.buffer.lines = VecDeque::new();
-        for line in lines {
+        for line in text.lines() {
             let lb: LineBuffer = line.chars().collect();
             self.buffer.lines.push_back(lb);
         }
+        if self.buffer.lines.is_empty() {
+            self.buffer.lines.push_back(LineBuffer::new());
+        }
+        self.line_ending = if text.contains("\r\n") { LineEnding::CrLf } else { LineEnding::Lf };
+        self.final_newline = text.ends_with('\n');
+        self.modified = false;
+        self.version += 1;
+        self.carets = vec![Position::new(0, 0)];
+        self.edit_type = EditType::Normal;
+        self.buffer.dirty.mark_below(0);
+        self.notify(HookEvent::Opened);
     }
-    pub fn insert_line(&mut self) {
+    pub fn filename(&self) -> &str {
+        &self.filename
+    }
+    pub fn is_modified(&self) -> bool {
+        self.modified
+    }
+    /// A number which changes whenever the text does.
+    pub fn version(&self) -> usize {
+        self.version
+    }
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+    pub fn line_ending(&self) -> LineEnding {
+        self.line_ending
+    }
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+    /// Sets the encoding the file is read and written in.
+    pub fn set_encoding(&mut self, encoding: Encoding) {
+        self.encoding = encoding;
+    }
+    pub fn is_readonly(&self) -> bool {
+        self.readonly
+    }
+    pub fn set_readonly(&mut self, readonly: bool) {
+        self.readonly = readonly;
+    }
+    pub fn filetype(&self) -> &'static str {
+        let first_line = self.buffer.get(0).map(|line| line.extract()).unwrap_or_default();
+        filetype::detect(&self.filename, &first_line)
+    }
+    /// Characters selected by the caret.
+    pub fn selection_len(&self, idx: usize) -> usize {
+        self.carets.get(idx).expect("Caret not found!").range.unsigned_abs()
+    }
+    /// Whether anything changed since the last `take_dirty`.
+    pub fn is_dirty(&self) -> bool {
+        !self.buffer.dirty.rows.is_empty() || self.buffer.dirty.below.is_some()
+    }
+    /// Asks the view to repaint the row, for changes outside the text.
+    pub fn mark_dirty(&mut self, row: usize) {
+        self.buffer.dirty.rows.insert(row);
+    }
+    /// Returns the rows changed since the last call.
+    pub fn take_dirty(&mut self) -> Dirty {
+        mem::replace(&mut self.buffer.dirty, Dirty::new())
+    }
+    /// Starts a new undoable command; every change recorded until the next call
+    /// is undone as one step.
+    fn begin_change(&mut self) {
+        self.change_seq += 1;
+        self.change_carets = self.carets.clone();
         for caret in self.carets.iter_mut() {
-            let conc_line = self.buffer
-                                .get_mut(caret.row)
-                                .expect("Line out of bounds!")
-                                .split_off(caret.col);
-            self.buffer.new_line(caret.row + 1, conc_line);
-            caret.row += 1;
-            caret.col = 0;
+            caret.preferred_col = None;
         }
     }
+    /// Queues the change for the hooks, undone when `undo` is set.
+    fn notify_change(&mut self, ctype: ChangeType, row: usize, col: usize, text: &str, undo: bool) {
+        let inserted = match ctype {
+            ChangeType::Insert => !undo,
+            ChangeType::Delete => undo,
+        };
+        self.notify(HookEvent::Changed {
+            start: (row, col),
+            end: text_end(row, col, text),
+            text: String::from(text),
+            inserted,
+        });
+    }
+    fn record(&mut self, ctype: ChangeType, row: usize, col: usize, text: String) {
+        self.modified = true;
+        self.version += 1;
+        self.notify_change(ctype, row, col, &text, false);
+        self.redo_pool.borrow_mut().clear();
+        self.undo_pool.borrow_mut().push(Change {
+            pos: self.change_carets.clone(),
+            row,
+            col,
+            text,
+            ctype,
+            seq: self.change_seq,
+        });
+    }
+    fn apply_insert(&mut self, row: usize, col: usize, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        let end = self.buffer.insert_text(row, col, text);
+        for caret in self.carets.iter_mut() {
+            shift_for_insert(caret, (row, col), end);
+        }
+        self.record(ChangeType::Insert, row, col, String::from(text));
+    }
+    fn apply_delete(&mut self, start: (usize, usize), end: (usize, usize)) {
+        if start >= end {
+            return;
+        }
+        let text = self.buffer.delete(start, end);
+        for caret in self.carets.iter_mut() {
+            shift_for_delete(caret, start, end);
+        }
+        self.record(ChangeType::Delete, start.0, start.1, text);
+    }
+    /// Deletes the selection of every caret and leaves Select mode. Returns
+    /// `false` when nothing was selected.
+    fn delete_selections(&mut self) -> bool {
+        let mut deleted = false;
+        for idx in 0..self.carets.len() {
+            if let Some((start, end)) = self.selection(idx) {
+                self.apply_delete(start, end);
+                self.carets.get_mut(idx).expect("Caret not found!").range = 0;
+                deleted = true;
+            }
+        }
+        self.mode_normal();
+        deleted
+    }
+    /// Deletes the range returned by `range_of` for every caret as one command.
+    /// Selections, when present, are deleted instead.
+    fn delete_each<F>(&mut self, range_of: F)
+        where F: Fn(&TextBuffer, &str, usize, usize) -> ((usize, usize), (usize, usize))
+    {
+        self.begin_change();
+        if self.delete_selections() {
+            self.merge_carets();
+            return;
+        }
+        for idx in 0..self.carets.len() {
+            let (row, col) = self.caret_at(idx);
+            let (start, end) = range_of(&self.buffer, &self.word_chars, row, col);
+            self.apply_delete(start, end);
+        }
+        self.merge_carets();
+    }
+    fn caret_at(&self, idx: usize) -> (usize, usize) {
+        let caret = self.carets.get(idx).expect("Caret not found!");
+        (caret.row, caret.col)
+    }
+    /// Drops carets which ended up on the same position, keeping the first.
+    fn merge_carets(&mut self) {
+        let mut idx = 1;
+        while idx < self.carets.len() {
+            let pos = self.caret_at(idx);
+            if (0..idx).any(|other| self.caret_at(other) == pos) {
+                self.carets.remove(idx);
+            } else {
+                idx += 1;
+            }
+        }
+    }
+    pub fn insert_line(&mut self) {
+        self.insert(String::from("\n"));
+    }
     pub fn backspace(&mut self) {
-        let main_caret = self.carets.get_mut(0).expect("Caret not found!");
-        if main_caret.col == 0 {
-            if main_caret.row > 0 {
-                let mut conc_line = self.buffer
-                                        .get(main_caret.row)
-                                        .expect("Line out of bounds!")
-                                        .clone();
-                {
-                    let mut prev_line = self.buffer
-                                            .get_mut(main_caret.row - 1)
-                                            .expect("Line out of bounds!");
-                    prev_line.append(&mut conc_line);
-                }
-                self.buffer.remove(main_caret.row);
-                main_caret.row -= 1;
-                main_caret.col = self.buffer
-                                     .get(main_caret.row)
-                                     .expect("Line out of bounds!")
-                                     .len();
+        self.delete_each(|buffer, _, row, col| {
+            if col > 0 {
+                ((row, col - 1), (row, col))
+            } else if row > 0 {
+                ((row - 1, buffer.get(row - 1).expect("Line out of bounds!").len()), (row, col))
+            } else {
+                ((row, col), (row, col))
             }
-        } else {
-            let removed_char = self.buffer
-                                   .get_mut(main_caret.row)
-                                   .expect("Caret out of bounds!")
-                                   .remove(main_caret.col - 1);
-            match removed_char {
-                Some(c) => {
-                    main_caret.col -= 1;
+        });
+    }
+    pub fn delete_forward(&mut self) {
+        self.delete_each(|buffer, _, row, col| {
+            if col < buffer.get(row).expect("Line out of bounds!").len() {
+                ((row, col), (row, col + 1))
+            } else if row < buffer.len() - 1 {
+                ((row, col), (row + 1, 0))
+            } else {
+                ((row, col), (row, col))
+            }
+        });
+    }
+    pub fn delete_word_backward(&mut self) {
+        self.delete_each(|buffer, word_chars, row, col| {
+            (word_left_of(buffer, word_chars, row, col), (row, col))
+        });
+    }
+    pub fn delete_word_forward(&mut self) {
+        self.delete_each(|buffer, word_chars, row, col| {
+            ((row, col), word_right_of(buffer, word_chars, row, col))
+        });
+    }
+    pub fn delete_to_line_start(&mut self) {
+        self.delete_each(|_, _, row, col| ((row, 0), (row, col)));
+    }
+    /// Deletes to the end of the line, or joins the next line when the caret is
+    /// already there.
+    pub fn delete_to_line_end(&mut self) {
+        self.delete_each(|buffer, _, row, col| {
+            let len = buffer.get(row).expect("Line out of bounds!").len();
+            if col < len {
+                ((row, col), (row, len))
+            } else if row < buffer.len() - 1 {
+                ((row, col), (row + 1, 0))
+            } else {
+                ((row, col), (row, col))
+            }
+        });
+    }
+    pub fn delete_line(&mut self) {
+        self.delete_each(|buffer, _, row, col| {
+            if row < buffer.len() - 1 {
+                ((row, 0), (row + 1, 0))
+            } else if row > 0 {
+                let prev_len = buffer.get(row - 1).expect("Line out of bounds!").len();
+                let len = buffer.get(row).expect("Line out of bounds!").len();
+                ((row - 1, prev_len), (row, len))
+            } else {
+                ((row, 0), (row, buffer.get(row).expect("Line out of bounds!").len()))
+            }
+        });
+    }
+    /// Reverts the last command. Returns `false` when there is nothing to undo.
+    pub fn undo(&mut self) -> bool {
+        let seq = match self.undo_pool.borrow().last() {
+            Some(change) => change.seq,
+            None => return false,
+        };
+        self.modified = true;
+        self.version += 1;
+        loop {
+            let change = {
+                let mut pool = self.undo_pool.borrow_mut();
+                match pool.last().map(|change| change.seq) {
+                    Some(s) if s == seq => pool.pop().unwrap(),
+                    _ => break,
+                }
+            };
+            match change.ctype {
+                ChangeType::Insert => {
+                    let end = text_end(change.row, change.col, &change.text);
+                    self.buffer.delete((change.row, change.col), end);
+                }
+                ChangeType::Delete => {
+                    self.buffer.insert_text(change.row, change.col, &change.text);
                 }
-                None => {}
             }
+            self.notify_change(change.ctype, change.row, change.col, &change.text, true);
+            self.carets = change.pos.clone();
+            self.redo_pool.borrow_mut().push(change);
         }
+        true
     }
-    pub fn move_left(&mut self) {
-        match self.edit_type {
-            EditType::Normal => {
-                for caret in self.carets.iter_mut() {
-                    if caret.col == 0 {
-                        if caret.row > 0 {
-                            caret.row -= 1;
-                            caret.col = self.buffer
-                                            .get(caret.row)
-                                            .expect("Caret out of bounds!")
-                                            .len();
-                        }
-                    } else {
-                        caret.col -= 1;
-                    }
+    /// Reapplies the last undone command. Returns `false` when there is nothing
+    /// to redo.
+    pub fn redo(&mut self) -> bool {
+        let seq = match self.redo_pool.borrow().last() {
+            Some(change) => change.seq,
+            None => return false,
+        };
+        self.modified = true;
+        self.version += 1;
+        let mut carets = Vec::new();
+        loop {
+            let change = {
+                let mut pool = self.redo_pool.borrow_mut();
+                match pool.last().map(|change| change.seq) {
+                    Some(s) if s == seq => pool.pop().unwrap(),
+                    _ => break,
                 }
-            }
-            EditType::Select => {
-                for caret in self.carets.iter_mut() {
-                    caret.range -= 1;
-                    let ranged_col = caret.range + caret.col as isize;
-                    if ranged_col < 0 {
-                        panic!("select to before line.");
+            };
+            let (row, col) = match change.ctype {
+                ChangeType::Insert => self.buffer.insert_text(change.row, change.col, &change.text),
+                ChangeType::Delete => {
+                    let end = text_end(change.row, change.col, &change.text);
+                    self.buffer.delete((change.row, change.col), end);
+                    (change.row, change.col)
+                }
+            };
+            self.notify_change(change.ctype, change.row, change.col, &change.text, false);
+            for caret in carets.iter_mut() {
+                match change.ctype {
+                    ChangeType::Insert => shift_for_insert(caret, (change.row, change.col), (row, col)),
+                    ChangeType::Delete => {
+                        let end = text_end(change.row, change.col, &change.text);
+                        shift_for_delete(caret, (change.row, change.col), end)
                     }
                 }
             }
+            carets.push(Position::new(row, col));
+            self.undo_pool.borrow_mut().push(change);
         }
-
+        self.carets = carets;
+        self.merge_carets();
+        true
     }
-    pub fn move_right(&mut self) {
-        match self.edit_type {
-            EditType::Normal => {
-                for caret in self.carets.iter_mut() {
-                    caret.col += 1;
-                    let line = self.buffer.get(caret.row).expect("Caret out of bounds!");
-                    let len = line.len();
-                    if len < caret.col {
-                        if self.buffer.len() - 1 <= caret.row {
-                            caret.col = len;
-                        } else {
-                            caret.col = 0;
-                            caret.row += 1;
-                        }
-                    }
+    pub fn move_left(&mut self) {
+        let anchors = self.begin_motion();
+        for caret in self.carets.iter_mut() {
+            caret.preferred_col = None;
+            if caret.col == 0 {
+                if caret.row > 0 {
+                    caret.row -= 1;
+                    caret.col = self.buffer
+                                    .get(caret.row)
+                                    .expect("Caret out of bounds!")
+                                    .len();
                 }
+            } else {
+                caret.col -= 1;
             }
-            EditType::Select => {
-                let row_max = self.len() - 1;
-                let col_row_max = self.get(row_max).expect("Line out of bounds!").len();
-                for caret in self.carets.iter_mut() {
-                    caret.range += 1;
-                    let ranged_col = caret.range + caret.col as isize;
-                    if caret.row == row_max && ranged_col > col_row_max as isize {
-                        caret.range = (col_row_max - caret.col) as isize;
-                    }
+        }
+        self.end_motion(anchors);
+    }
+    pub fn move_right(&mut self) {
+        let anchors = self.begin_motion();
+        for caret in self.carets.iter_mut() {
+            caret.preferred_col = None;
+            caret.col += 1;
+            let line = self.buffer.get(caret.row).expect("Caret out of bounds!");
+            let len = line.len();
+            if len < caret.col {
+                if self.buffer.len() - 1 <= caret.row {
+                    caret.col = len;
+                } else {
+                    caret.col = 0;
+                    caret.row += 1;
                 }
             }
         }
-
+        self.end_motion(anchors);
     }
     pub fn move_top(&mut self) {
+        let anchors = self.begin_motion();
         for caret in self.carets.iter_mut() {
             caret.row = 0;
             caret.col = 0;
+            caret.preferred_col = None;
         }
+        self.end_motion(anchors);
     }
     pub fn move_end(&mut self) {
+        let anchors = self.begin_motion();
         let row = self.buffer.len() - 1;
         let col = self.buffer.back().expect("Buffer is empty!").len();
         for caret in self.carets.iter_mut() {
             caret.row = row;
             caret.col = col;
+            caret.preferred_col = None;
         }
+        self.end_motion(anchors);
     }
     pub fn move_pageup(&mut self, row: usize) {
+        let anchors = self.begin_motion();
         for caret in self.carets.iter_mut() {
             if caret.row < row {
                 caret.row = 0;
                 caret.col = 0;
+                caret.preferred_col = None;
             } else {
+                let col = caret.preferred_col.unwrap_or(caret.col);
                 caret.row -= row;
                 let len = self.buffer.get(caret.row).expect("Line out of bounds!").len();
-                if len < caret.col {
-                    caret.col = len;
-                }
+                caret.col = if len < col { len } else { col };
+                caret.preferred_col = Some(col);
             }
         }
+        self.end_motion(anchors);
     }
     pub fn move_pagedown(&mut self, row: usize) {
+        let anchors = self.begin_motion();
         let row_max = self.buffer.len() - 1;
         let col_row_max = self.buffer.get(row_max).expect("Line out of bounds!").len();
         for caret in self.carets.iter_mut() {
             if caret.row + row > row_max {
                 caret.row = row_max;
                 caret.col = col_row_max;
+                caret.preferred_col = None;
             } else {
+                let col = caret.preferred_col.unwrap_or(caret.col);
                 caret.row += row;
                 let len = self.buffer.get(caret.row).expect("Line out of bounds!").len();
-                if len < caret.col {
-                    caret.col = len;
-                }
+                caret.col = if len < col { len } else { col };
+                caret.preferred_col = Some(col);
             }
         }
+        self.end_motion(anchors);
     }
     pub fn move_up(&mut self) {
+        let anchors = self.begin_motion();
         for caret in self.carets.iter_mut() {
+            let col = caret.preferred_col.unwrap_or(caret.col);
             if caret.row > 0 {
                 caret.row -= 1;
                 let len = self.buffer.get(caret.row).expect("Line out of bounds!").len();
-                if len < caret.col {
-                    caret.col = len;
-                }
+                caret.col = if len < col { len } else { col };
             } else {
                 caret.col = 0;
             }
+            caret.preferred_col = Some(col);
         }
+        self.end_motion(anchors);
     }
     pub fn move_down(&mut self) {
+        let anchors = self.begin_motion();
         for caret in self.carets.iter_mut() {
+            let col = caret.preferred_col.unwrap_or(caret.col);
             if caret.row < self.buffer.len() - 1 {
                 caret.row += 1;
                 let len = self.buffer.get(caret.row).expect("Line out of bounds!").len();
-                if len < caret.col {
-                    caret.col = len;
-                }
+                caret.col = if len < col { len } else { col };
             } else {
                 caret.col = self.buffer
                                 .get(caret.row)
                                 .expect("Caret out of bounds!")
                                 .len();
             }
+            caret.preferred_col = Some(col);
         }
-
+        self.end_motion(anchors);
+    }
+    pub fn move_word_left(&mut self) {
+        let anchors = self.begin_motion();
+        for caret in self.carets.iter_mut() {
+            let (row, col) = word_left_of(&self.buffer, &self.word_chars, caret.row, caret.col);
+            caret.row = row;
+            caret.col = col;
+            caret.preferred_col = None;
+        }
+        self.end_motion(anchors);
+    }
+    pub fn move_word_right(&mut self) {
+        let anchors = self.begin_motion();
+        for caret in self.carets.iter_mut() {
+            let (row, col) = word_right_of(&self.buffer, &self.word_chars, caret.row, caret.col);
+            caret.row = row;
+            caret.col = col;
+            caret.preferred_col = None;
+        }
+        self.end_motion(anchors);
+    }
+    /// Moves to the blank line above the current paragraph, or to the top of
+    /// the buffer.
+    pub fn move_paragraph_up(&mut self) {
+        let anchors = self.begin_motion();
+        for caret in self.carets.iter_mut() {
+            caret.preferred_col = None;
+            let mut row = caret.row;
+            while row > 0 && is_blank(self.buffer.get(row - 1).expect("Line out of bounds!")) {
+                row -= 1;
+            }
+            while row > 0 && !is_blank(self.buffer.get(row - 1).expect("Line out of bounds!")) {
+                row -= 1;
+            }
+            caret.row = if row > 0 { row - 1 } else { 0 };
+            caret.col = 0;
+        }
+        self.end_motion(anchors);
+    }
+    /// Moves to the blank line below the current paragraph, or to the end of
+    /// the buffer.
+    pub fn move_paragraph_down(&mut self) {
+        let anchors = self.begin_motion();
+        let row_max = self.buffer.len() - 1;
+        for caret in self.carets.iter_mut() {
+            caret.preferred_col = None;
+            let mut row = caret.row;
+            while row < row_max && is_blank(self.buffer.get(row + 1).expect("Line out of bounds!")) {
+                row += 1;
+            }
+            while row < row_max && !is_blank(self.buffer.get(row + 1).expect("Line out of bounds!")) {
+                row += 1;
+            }
+            if row < row_max {
+                caret.row = row + 1;
+                caret.col = 0;
+            } else {
+                caret.row = row_max;
+                caret.col = self.buffer.get(row_max).expect("Line out of bounds!").len();
+            }
+        }
+        self.end_motion(anchors);
+    }
+    pub fn move_line_start(&mut self) {
+        let anchors = self.begin_motion();
+        for caret in self.carets.iter_mut() {
+            caret.col = 0;
+            caret.preferred_col = None;
+        }
+        self.end_motion(anchors);
+    }
+    pub fn move_line_first_nonblank(&mut self) {
+        let anchors = self.begin_motion();
+        for caret in self.carets.iter_mut() {
+            let line = self.buffer.get(caret.row).expect("Caret out of bounds!");
+            caret.col = line.iter().take_while(|c| c.is_whitespace()).count();
+            caret.preferred_col = None;
+        }
+        self.end_motion(anchors);
+    }
+    pub fn move_line_end(&mut self) {
+        let anchors = self.begin_motion();
+        for caret in self.carets.iter_mut() {
+            caret.col = self.buffer.get(caret.row).expect("Caret out of bounds!").len();
+            caret.preferred_col = None;
+        }
+        self.end_motion(anchors);
     }
     pub fn insert_char(&mut self, c: char) {
-        let main_caret = self.carets.get_mut(0).expect("Caret not found!");
-        let line = &mut self.buffer.get_mut(main_caret.row).expect("Caret out of bounds!");
-        line.insert(main_caret.col, c);
-        main_caret.col += 1;
+        let mut text = String::new();
+        text.push(c);
+        self.insert(text);
     }
     pub fn insert(&mut self, text: String) {
-        let main_caret = self.carets.get_mut(0).expect("Caret not found!");
-        let line = &mut self.buffer.get_mut(main_caret.row).expect("Caret out of bounds!");
-        if line.len() == main_caret.col {
-            main_caret.col += line.input_back(text);
-        } else if main_caret.col == 0 {
-            main_caret.col += line.input_front(text);
-        } else {
-            main_caret.col += line.input_at(&main_caret.col, text);
+        self.begin_change();
+        self.delete_selections();
+        for idx in 0..self.carets.len() {
+            let (row, col) = self.caret_at(idx);
+            self.apply_insert(row, col, &text);
         }
+        self.merge_carets();
     }
     pub fn len(&self) -> usize {
         self.buffer.len()
@@ -411,4 +1296,252 @@ mod tests {
         editor.insert(String::from("lo world!!"));
         assert_eq!(editor.get_all(), "Hello world!!");
     }
+
+    /// An editor holding the text, for the tests of this module and of the
+    /// others under it.
+    pub fn editor_with(text: &str) -> Editor {
+        let mut editor = Editor::new();
+        editor.buffer.lines = text.split('\n').map(|line| line.chars().collect()).collect();
+        editor
+    }
+
+    fn caret(editor: &Editor) -> (usize, usize) {
+        let caret = editor.carets.first().unwrap();
+        (caret.row, caret.col)
+    }
+
+    #[test]
+    fn word_motion_test() {
+        let mut editor = editor_with("let foo_bar = baz(1);\nnext");
+        editor.move_word_right();
+        assert_eq!(caret(&editor), (0, 4));
+        editor.move_word_right();
+        assert_eq!(caret(&editor), (0, 12));
+        editor.move_word_right();
+        assert_eq!(caret(&editor), (0, 14));
+        editor.move_line_end();
+        editor.move_word_right();
+        assert_eq!(caret(&editor), (1, 0));
+        editor.move_word_left();
+        assert_eq!(caret(&editor), (0, 21));
+        editor.move_word_left();
+        assert_eq!(caret(&editor), (0, 19));
+        editor.set_word_chars("");
+        editor.move_line_start();
+        editor.move_word_right();
+        editor.move_word_right();
+        assert_eq!(caret(&editor), (0, 7));
+    }
+
+    #[test]
+    fn paragraph_motion_test() {
+        let mut editor = editor_with("a\nb\n\n\nc\nd");
+        editor.move_paragraph_down();
+        assert_eq!(caret(&editor), (2, 0));
+        editor.move_paragraph_down();
+        assert_eq!(caret(&editor), (5, 1));
+        editor.move_paragraph_up();
+        assert_eq!(caret(&editor), (3, 0));
+        editor.move_paragraph_up();
+        assert_eq!(caret(&editor), (0, 0));
+    }
+
+    #[test]
+    fn line_motion_test() {
+        let mut editor = editor_with("    indented");
+        editor.move_line_end();
+        assert_eq!(caret(&editor), (0, 12));
+        editor.move_line_first_nonblank();
+        assert_eq!(caret(&editor), (0, 4));
+        editor.move_line_start();
+        assert_eq!(caret(&editor), (0, 0));
+    }
+
+    #[test]
+    fn delete_test() {
+        let mut editor = editor_with("foo bar\nbaz");
+        editor.move_line_end();
+        editor.delete_forward();
+        assert_eq!(editor.get_all(), "foo barbaz");
+        editor.delete_word_backward();
+        assert_eq!(editor.get_all(), "foo baz");
+        assert_eq!(caret(&editor), (0, 4));
+        editor.delete_word_forward();
+        assert_eq!(editor.get_all(), "foo ");
+        editor.delete_to_line_start();
+        assert_eq!(editor.get_all(), "");
+        assert_eq!(caret(&editor), (0, 0));
+    }
+
+    #[test]
+    fn kill_line_test() {
+        let mut editor = editor_with("one\ntwo\nthree");
+        editor.move_right();
+        editor.delete_to_line_end();
+        assert_eq!(editor.get_all(), "o\ntwo\nthree");
+        editor.delete_to_line_end();
+        assert_eq!(editor.get_all(), "otwo\nthree");
+        editor.delete_line();
+        assert_eq!(editor.get_all(), "three");
+        editor.delete_line();
+        assert_eq!(editor.get_all(), "");
+        assert_eq!(editor.len(), 1);
+    }
+
+    #[test]
+    fn multi_caret_delete_test() {
+        let mut editor = editor_with("ab\ncd");
+        editor.carets.get_mut(0).unwrap().col = 1;
+        editor.carets.push(Position::new(1, 1));
+        editor.backspace();
+        assert_eq!(editor.get_all(), "b\nd");
+        editor.backspace();
+        assert_eq!(editor.get_all(), "bd");
+        assert_eq!(editor.carets.len(), 2);
+        editor.delete_forward();
+        assert_eq!(editor.get_all(), "");
+        assert_eq!(editor.carets.len(), 1);
+    }
+
+    #[test]
+    fn undo_redo_test() {
+        let mut editor = editor_with("hello world");
+        editor.move_line_end();
+        editor.delete_word_backward();
+        editor.insert(String::from("there\nfriend"));
+        assert_eq!(editor.get_all(), "hello there\nfriend");
+        assert!(editor.undo());
+        assert_eq!(editor.get_all(), "hello ");
+        assert!(editor.undo());
+        assert_eq!(editor.get_all(), "hello world");
+        assert_eq!(caret(&editor), (0, 11));
+        assert!(!editor.undo());
+        assert!(editor.redo());
+        assert!(editor.redo());
+        assert_eq!(editor.get_all(), "hello there\nfriend");
+        assert_eq!(caret(&editor), (1, 6));
+        assert!(!editor.redo());
+    }
+
+    #[test]
+    fn mouse_selection_test() {
+        let mut editor = editor_with("foo bar.baz\nnext");
+        editor.place_caret(7, 50);
+        assert_eq!(caret(&editor), (1, 4));
+        editor.place_caret(0, 2);
+        editor.select_to(1, 2);
+        assert_eq!(editor.selection(0), Some(((0, 2), (1, 2))));
+        editor.select_word_at(0, 5);
+        assert_eq!(editor.selection(0), Some(((0, 4), (0, 7))));
+        editor.select_word_at(0, 11);
+        assert_eq!(editor.selection(0), Some(((0, 8), (0, 11))));
+        editor.select_line(0);
+        assert_eq!(editor.selection(0), Some(((0, 0), (1, 0))));
+        editor.select_line(1);
+        assert_eq!(editor.selection(0), Some(((1, 0), (1, 4))));
+    }
+
+    #[test]
+    fn dirty_test() {
+        let mut editor = editor_with("a\nb\nc");
+        editor.take_dirty();
+        editor.move_down();
+        editor.insert_char('x');
+        let dirty = editor.take_dirty();
+        assert!(dirty.contains(1));
+        assert!(!dirty.contains(0) && !dirty.contains(2));
+        editor.backspace();
+        editor.backspace();
+        let dirty = editor.take_dirty();
+        assert!(dirty.contains(0) && dirty.contains(2));
+        assert!(!editor.take_dirty().contains(0));
+    }
+
+    #[test]
+    fn sticky_column_test() {
+        let mut editor = editor_with("long line\nab\nanother line");
+        editor.move_line_end();
+        editor.move_down();
+        assert_eq!(caret(&editor), (1, 2));
+        editor.move_down();
+        assert_eq!(caret(&editor), (2, 9));
+        editor.move_left();
+        editor.move_up();
+        assert_eq!(caret(&editor), (1, 2));
+        editor.move_up();
+        assert_eq!(caret(&editor), (0, 8));
+    }
+
+    #[test]
+    fn modified_test() {
+        let mut editor = editor_with("b\na");
+        assert!(!editor.is_modified());
+        editor.move_down();
+        editor.move_up();
+        assert!(!editor.is_modified());
+        editor.sort_lines(false, false);
+        assert!(editor.is_modified());
+        editor.set_text("a");
+        assert!(!editor.is_modified());
+        editor.delete_forward();
+        assert!(editor.is_modified());
+        assert!(editor.save().is_err());
+    }
+
+    #[test]
+    fn stream_test() {
+        let mut editor = Editor::new();
+        editor.read_from(&b"one\r\ntwo\r\n"[..]).unwrap();
+        assert_eq!(editor.get_all(), "one\ntwo");
+        assert_eq!(editor.path(), None);
+        editor.insert(String::from("x"));
+        let mut out = Vec::new();
+        editor.write_to(&mut out).unwrap();
+        assert_eq!(out, b"xone\r\ntwo\r\n".to_vec());
+        assert!(editor.read_from(&b"\xff"[..]).is_err());
+    }
+
+    #[test]
+    fn file_test() {
+        let path = std::env::temp_dir().join(format!("mal-file-test-{}", std::process::id()));
+        let mut editor = Editor::new();
+        assert!(editor.read_file(&path).is_err());
+        fs::write(&path, b"caf\xe9\n").unwrap();
+        assert!(editor.read_file(&path).is_err());
+        editor.set_encoding(Encoding::Latin1);
+        editor.read_file(&path).unwrap();
+        assert_eq!(editor.get_all(), "café");
+        editor.set_readonly(true);
+        editor.insert(String::from("ü"));
+        assert!(editor.save().is_err());
+        editor.set_readonly(false);
+        editor.save().unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"\xfccaf\xe9\n".to_vec());
+        editor.insert(String::from("€"));
+        assert!(editor.save().is_err());
+        assert!(editor.is_modified());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn save_replaces_file_test() {
+        use std::os::unix::fs::{PermissionsExt, symlink};
+        let dir = std::env::temp_dir().join(format!("mal-save-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("run.sh");
+        fs::write(&path, "echo\n").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        let link = dir.join("link.sh");
+        symlink(&path, &link).unwrap();
+        let mut editor = Editor::new();
+        editor.read_file(&link).unwrap();
+        editor.insert(String::from("#"));
+        editor.save().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "#echo\n");
+        assert!(fs::symlink_metadata(&link).unwrap().file_type().is_symlink());
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o755);
+        // No temporary file is left behind.
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -0,0 +1,158 @@
+use super::Editor;
+
+/// Text to put in place of what lies between a start and an end, as rows
+/// and columns.
+pub type RangeEdit = ((usize, usize), (usize, usize), String);
+
+/// Character offsets of the occurrences of the pattern, left to right and not
+/// overlapping.
+fn find_all(text: &[char], pattern: &[char]) -> Vec<usize> {
+    let mut found = Vec::new();
+    if pattern.is_empty() || pattern.len() > text.len() {
+        return found;
+    }
+    let mut offset = 0;
+    while offset + pattern.len() <= text.len() {
+        if &text[offset..offset + pattern.len()] == pattern {
+            found.push(offset);
+            offset += pattern.len();
+        } else {
+            offset += 1;
+        }
+    }
+    found
+}
+
+impl Editor {
+    /// Moves the main caret to the next occurrence of the text at or after
+    /// it, wrapping around at the end. The text may span lines. Returns
+    /// `false` when there is none.
+    pub fn find(&mut self, pattern: &str) -> bool {
+        let text: Vec<char> = self.get_all().chars().collect();
+        let pattern: Vec<char> = pattern.chars().collect();
+        let found = find_all(&text, &pattern);
+        let caret = {
+            let main_caret = self.carets.first().expect("Caret not found!");
+            self.buffer.offset_of(main_caret.row, main_caret.col)
+        };
+        match found.iter().find(|&&offset| offset >= caret).or(found.first()) {
+            Some(&offset) => {
+                let (row, col) = self.buffer.position_of(offset);
+                self.place_caret(row, col);
+                true
+            }
+            None => false,
+        }
+    }
+    /// Replaces every occurrence of the text as one undoable change. Returns
+    /// how many were replaced.
+    pub fn replace_all(&mut self, pattern: &str, with: &str) -> usize {
+        let text: Vec<char> = self.get_all().chars().collect();
+        let len = pattern.chars().count();
+        let found = find_all(&text, &pattern.chars().collect::<Vec<char>>());
+        if found.is_empty() {
+            return 0;
+        }
+        self.begin_change();
+        self.mode_normal();
+        // From the end, so the offsets before each one stay valid.
+        for &offset in found.iter().rev() {
+            let start = self.buffer.position_of(offset);
+            let end = self.buffer.position_of(offset + len);
+            self.apply_delete(start, end);
+            self.apply_insert(start.0, start.1, with);
+        }
+        found.len()
+    }
+    /// Replaces each range of text, given as start and end rows and columns,
+    /// as one undoable change. Positions past the end of a line or of the
+    /// buffer are taken as those ends.
+    pub fn replace_ranges(&mut self, edits: &[RangeEdit]) {
+        if edits.is_empty() {
+            return;
+        }
+        let mut edits: Vec<(usize, usize, &str)> = edits.iter()
+                                                       .map(|&(start, end, ref text)| {
+                                                           (self.clamped_offset(start),
+                                                            self.clamped_offset(end),
+                                                            &text[..])
+                                                       })
+                                                       .collect();
+        edits.sort_by_key(|&(start, end, _)| (start, end));
+        self.begin_change();
+        self.mode_normal();
+        for &(start, end, text) in edits.iter().rev() {
+            let start = self.buffer.position_of(start);
+            let end = self.buffer.position_of(end);
+            if start != end {
+                self.apply_delete(start, end);
+            }
+            if !text.is_empty() {
+                self.apply_insert(start.0, start.1, text);
+            }
+        }
+    }
+    fn clamped_offset(&self, position: (usize, usize)) -> usize {
+        let row = position.0.min(self.buffer.len() - 1);
+        let len = self.buffer.get(row).expect("Line out of bounds!").len();
+        let col = if position.0 > row { len } else { position.1.min(len) };
+        self.buffer.offset_of(row, col)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Editor;
+
+    fn caret(editor: &Editor) -> (usize, usize) {
+        (editor.carets[0].row, editor.carets[0].col)
+    }
+
+    #[test]
+    fn find_test() {
+        let mut editor = Editor::new();
+        editor.set_text("foo bar\nbar foo\nbaz");
+        assert!(editor.find("foo"));
+        assert_eq!(caret(&editor), (0, 0));
+        editor.move_right();
+        assert!(editor.find("foo"));
+        assert_eq!(caret(&editor), (1, 4));
+        editor.move_right();
+        assert!(editor.find("foo"));
+        assert_eq!(caret(&editor), (0, 0));
+        assert!(editor.find("foo\nbaz"));
+        assert_eq!(caret(&editor), (1, 4));
+        assert!(!editor.find("qux"));
+        assert!(!editor.find(""));
+    }
+
+    #[test]
+    fn replace_all_test() {
+        let mut editor = Editor::new();
+        editor.set_text("aaa\nbab");
+        editor.place_caret(1, 3);
+        assert_eq!(editor.replace_all("a", "xy"), 4);
+        assert_eq!(editor.get_all(), "xyxyxy\nbxyb");
+        assert_eq!(caret(&editor), (1, 4));
+        assert_eq!(editor.replace_all("y\nb", " "), 1);
+        assert_eq!(editor.get_all(), "xyxyx xyb");
+        assert_eq!(editor.replace_all("q", "z"), 0);
+        editor.undo();
+        assert_eq!(editor.get_all(), "xyxyxy\nbxyb");
+        editor.undo();
+        assert_eq!(editor.get_all(), "aaa\nbab");
+    }
+
+    #[test]
+    fn replace_ranges_test() {
+        let mut editor = Editor::new();
+        editor.set_text("let foo = 1;\nfoo + foo");
+        editor.replace_ranges(&[((1, 6), (1, 9), String::from("bar")),
+                                ((0, 4), (0, 7), String::from("bar")),
+                                ((1, 0), (1, 3), String::from("bar")),
+                                ((5, 0), (9, 9), String::from("!"))]);
+        assert_eq!(editor.get_all(), "let bar = 1;\nbar + bar!");
+        editor.undo();
+        assert_eq!(editor.get_all(), "let foo = 1;\nfoo + foo");
+    }
+}
@@ -0,0 +1,323 @@
+use std::collections::VecDeque;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use super::{Change, ChangeType, Dirty, Editor, HookEvent, LineBuffer, LineEnding, Position,
+            TextBuffer, text_end};
+
+const MAGIC: &str = "MAL_SWAP 1";
+
+/// Swap file of the file: `.name.mal.swp` next to it, apart from the
+/// `.name.swp` of Vim, or one named after the process in the cache directory
+/// for a buffer without a file.
+pub fn swap_path(file: Option<&Path>) -> PathBuf {
+    match file.and_then(|file| file.file_name().map(|name| (file, name))) {
+        Some((file, name)) => file.with_file_name(format!(".{}.mal.swp", name.to_string_lossy())),
+        None => cache_dir().join(format!("untitled-{}.swp", process::id())),
+    }
+}
+
+/// Files to dump the text of the buffer to after a crash, in order of
+/// preference: next to the file, then in the cache directory.
+pub fn recovery_paths(file: Option<&Path>) -> Vec<PathBuf> {
+    let name = match file.and_then(|file| file.file_name()) {
+        Some(name) => name.to_string_lossy().into_owned(),
+        None => String::from("untitled"),
+    };
+    let name = format!("{}.{}.recovered", name, process::id());
+    let mut paths = Vec::new();
+    if let Some(file) = file {
+        paths.push(file.with_file_name(&name));
+    }
+    paths.push(cache_dir().join(&name));
+    paths
+}
+
+/// Side file autosaves go to instead of the file itself: `.name.autosave`
+/// next to it.
+pub fn autosave_path(file: &Path) -> PathBuf {
+    let name = file.file_name().map_or(String::new(), |name| name.to_string_lossy().into_owned());
+    file.with_file_name(format!(".{}.autosave", name))
+}
+
+/// `$XDG_CACHE_HOME/mal`, or `~/.cache/mal`.
+fn cache_dir() -> PathBuf {
+    let dir = match env::var_os("XDG_CACHE_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => {
+            match env::var_os("HOME") {
+                Some(home) => PathBuf::from(home).join(".cache"),
+                None => env::temp_dir(),
+            }
+        }
+    };
+    dir.join("mal")
+}
+
+/// Writes the swap file through a temporary file, so a crash while writing
+/// leaves the previous swap file intact.
+pub fn write_swap(path: &Path, data: &str) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        if !dir.as_os_str().is_empty() {
+            fs::create_dir_all(dir)?;
+        }
+    }
+    let tmp = PathBuf::from(format!("{}~", path.display()));
+    fs::write(&tmp, data)?;
+    fs::rename(&tmp, path)
+}
+
+pub fn remove_swap(path: &Path) {
+    let _ = fs::remove_file(path);
+}
+
+/// Reads swap data back line by line and in length-prefixed blobs.
+struct Reader<'a> {
+    data: &'a str,
+}
+
+impl<'a> Reader<'a> {
+    fn line(&mut self) -> Result<&'a str, String> {
+        match self.data.find('\n') {
+            Some(end) => {
+                let line = &self.data[..end];
+                self.data = &self.data[end + 1..];
+                Ok(line)
+            }
+            None => Err(String::from("Truncated swap file")),
+        }
+    }
+    /// Reads a line of `keyword` followed by numbers.
+    fn numbers(&mut self, keyword: &str) -> Result<Vec<usize>, String> {
+        let line = self.line()?;
+        let mut words = line.split(' ');
+        if words.next() != Some(keyword) {
+            return Err(format!("Expected \"{}\" in swap file", keyword));
+        }
+        words.map(|word| {
+                 word.parse().map_err(|_| format!("Bad number \"{}\" in swap file", word))
+             })
+             .collect()
+    }
+    fn blob(&mut self, len: usize) -> Result<&'a str, String> {
+        if self.data.len() < len + 1 || !self.data.is_char_boundary(len) {
+            return Err(String::from("Truncated swap file"));
+        }
+        let blob = &self.data[..len];
+        self.data = &self.data[len + 1..];
+        Ok(blob)
+    }
+}
+
+/// Whether the changes can be undone, the last first, starting from the
+/// lines, each inside the text as it is by then and restoring carets inside
+/// it too.
+fn undoable(lines: &VecDeque<LineBuffer>, changes: &[Change]) -> bool {
+    let mut buffer = TextBuffer {
+        lines: lines.clone(),
+        dirty: Dirty::new(),
+    };
+    let inside = |buffer: &TextBuffer, (row, col): (usize, usize)| {
+        buffer.get(row).is_some_and(|line| col <= line.len())
+    };
+    for change in changes.iter().rev() {
+        let start = (change.row, change.col);
+        match change.ctype {
+            ChangeType::Insert => {
+                let end = text_end(change.row, change.col, &change.text);
+                if !inside(&buffer, start) || !inside(&buffer, end) {
+                    return false;
+                }
+                buffer.delete(start, end);
+            }
+            ChangeType::Delete => {
+                if !inside(&buffer, start) {
+                    return false;
+                }
+                buffer.insert_text(change.row, change.col, &change.text);
+            }
+        }
+        if change.pos.is_empty() || change.pos.iter().any(|pos| !inside(&buffer, (pos.row, pos.col))) {
+            return false;
+        }
+    }
+    true
+}
+
+impl Editor {
+    /// Serializes the buffer, carets and undo history.
+    pub fn swap_data(&self) -> String {
+        let text = self.get_all();
+        let mut data = format!("{}\nfile {}\nformat {} {}\ncarets {}\n",
+                               MAGIC,
+                               self.filename,
+                               if self.line_ending == LineEnding::CrLf { 1 } else { 0 },
+                               if self.final_newline { 1 } else { 0 },
+                               self.carets.len());
+        for caret in self.carets.iter() {
+            data.push_str(&format!("caret {} {}\n", caret.row, caret.col));
+        }
+        data.push_str(&format!("seq {}\ntext {}\n{}\n", self.change_seq, text.len(), text));
+        let undo = self.undo_pool.borrow();
+        data.push_str(&format!("changes {}\n", undo.len()));
+        for change in undo.iter() {
+            data.push_str(&format!("change {} {} {} {} {}",
+                                   change.seq,
+                                   match change.ctype {
+                                       ChangeType::Insert => 0,
+                                       ChangeType::Delete => 1,
+                                   },
+                                   change.row,
+                                   change.col,
+                                   change.pos.len()));
+            for pos in change.pos.iter() {
+                data.push_str(&format!(" {} {}", pos.row, pos.col));
+            }
+            data.push_str(&format!(" {}\n{}\n", change.text.len(), change.text));
+        }
+        data
+    }
+    /// Restores the buffer, carets and undo history from swap data. The buffer
+    /// is left modified, as it differs from the file until saved.
+    pub fn restore_swap(&mut self, data: &str) -> Result<(), String> {
+        let mut reader = Reader { data };
+        if reader.line()? != MAGIC {
+            return Err(String::from("Not a swap file"));
+        }
+        reader.line()?;
+        let format = reader.numbers("format")?;
+        if format.len() != 2 {
+            return Err(String::from("Bad format line in swap file"));
+        }
+        let count = reader.numbers("carets")?;
+        let mut carets = Vec::new();
+        for _ in 0..count.first().cloned().unwrap_or(0) {
+            let caret = reader.numbers("caret")?;
+            if caret.len() != 2 {
+                return Err(String::from("Bad caret in swap file"));
+            }
+            carets.push(Position::new(caret[0], caret[1]));
+        }
+        let seq = reader.numbers("seq")?;
+        let len = reader.numbers("text")?;
+        let text = reader.blob(len.first().cloned().unwrap_or(0))?;
+        let count = reader.numbers("changes")?;
+        let mut changes = Vec::new();
+        for _ in 0..count.first().cloned().unwrap_or(0) {
+            let numbers = reader.numbers("change")?;
+            if numbers.len() < 6 || numbers.len() != 6 + 2 * numbers[4] {
+                return Err(String::from("Bad change in swap file"));
+            }
+            let pos = numbers[5..numbers.len() - 1]
+                          .chunks(2)
+                          .map(|pair| Position::new(pair[0], pair[1]))
+                          .collect();
+            let text = reader.blob(numbers[numbers.len() - 1])?;
+            changes.push(Change {
+                pos,
+                row: numbers[2],
+                col: numbers[3],
+                text: String::from(text),
+                ctype: if numbers[1] == 0 { ChangeType::Insert } else { ChangeType::Delete },
+                seq: numbers[0],
+            });
+        }
+        let lines: VecDeque<LineBuffer> = text.split('\n')
+                                              .map(|line| line.chars().collect())
+                                              .collect();
+        let outside = |caret: &Position| {
+            caret.row >= lines.len() || caret.col > lines[caret.row].len()
+        };
+        if carets.is_empty() || carets.iter().any(outside) {
+            return Err(String::from("Caret out of bounds in swap file"));
+        }
+        if !undoable(&lines, &changes) {
+            return Err(String::from("Change out of bounds in swap file"));
+        }
+        self.buffer.lines = lines;
+        self.buffer.dirty.mark_below(0);
+        self.line_ending = if format[0] == 1 { LineEnding::CrLf } else { LineEnding::Lf };
+        self.final_newline = format[1] == 1;
+        self.carets = carets;
+        self.mode_normal();
+        self.change_seq = seq.first().cloned().unwrap_or(0);
+        *self.undo_pool.borrow_mut() = changes;
+        self.redo_pool.borrow_mut().clear();
+        self.modified = true;
+        self.version += 1;
+        self.notify(HookEvent::Opened);
+        self.run_hooks();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::Editor;
+
+    #[test]
+    fn swap_path_test() {
+        assert_eq!(swap_path(Some(Path::new("/tmp/dir/file.txt"))),
+                   PathBuf::from("/tmp/dir/.file.txt.mal.swp"));
+        assert_eq!(swap_path(Some(Path::new("file.txt"))), PathBuf::from(".file.txt.mal.swp"));
+        assert!(swap_path(None).to_string_lossy().contains("untitled-"));
+        assert_eq!(autosave_path(Path::new("/tmp/dir/file.txt")),
+                   PathBuf::from("/tmp/dir/.file.txt.autosave"));
+        let paths = recovery_paths(Some(Path::new("/tmp/dir/file.txt")));
+        assert_eq!(paths.len(), 2);
+        assert_eq!(paths[0],
+                   PathBuf::from(format!("/tmp/dir/file.txt.{}.recovered", process::id())));
+    }
+
+    #[test]
+    fn round_trip_test() {
+        let mut editor = Editor::new();
+        editor.set_text("first\r\nsecond\r\n");
+        editor.insert(String::from("ä\n"));
+        editor.move_end();
+        editor.insert_line();
+        editor.insert(String::from("last"));
+        editor.backspace();
+        let data = editor.swap_data();
+
+        let mut restored = Editor::new();
+        restored.restore_swap(&data).unwrap();
+        assert_eq!(restored.get_all(), "ä\nfirst\nsecond\nlas");
+        assert_eq!(restored.carets.first().unwrap().col, 3);
+        assert_eq!(restored.line_ending(), LineEnding::CrLf);
+        assert!(restored.is_modified());
+        assert_eq!(restored.swap_data(), data);
+        restored.undo();
+        assert_eq!(restored.get_all(), "ä\nfirst\nsecond\nlast");
+        restored.undo();
+        restored.undo();
+        assert_eq!(restored.get_all(), "ä\nfirst\nsecond");
+        restored.undo();
+        assert_eq!(restored.get_all(), "first\nsecond");
+        assert!(!restored.undo());
+
+        assert!(restored.restore_swap("garbage\n").is_err());
+        assert!(restored.restore_swap(&data[..data.len() - 5]).is_err());
+        assert_eq!(restored.get_all(), "first\nsecond");
+
+        // A change outside the text would make undo panic, so it is refused.
+        let corrupt: Vec<String> = data.lines()
+                                       .map(|line| {
+                                           if line.starts_with("change ") {
+                                               let mut words: Vec<&str> = line.split(' ').collect();
+                                               words[3] = "9";
+                                               words.join(" ")
+                                           } else {
+                                               String::from(line)
+                                           }
+                                       })
+                                       .collect();
+        assert_eq!(restored.restore_swap(&(corrupt.join("\n") + "\n")),
+                   Err(String::from("Change out of bounds in swap file")));
+        assert_eq!(restored.get_all(), "first\nsecond");
+    }
+}
@@ -0,0 +1,146 @@
+use backend::Goto;
+use frontend::parse_keys;
+use super::EditorView;
+
+/// Runs the script of editor commands on the buffer shown, one command per
+/// line:
+///
+/// * `goto LINE[:COL]`, `goto +N`, `goto -N` or `goto N%`
+/// * `search TEXT` moves to the next occurrence at or after the caret
+/// * `replace /OLD/NEW/` replaces every occurrence, with any delimiter
+/// * `insert TEXT` inserts at the carets
+/// * `delete [N]` deletes N lines from the caret's, one by default
+/// * `keys KEYS` presses the keys, written as for key scripts, like `<C-s>`
+/// * `save [PATH]` saves the buffer, under another name if given
+///
+/// Texts take `\n`, `\t` and `\\` escapes. Blank lines and lines starting
+/// with `#` are skipped. Stops at the first command which fails, or at a key
+/// which quits.
+pub fn run(view: &mut EditorView, script: &str) -> Result<(), String> {
+    for (idx, line) in script.lines().enumerate() {
+        let line = line.trim_start();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (command, arg) = match line.find(' ') {
+            Some(space) => (&line[..space], &line[space + 1..]),
+            None => (line, ""),
+        };
+        let result = execute(view, command, arg);
+        view.run_hooks();
+        match result {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(why) => return Err(format!("line {}: {}", idx + 1, why)),
+        }
+    }
+    Ok(())
+}
+
+/// Runs one command. Returns `false` when it quits the editor.
+fn execute(view: &mut EditorView, command: &str, arg: &str) -> Result<bool, String> {
+    match command {
+        "goto" => {
+            let target = Goto::parse(arg).ok_or_else(|| format!("Bad target \"{}\"", arg))?;
+            view.editor.goto(&target);
+        }
+        "search" => {
+            let text = unescape(arg);
+            if !view.editor.find(&text) {
+                return Err(format!("\"{}\" not found", arg));
+            }
+        }
+        "replace" => {
+            let mut parts = match arg.chars().next() {
+                Some(delimiter) => arg[delimiter.len_utf8()..].split(delimiter),
+                None => return Err(String::from("Expected /OLD/NEW/")),
+            };
+            let (old, new) = match (parts.next(), parts.next()) {
+                (Some(old), Some(new)) if !old.is_empty() => (unescape(old), unescape(new)),
+                _ => return Err(String::from("Expected /OLD/NEW/")),
+            };
+            if view.editor.replace_all(&old, &new) == 0 {
+                return Err(format!("\"{}\" not found", old));
+            }
+        }
+        "insert" => view.editor.insert(unescape(arg)),
+        "delete" => {
+            let count = if arg.is_empty() {
+                1
+            } else {
+                arg.trim().parse().map_err(|_| format!("Bad line count \"{}\"", arg))?
+            };
+            for _ in 0..count {
+                view.editor.delete_line();
+            }
+        }
+        "keys" => {
+            for key in parse_keys(arg)? {
+                if !view.handle_key(key) {
+                    return Ok(false);
+                }
+            }
+        }
+        "save" if arg.is_empty() => view.editor.save()?,
+        "save" => view.editor.save_as(arg)?,
+        _ => return Err(format!("Unknown command \"{}\"", command)),
+    }
+    Ok(true)
+}
+
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('t') => unescaped.push('\t'),
+            Some(other) => unescaped.push(other),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::process;
+    use super::*;
+    use frontend::MemoryFrontend;
+
+    fn view_with(text: &str) -> EditorView {
+        let mut view = EditorView::new(Box::new(MemoryFrontend::new(40, 10)));
+        view.editor.set_text(text);
+        view
+    }
+
+    #[test]
+    fn run_test() {
+        let mut view = view_with("one\ntwo\nthree\nfour");
+        let script = "# comment\n\ngoto 2\ninsert >\\t\nsearch thr\ndelete\n\
+                      replace |o|0|\nkeys <End>!<C-z><Home>^\n";
+        assert_eq!(run(&mut view, script), Ok(()));
+        assert_eq!(view.editor.get_all(), "^0ne\n>\ttw0\nf0ur");
+        let mut view = view_with("abc");
+        assert_eq!(run(&mut view, "insert x\nsearch q\ninsert y"),
+                   Err(String::from("line 2: \"q\" not found")));
+        assert_eq!(view.editor.get_all(), "xabc");
+        assert!(run(&mut view, "goto nowhere").is_err());
+        assert!(run(&mut view, "replace /q/z/").is_err());
+        assert!(run(&mut view, "replace //z/").is_err());
+        assert!(run(&mut view, "frobnicate").is_err());
+        assert!(run(&mut view, "save").is_err());
+        // Quitting ends the script; there is nothing to ask about once saved.
+        let path = env::temp_dir().join(format!("mal-batch-test-{}", process::id()));
+        let script = format!("save {}\nkeys <C-q>\ninsert z", path.display());
+        assert_eq!(run(&mut view, &script), Ok(()));
+        assert_eq!(view.editor.get_all(), "xabc");
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// Settings read from a file of `key = value` lines. Blank lines and lines
+/// starting with `#` are ignored.
+pub struct Config {
+    values: HashMap<String, String>,
+}
+
+impl Config {
+    pub fn new() -> Config {
+        Config { values: HashMap::new() }
+    }
+    pub fn parse(text: &str) -> Result<Config, String> {
+        let mut config = Config::new();
+        for (idx, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.find('=') {
+                Some(eq) => {
+                    let key = line[..eq].trim();
+                    let value = unquote(line[eq + 1..].trim());
+                    config.values.insert(String::from(key), String::from(value));
+                }
+                None => return Err(format!("line {}: expected key = value", idx + 1)),
+            }
+        }
+        Ok(config)
+    }
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Config, String> {
+        let mut text = String::new();
+        match File::open(path.as_ref()).and_then(|mut file| file.read_to_string(&mut text)) {
+            Ok(_) => {}
+            Err(why) => return Err(format!("Couldn't read {}: {}", path.as_ref().display(), why)),
+        }
+        Config::parse(&text).map_err(|why| format!("{}: {}", path.as_ref().display(), why))
+    }
+    /// Loads the config from the default path if the file exists.
+    pub fn load_default() -> Result<Config, String> {
+        match default_path() {
+            Some(ref path) if path.exists() => Config::load(path),
+            _ => Ok(Config::new()),
+        }
+    }
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(|value| &value[..])
+    }
+    pub fn get_or<'a>(&'a self, key: &str, default: &'a str) -> &'a str {
+        self.get(key).unwrap_or(default)
+    }
+    pub fn get_bool(&self, key: &str) -> Option<bool> {
+        match self.get(key) {
+            Some("true") | Some("yes") | Some("on") => Some(true),
+            Some("false") | Some("no") | Some("off") => Some(false),
+            _ => None,
+        }
+    }
+    /// Values of the keys starting with the prefix, by the rest of the key.
+    pub fn prefixed(&self, prefix: &str) -> HashMap<String, String> {
+        self.values
+            .iter()
+            .filter(|&(key, _)| key.starts_with(prefix) && key.len() > prefix.len())
+            .map(|(key, value)| (String::from(&key[prefix.len()..]), value.clone()))
+            .collect()
+    }
+    pub fn set(&mut self, key: &str, value: &str) {
+        self.values.insert(String::from(key), String::from(value));
+    }
+}
+
+/// `$XDG_CONFIG_HOME/mal`, or `~/.config/mal`.
+pub fn config_dir() -> Option<PathBuf> {
+    match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) => Some(PathBuf::from(dir)),
+        None => env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")),
+    }
+    .map(|dir| dir.join("mal"))
+}
+
+/// `config` in the config directory.
+pub fn default_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("config"))
+}
+
+/// Strips one pair of surrounding double quotes, so values can keep leading
+/// or trailing spaces.
+fn unquote(value: &str) -> &str {
+    if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+        &value[1..value.len() - 1]
+    } else {
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_test() {
+        let config = Config::parse("# comment\n\nstatus_format = \" %f \"\nsign_column=yes\n")
+                         .unwrap();
+        assert_eq!(config.get("status_format"), Some(" %f "));
+        assert_eq!(config.get_bool("sign_column"), Some(true));
+        assert_eq!(config.get("missing"), None);
+        assert_eq!(config.get_or("missing", "x"), "x");
+        assert!(Config::parse("no equals sign").is_err());
+    }
+
+    #[test]
+    fn prefixed_test() {
+        let config = Config::parse("lsp.rust = rust-analyzer\nlsp. = x\nlspx = y\n").unwrap();
+        let servers = config.prefixed("lsp.");
+        assert_eq!(servers.len(), 1);
+        assert_eq!(servers["rust"], "rust-analyzer");
+    }
+}
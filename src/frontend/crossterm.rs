@@ -0,0 +1,190 @@
+use std::fs::OpenOptions;
+use std::io::{Write, stdout};
+use std::time::Duration;
+
+use crossterm::QueueableCommand;
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{self, DisableFocusChange, DisableMouseCapture, EnableFocusChange,
+                       EnableMouseCapture, KeyCode, KeyEventKind, KeyModifiers, MouseButton,
+                       MouseEventKind};
+use crossterm::style::{self, Attribute, Print, SetAttribute, SetBackgroundColor,
+                       SetForegroundColor};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::tty::IsTty;
+
+use super::{Color, Event, Frontend, Key, Mouse, Style};
+
+/// Pure Rust frontend on top of crossterm.
+pub struct CrosstermFrontend {
+    /// Standard output, or the terminal itself when the output is piped.
+    out: Box<dyn Write>,
+    /// Columns of the terminal, past which printed text is clipped rather
+    /// than wrapped onto the next row.
+    width: usize,
+}
+
+fn ct_color(color: Color) -> style::Color {
+    match color {
+        Color::Default => style::Color::Reset,
+        Color::Black => style::Color::Black,
+        Color::Red => style::Color::DarkRed,
+        Color::Green => style::Color::DarkGreen,
+        Color::Yellow => style::Color::DarkYellow,
+        Color::Blue => style::Color::DarkBlue,
+        Color::Magenta => style::Color::DarkMagenta,
+        Color::Cyan => style::Color::DarkCyan,
+        Color::White => style::Color::Grey,
+    }
+}
+
+fn key(code: KeyCode, modifiers: KeyModifiers) -> Key {
+    let ctrl = modifiers.contains(KeyModifiers::CONTROL);
+    let alt = modifiers.contains(KeyModifiers::ALT);
+    let shift = modifiers.contains(KeyModifiers::SHIFT);
+    match code {
+        KeyCode::Char(c) if ctrl => Key::Ctrl(c),
+        KeyCode::Char(c) if alt => Key::Alt(c),
+        KeyCode::Char(c) => Key::Char(c),
+        KeyCode::Left if ctrl => Key::CtrlLeft,
+        KeyCode::Right if ctrl => Key::CtrlRight,
+        KeyCode::Up if ctrl => Key::CtrlUp,
+        KeyCode::Down if ctrl => Key::CtrlDown,
+        KeyCode::Delete if ctrl => Key::CtrlDelete,
+        KeyCode::PageUp if ctrl => Key::CtrlPageUp,
+        KeyCode::PageDown if ctrl => Key::CtrlPageDown,
+        KeyCode::Left if shift => Key::ShiftLeft,
+        KeyCode::Right if shift => Key::ShiftRight,
+        KeyCode::Up if shift => Key::ShiftUp,
+        KeyCode::Down if shift => Key::ShiftDown,
+        KeyCode::Up if alt => Key::AltUp,
+        KeyCode::Down if alt => Key::AltDown,
+        KeyCode::Left => Key::Left,
+        KeyCode::Right => Key::Right,
+        KeyCode::Up => Key::Up,
+        KeyCode::Down => Key::Down,
+        KeyCode::Enter => Key::Enter,
+        KeyCode::Tab => Key::Tab,
+        KeyCode::Esc => Key::Esc,
+        KeyCode::Backspace => Key::Backspace,
+        KeyCode::Delete => Key::Delete,
+        KeyCode::Insert => Key::Insert,
+        KeyCode::Home => Key::Home,
+        KeyCode::End => Key::End,
+        KeyCode::PageUp => Key::PageUp,
+        KeyCode::PageDown => Key::PageDown,
+        KeyCode::F(n) => Key::F(n as u32),
+        _ => Key::Unknown,
+    }
+}
+
+impl CrosstermFrontend {
+    pub fn new() -> Result<CrosstermFrontend, String> {
+        let mut out: Box<dyn Write> = if stdout().is_tty() {
+            Box::new(stdout())
+        } else {
+            let tty = OpenOptions::new().write(true).open("/dev/tty");
+            Box::new(tty.map_err(|e| format!("Couldn't open the terminal: {}", e))?)
+        };
+        terminal::enable_raw_mode().map_err(|e| format!("{}", e))?;
+        out.queue(EnterAlternateScreen)
+           .and_then(|out| out.queue(EnableMouseCapture))
+           .and_then(|out| out.queue(EnableFocusChange))
+           .and_then(|out| out.queue(Hide))
+           .and_then(|out| out.flush())
+           .map_err(|e| format!("{}", e))?;
+        let width = terminal::size().map(|(width, _)| width as usize).unwrap_or(80);
+        Ok(CrosstermFrontend {
+            out,
+            width,
+        })
+    }
+}
+
+impl Drop for CrosstermFrontend {
+    fn drop(&mut self) {
+        let _ = self.out
+                    .queue(DisableMouseCapture)
+                    .and_then(|out| out.queue(DisableFocusChange))
+                    .and_then(|out| out.queue(Show))
+                    .and_then(|out| out.queue(LeaveAlternateScreen))
+                    .and_then(|out| out.flush());
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+impl Frontend for CrosstermFrontend {
+    fn size(&self) -> (usize, usize) {
+        match terminal::size() {
+            Ok((width, height)) => (width as usize, height as usize),
+            Err(_) => (80, 24),
+        }
+    }
+    fn clear(&mut self) {
+        let _ = self.out.queue(Clear(ClearType::All));
+    }
+    fn print(&mut self, x: usize, y: usize, style: Style, fg: Color, bg: Color, text: &str) {
+        if x >= self.width {
+            return;
+        }
+        let text: String = text.chars().take(self.width - x).collect();
+        let attribute = match style {
+            Style::Normal => Attribute::Reset,
+            Style::Bold => Attribute::Bold,
+            Style::Underline => Attribute::Underlined,
+            Style::Reverse => Attribute::Reverse,
+        };
+        let _ = self.out
+                    .queue(MoveTo(x as u16, y as u16))
+                    .and_then(|out| out.queue(SetAttribute(Attribute::Reset)))
+                    .and_then(|out| out.queue(SetAttribute(attribute)))
+                    .and_then(|out| out.queue(SetForegroundColor(ct_color(fg))))
+                    .and_then(|out| out.queue(SetBackgroundColor(ct_color(bg))))
+                    .and_then(|out| out.queue(Print(&text)))
+                    .and_then(|out| out.queue(SetAttribute(Attribute::Reset)));
+    }
+    fn set_cursor(&mut self, position: Option<(usize, usize)>) {
+        let _ = match position {
+            Some((x, y)) => self.out.queue(MoveTo(x as u16, y as u16)).and_then(|out| out.queue(Show)),
+            None => self.out.queue(Hide),
+        };
+    }
+    fn present(&mut self) {
+        let _ = self.out.flush();
+    }
+    fn poll_event(&mut self, timeout: Option<Duration>) -> Option<Event> {
+        if let Some(timeout) = timeout {
+            match event::poll(timeout) {
+                Ok(true) => {}
+                _ => return None,
+            }
+        }
+        match event::read() {
+            Ok(event::Event::Key(key_event)) => {
+                if key_event.kind == KeyEventKind::Release {
+                    return None;
+                }
+                Some(Event::Key(key(key_event.code, key_event.modifiers)))
+            }
+            Ok(event::Event::Mouse(mouse_event)) => {
+                let mouse = match mouse_event.kind {
+                    MouseEventKind::Down(MouseButton::Left) => Mouse::Left,
+                    MouseEventKind::Down(MouseButton::Right) => Mouse::Right,
+                    MouseEventKind::Down(MouseButton::Middle) => Mouse::Middle,
+                    MouseEventKind::Drag(MouseButton::Left) => Mouse::Drag,
+                    MouseEventKind::Up(_) => Mouse::Release,
+                    MouseEventKind::ScrollUp => Mouse::WheelUp,
+                    MouseEventKind::ScrollDown => Mouse::WheelDown,
+                    _ => return None,
+                };
+                Some(Event::Mouse(mouse, mouse_event.column as usize, mouse_event.row as usize))
+            }
+            Ok(event::Event::Resize(width, height)) => {
+                self.width = width as usize;
+                Some(Event::Resize(width as usize, height as usize))
+            }
+            Ok(event::Event::FocusGained) => Some(Event::FocusGained),
+            Ok(event::Event::FocusLost) => Some(Event::FocusLost),
+            _ => None,
+        }
+    }
+}
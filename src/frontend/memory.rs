@@ -0,0 +1,108 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::time::Duration;
+
+use super::{Color, Event, Frontend, Style};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cell {
+    pub ch: char,
+    pub style: Style,
+    pub fg: Color,
+    pub bg: Color,
+}
+
+const BLANK: Cell = Cell {
+    ch: ' ',
+    style: Style::Normal,
+    fg: Color::Default,
+    bg: Color::Default,
+};
+
+struct Screen {
+    width: usize,
+    height: usize,
+    cells: Vec<Cell>,
+    cursor: Option<(usize, usize)>,
+    events: VecDeque<Event>,
+}
+
+/// A frontend drawing into memory and reading queued events, for tests.
+/// Clones share the same screen, so a test can keep one to inspect what the
+/// view drew.
+#[derive(Clone)]
+pub struct MemoryFrontend {
+    screen: Rc<RefCell<Screen>>,
+}
+
+impl MemoryFrontend {
+    pub fn new(width: usize, height: usize) -> MemoryFrontend {
+        MemoryFrontend {
+            screen: Rc::new(RefCell::new(Screen {
+                width,
+                height,
+                cells: vec![BLANK; width * height],
+                cursor: None,
+                events: VecDeque::new(),
+            })),
+        }
+    }
+    pub fn cell(&self, x: usize, y: usize) -> Cell {
+        let screen = self.screen.borrow();
+        screen.cells[y * screen.width + x]
+    }
+    /// Text of a screen row without trailing blanks.
+    pub fn line(&self, y: usize) -> String {
+        let screen = self.screen.borrow();
+        let line: String = screen.cells[y * screen.width..(y + 1) * screen.width]
+                               .iter()
+                               .map(|cell| cell.ch)
+                               .collect();
+        String::from(line.trim_end())
+    }
+    pub fn cursor(&self) -> Option<(usize, usize)> {
+        self.screen.borrow().cursor
+    }
+    /// Queues an event for `poll_event`.
+    pub fn push_event(&self, event: Event) {
+        self.screen.borrow_mut().events.push_back(event);
+    }
+}
+
+impl Frontend for MemoryFrontend {
+    fn size(&self) -> (usize, usize) {
+        let screen = self.screen.borrow();
+        (screen.width, screen.height)
+    }
+    fn clear(&mut self) {
+        for cell in self.screen.borrow_mut().cells.iter_mut() {
+            *cell = BLANK;
+        }
+    }
+    fn print(&mut self, x: usize, y: usize, style: Style, fg: Color, bg: Color, text: &str) {
+        let mut screen = self.screen.borrow_mut();
+        if y >= screen.height {
+            return;
+        }
+        let width = screen.width;
+        for (idx, c) in text.chars().enumerate() {
+            if x + idx >= width {
+                break;
+            }
+            screen.cells[y * width + x + idx] = Cell {
+                ch: c,
+                style,
+                fg,
+                bg,
+            };
+        }
+    }
+    fn set_cursor(&mut self, position: Option<(usize, usize)>) {
+        self.screen.borrow_mut().cursor = position;
+    }
+    fn present(&mut self) {}
+    fn poll_event(&mut self, timeout: Option<Duration>) -> Option<Event> {
+        Some(self.screen.borrow_mut().events.pop_front().unwrap_or(Event::Closed))
+    }
+}
@@ -0,0 +1,271 @@
+use std::time::Duration;
+
+mod crossterm;
+mod memory;
+mod rustbox;
+
+pub use self::crossterm::CrosstermFrontend;
+pub use self::memory::MemoryFrontend;
+pub use self::rustbox::RustBoxFrontend;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Color {
+    Default,
+    Black,
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+    White,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Style {
+    Normal,
+    Bold,
+    Underline,
+    Reverse,
+}
+
+/// A key press, with the modified keys the editor binds spelled out.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Key {
+    Char(char),
+    Ctrl(char),
+    Alt(char),
+    Enter,
+    Tab,
+    Esc,
+    Backspace,
+    Delete,
+    Insert,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    CtrlLeft,
+    CtrlRight,
+    CtrlUp,
+    CtrlDown,
+    CtrlDelete,
+    CtrlPageUp,
+    CtrlPageDown,
+    ShiftLeft,
+    ShiftRight,
+    ShiftUp,
+    ShiftDown,
+    AltUp,
+    AltDown,
+    F(u32),
+    Unknown,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mouse {
+    Left,
+    Right,
+    Middle,
+    /// Motion with the left button held. Not every frontend reports it.
+    Drag,
+    Release,
+    WheelUp,
+    WheelDown,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Event {
+    Key(Key),
+    Mouse(Mouse, usize, usize),
+    Resize(usize, usize),
+    /// The terminal window got or lost focus. Not every frontend reports it.
+    FocusGained,
+    FocusLost,
+    /// No more input will come, as from the in-memory frontend once its
+    /// queue is empty.
+    Closed,
+}
+
+/// A terminal the editor view draws to and reads input from.
+pub trait Frontend {
+    /// Width and height in cells.
+    fn size(&self) -> (usize, usize);
+    fn clear(&mut self);
+    fn print(&mut self, x: usize, y: usize, style: Style, fg: Color, bg: Color, text: &str);
+    fn print_char(&mut self, x: usize, y: usize, style: Style, fg: Color, bg: Color, c: char) {
+        let mut text = String::new();
+        text.push(c);
+        self.print(x, y, style, fg, bg, &text);
+    }
+    /// Shows the cursor at the cell, or hides it with `None`.
+    fn set_cursor(&mut self, position: Option<(usize, usize)>);
+    /// Flushes everything drawn since the last call to the screen.
+    fn present(&mut self);
+    /// Waits for the next event. With a timeout, returns `None` when it expires.
+    fn poll_event(&mut self, timeout: Option<Duration>) -> Option<Event>;
+}
+
+/// Opens the frontend with the given name, `rustbox` or `crossterm`.
+pub fn open(name: &str) -> Result<Box<dyn Frontend>, String> {
+    match name {
+        "rustbox" => RustBoxFrontend::new().map(|frontend| Box::new(frontend) as Box<dyn Frontend>),
+        "crossterm" => {
+            CrosstermFrontend::new().map(|frontend| Box::new(frontend) as Box<dyn Frontend>)
+        }
+        _ => Err(format!("Unknown frontend \"{}\"", name)),
+    }
+}
+
+/// Parses a key script such as `"abc<Left><Enter><BS>"` into key presses.
+/// Plain characters stand for themselves and names in angle brackets for the
+/// other keys, with `C-`, `S-` and `A-` prefixes for Ctrl, Shift and Alt
+/// (`<C-w>`, `<S-Left>`, `<A-Up>`). `<lt>` is a literal `<`.
+pub fn parse_keys(script: &str) -> Result<Vec<Key>, String> {
+    let mut keys = Vec::new();
+    let mut chars = script.chars();
+    while let Some(c) = chars.next() {
+        if c != '<' {
+            keys.push(Key::Char(c));
+            continue;
+        }
+        let mut name = String::new();
+        loop {
+            match chars.next() {
+                Some('>') => break,
+                Some(c) => name.push(c),
+                None => return Err(format!("Unterminated key \"<{}\"", name)),
+            }
+        }
+        match key_named(&name) {
+            Some(key) => keys.push(key),
+            None => return Err(format!("Unknown key \"<{}>\"", name)),
+        }
+    }
+    Ok(keys)
+}
+
+fn key_named(name: &str) -> Option<Key> {
+    // The modifier of a character is named in either case, but the
+    // character keeps its own: `<A-X>` is Alt with a capital X.
+    let chars: Vec<char> = name.chars().collect();
+    if let &[modifier, '-', c] = &chars[..] {
+        match modifier.to_ascii_lowercase() {
+            'c' => return Some(Key::Ctrl(c)),
+            'a' => return Some(Key::Alt(c)),
+            _ => {}
+        }
+    }
+    let name = name.to_lowercase();
+    let key = match &name[..] {
+        "lt" => Key::Char('<'),
+        "space" => Key::Char(' '),
+        "cr" | "enter" | "return" => Key::Enter,
+        "tab" => Key::Tab,
+        "esc" => Key::Esc,
+        "bs" | "backspace" => Key::Backspace,
+        "del" | "delete" => Key::Delete,
+        "ins" | "insert" => Key::Insert,
+        "left" => Key::Left,
+        "right" => Key::Right,
+        "up" => Key::Up,
+        "down" => Key::Down,
+        "home" => Key::Home,
+        "end" => Key::End,
+        "pageup" | "pgup" => Key::PageUp,
+        "pagedown" | "pgdn" => Key::PageDown,
+        "c-left" => Key::CtrlLeft,
+        "c-right" => Key::CtrlRight,
+        "c-up" => Key::CtrlUp,
+        "c-down" => Key::CtrlDown,
+        "c-del" | "c-delete" => Key::CtrlDelete,
+        "c-pageup" | "c-pgup" => Key::CtrlPageUp,
+        "c-pagedown" | "c-pgdn" => Key::CtrlPageDown,
+        "s-left" => Key::ShiftLeft,
+        "s-right" => Key::ShiftRight,
+        "s-up" => Key::ShiftUp,
+        "s-down" => Key::ShiftDown,
+        "a-up" => Key::AltUp,
+        "a-down" => Key::AltDown,
+        _ => {
+            return name.strip_prefix('f').and_then(|n| n.parse().ok()).map(Key::F);
+        }
+    };
+    Some(key)
+}
+
+/// The key as written in key scripts, like `a`, `<C-s>` or `<PageUp>`.
+pub fn key_name(key: Key) -> String {
+    let name = match key {
+        Key::Char('<') => "lt",
+        Key::Char(' ') => "Space",
+        Key::Char(c) => return c.to_string(),
+        Key::Ctrl(c) => return format!("<C-{}>", c),
+        Key::Alt(c) => return format!("<A-{}>", c),
+        Key::F(n) => return format!("<F{}>", n),
+        Key::Enter => "CR",
+        Key::Tab => "Tab",
+        Key::Esc => "Esc",
+        Key::Backspace => "BS",
+        Key::Delete => "Del",
+        Key::Insert => "Insert",
+        Key::Left => "Left",
+        Key::Right => "Right",
+        Key::Up => "Up",
+        Key::Down => "Down",
+        Key::Home => "Home",
+        Key::End => "End",
+        Key::PageUp => "PageUp",
+        Key::PageDown => "PageDown",
+        Key::CtrlLeft => "C-Left",
+        Key::CtrlRight => "C-Right",
+        Key::CtrlUp => "C-Up",
+        Key::CtrlDown => "C-Down",
+        Key::CtrlDelete => "C-Del",
+        Key::CtrlPageUp => "C-PageUp",
+        Key::CtrlPageDown => "C-PageDown",
+        Key::ShiftLeft => "S-Left",
+        Key::ShiftRight => "S-Right",
+        Key::ShiftUp => "S-Up",
+        Key::ShiftDown => "S-Down",
+        Key::AltUp => "A-Up",
+        Key::AltDown => "A-Down",
+        Key::Unknown => "Unknown",
+    };
+    format!("<{}>", name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_keys_test() {
+        assert_eq!(parse_keys("ab<Left><CR><bs>"),
+                   Ok(vec![Key::Char('a'), Key::Char('b'), Key::Left, Key::Enter,
+                           Key::Backspace]));
+        assert_eq!(parse_keys("<C-w><A-j><S-Up><C-Left><A-Down><F5>"),
+                   Ok(vec![Key::Ctrl('w'), Key::Alt('j'), Key::ShiftUp, Key::CtrlLeft,
+                           Key::AltDown, Key::F(5)]));
+        assert_eq!(parse_keys("<C-PageUp><c-pgdn>"),
+                   Ok(vec![Key::CtrlPageUp, Key::CtrlPageDown]));
+        assert_eq!(parse_keys("<lt>x>"),
+                   Ok(vec![Key::Char('<'), Key::Char('x'), Key::Char('>')]));
+        assert_eq!(parse_keys(""), Ok(vec![]));
+        assert!(parse_keys("<Nope>").is_err());
+        assert!(parse_keys("<Left").is_err());
+    }
+
+    #[test]
+    fn key_name_test() {
+        let keys = parse_keys("a<lt><Space><C-s><A-x><A-X><F5><CR><S-Up><C-PageDown><A-Down>").unwrap();
+        let names: Vec<String> = keys.iter().map(|&key| key_name(key)).collect();
+        assert_eq!(names.concat(), "a<lt><Space><C-s><A-x><A-X><F5><CR><S-Up><C-PageDown><A-Down>");
+        assert_eq!(parse_keys(&names.concat()), Ok(keys));
+        assert_eq!(parse_keys("<a-X>"), Ok(vec![Key::Alt('X')]));
+    }
+}
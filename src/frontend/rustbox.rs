@@ -0,0 +1,155 @@
+use std::time::Duration;
+
+use rustbox;
+use rustbox::{InitOptions, InputMode, RustBox};
+
+use super::{Color, Event, Frontend, Key, Mouse, Style};
+
+/// Frontend on top of termbox.
+pub struct RustBoxFrontend {
+    terminal: RustBox,
+}
+
+fn rb_color(color: Color) -> rustbox::Color {
+    match color {
+        Color::Default => rustbox::Color::Default,
+        Color::Black => rustbox::Color::Black,
+        Color::Red => rustbox::Color::Red,
+        Color::Green => rustbox::Color::Green,
+        Color::Yellow => rustbox::Color::Yellow,
+        Color::Blue => rustbox::Color::Blue,
+        Color::Magenta => rustbox::Color::Magenta,
+        Color::Cyan => rustbox::Color::Cyan,
+        Color::White => rustbox::Color::White,
+    }
+}
+
+fn rb_style(style: Style) -> rustbox::Style {
+    match style {
+        Style::Normal => rustbox::RB_NORMAL,
+        Style::Bold => rustbox::RB_BOLD,
+        Style::Underline => rustbox::RB_UNDERLINE,
+        Style::Reverse => rustbox::RB_REVERSE,
+    }
+}
+
+fn key(key: rustbox::Key) -> Key {
+    match key {
+        rustbox::Key::Tab => Key::Tab,
+        rustbox::Key::Enter => Key::Enter,
+        rustbox::Key::Esc => Key::Esc,
+        rustbox::Key::Backspace => Key::Backspace,
+        rustbox::Key::Right => Key::Right,
+        rustbox::Key::Left => Key::Left,
+        rustbox::Key::Up => Key::Up,
+        rustbox::Key::Down => Key::Down,
+        rustbox::Key::Delete => Key::Delete,
+        rustbox::Key::Insert => Key::Insert,
+        rustbox::Key::Home => Key::Home,
+        rustbox::Key::End => Key::End,
+        rustbox::Key::PageUp => Key::PageUp,
+        rustbox::Key::PageDown => Key::PageDown,
+        rustbox::Key::Char(c) => Key::Char(c),
+        rustbox::Key::Ctrl(c) => Key::Ctrl(c),
+        rustbox::Key::F(n) => Key::F(n),
+        rustbox::Key::Unknown(_) => Key::Unknown,
+    }
+}
+
+impl RustBoxFrontend {
+    pub fn new() -> Result<RustBoxFrontend, String> {
+        let terminal = match RustBox::init(InitOptions {
+            input_mode: InputMode::EscMouse,
+            ..Default::default()
+        }) {
+            Ok(terminal) => terminal,
+            Err(e) => return Err(format!("{}", e)),
+        };
+        Ok(RustBoxFrontend { terminal })
+    }
+    /// Termbox does not know the xterm/rxvt sequences for modified arrows and
+    /// reports them, like Alt+key, as `Esc` followed by plain characters.
+    /// Collects those characters after an `Esc` and returns the key they
+    /// encode.
+    fn read_escape(&self) -> Key {
+        let mut seq = String::new();
+        while seq.len() < 5 {
+            match self.terminal.peek_event(Duration::from_millis(10), false) {
+                Ok(rustbox::Event::KeyEvent(rustbox::Key::Char(c))) => seq.push(c),
+                _ => break,
+            }
+            match seq.as_str() {
+                "[1;5A" | "Oa" => return Key::CtrlUp,
+                "[1;5B" | "Ob" => return Key::CtrlDown,
+                "[1;5C" | "Oc" => return Key::CtrlRight,
+                "[1;5D" | "Od" => return Key::CtrlLeft,
+                "[3;5~" | "[3^" => return Key::CtrlDelete,
+                "[5;5~" | "[5^" => return Key::CtrlPageUp,
+                "[6;5~" | "[6^" => return Key::CtrlPageDown,
+                "[1;2A" | "[a" => return Key::ShiftUp,
+                "[1;2B" | "[b" => return Key::ShiftDown,
+                "[1;2C" | "[c" => return Key::ShiftRight,
+                "[1;2D" | "[d" => return Key::ShiftLeft,
+                "[1;3A" => return Key::AltUp,
+                "[1;3B" => return Key::AltDown,
+                "[" | "O" => {}
+                _ if seq.len() == 1 => return Key::Alt(seq.chars().next().unwrap()),
+                _ => {}
+            }
+        }
+        match seq.as_str() {
+            "" => Key::Esc,
+            "[" | "O" => Key::Alt(seq.chars().next().unwrap()),
+            _ => Key::Unknown,
+        }
+    }
+}
+
+impl Frontend for RustBoxFrontend {
+    fn size(&self) -> (usize, usize) {
+        (self.terminal.width(), self.terminal.height())
+    }
+    fn clear(&mut self) {
+        self.terminal.clear();
+    }
+    fn print(&mut self, x: usize, y: usize, style: Style, fg: Color, bg: Color, text: &str) {
+        self.terminal.print(x, y, rb_style(style), rb_color(fg), rb_color(bg), text);
+    }
+    fn print_char(&mut self, x: usize, y: usize, style: Style, fg: Color, bg: Color, c: char) {
+        self.terminal.print_char(x, y, rb_style(style), rb_color(fg), rb_color(bg), c);
+    }
+    fn set_cursor(&mut self, position: Option<(usize, usize)>) {
+        match position {
+            Some((x, y)) => self.terminal.set_cursor(x as isize, y as isize),
+            None => self.terminal.set_cursor(-1, -1),
+        }
+    }
+    fn present(&mut self) {
+        self.terminal.present();
+    }
+    fn poll_event(&mut self, timeout: Option<Duration>) -> Option<Event> {
+        let event = match timeout {
+            Some(timeout) => self.terminal.peek_event(timeout, false),
+            None => self.terminal.poll_event(false),
+        };
+        match event {
+            Ok(rustbox::Event::KeyEvent(rustbox::Key::Esc)) => Some(Event::Key(self.read_escape())),
+            Ok(rustbox::Event::KeyEvent(k)) => Some(Event::Key(key(k))),
+            Ok(rustbox::Event::ResizeEvent(width, height)) => {
+                Some(Event::Resize(width as usize, height as usize))
+            }
+            Ok(rustbox::Event::MouseEvent(mouse, x, y)) if x >= 0 && y >= 0 => {
+                let mouse = match mouse {
+                    rustbox::Mouse::Left => Mouse::Left,
+                    rustbox::Mouse::Right => Mouse::Right,
+                    rustbox::Mouse::Middle => Mouse::Middle,
+                    rustbox::Mouse::Release => Mouse::Release,
+                    rustbox::Mouse::WheelUp => Mouse::WheelUp,
+                    rustbox::Mouse::WheelDown => Mouse::WheelDown,
+                };
+                Some(Event::Mouse(mouse, x as usize, y as usize))
+            }
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,1063 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::Value;
+
+use backend::{Editor, HookEvent, Hooks};
+use frontend::Color;
+use session;
+
+/// How long a server gets to shut down on exit before it is killed.
+const SHUTDOWN_MS: u64 = 500;
+
+/// The message framed as the protocol sends it, behind a `Content-Length`
+/// header.
+fn encode(message: &Value) -> Vec<u8> {
+    let body = message.to_string();
+    let mut bytes = format!("Content-Length: {}\r\n\r\n", body.len()).into_bytes();
+    bytes.extend_from_slice(body.as_bytes());
+    bytes
+}
+
+/// Reads the next message, or `None` at the end of the stream.
+fn read_message<R: BufRead>(reader: &mut R) -> Result<Option<Value>, String> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        match reader.read_line(&mut line) {
+            Ok(0) => return Ok(None),
+            Ok(_) => {}
+            Err(why) => return Err(why.to_string()),
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let mut parts = line.splitn(2, ':');
+        if let (Some(name), Some(value)) = (parts.next(), parts.next()) {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let length = length.ok_or_else(|| String::from("Missing Content-Length"))?;
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).map_err(|why| why.to_string())?;
+    serde_json::from_slice(&body).map(Some).map_err(|why| why.to_string())
+}
+
+pub fn path_to_uri(path: &Path) -> String {
+    let mut uri = String::from("file://");
+    for byte in path.to_string_lossy().bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => {
+                uri.push(byte as char)
+            }
+            _ => uri.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    uri
+}
+
+pub fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    if !uri.starts_with("file://") {
+        return None;
+    }
+    let bytes = &uri.as_bytes()[7..];
+    let mut decoded = Vec::new();
+    let mut idx = 0;
+    while idx < bytes.len() {
+        let escaped = if bytes[idx] == b'%' && idx + 2 < bytes.len() {
+            String::from_utf8(bytes[idx + 1..idx + 3].to_vec())
+                .ok()
+                .and_then(|hex| u8::from_str_radix(&hex, 16).ok())
+        } else {
+            None
+        };
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                idx += 3;
+            }
+            None => {
+                decoded.push(bytes[idx]);
+                idx += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).ok().map(PathBuf::from)
+}
+
+/// The column in characters of a column the server counts in UTF-16 code
+/// units, or in characters already.
+pub fn char_col(line: &str, col: usize, utf16: bool) -> usize {
+    if !utf16 {
+        return col;
+    }
+    let mut units = 0;
+    for (idx, c) in line.chars().enumerate() {
+        if units >= col {
+            return idx;
+        }
+        units += c.len_utf16();
+    }
+    line.chars().count()
+}
+
+/// The column in characters as the server counts it.
+pub fn lsp_col(line: &str, col: usize, utf16: bool) -> usize {
+    if utf16 {
+        line.chars().take(col).map(|c| c.len_utf16()).sum()
+    } else {
+        col
+    }
+}
+
+/// Severities of diagnostics, the most severe first.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum Severity {
+    Error,
+    Warning,
+    Information,
+    Hint,
+}
+
+impl Severity {
+    fn from_lsp(severity: Option<u64>) -> Severity {
+        match severity {
+            Some(2) => Severity::Warning,
+            Some(3) => Severity::Information,
+            Some(4) => Severity::Hint,
+            _ => Severity::Error,
+        }
+    }
+    /// Marker shown in the sign column.
+    pub fn sign(self) -> char {
+        match self {
+            Severity::Error => 'E',
+            Severity::Warning => 'W',
+            Severity::Information => 'I',
+            Severity::Hint => 'H',
+        }
+    }
+    pub fn color(self) -> Color {
+        match self {
+            Severity::Error => Color::Red,
+            Severity::Warning => Color::Yellow,
+            Severity::Information => Color::Blue,
+            Severity::Hint => Color::Cyan,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub row: usize,
+    pub severity: Severity,
+    /// First line of the message.
+    pub message: String,
+}
+
+/// Start and end of a text, as rows and columns in characters.
+pub type Range = ((usize, usize), (usize, usize));
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Location {
+    pub path: PathBuf,
+    pub range: Range,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextEdit {
+    pub range: Range,
+    pub text: String,
+}
+
+/// What the servers sent back, for the view to show.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Reply {
+    Hover(String),
+    Definition(Vec<Location>),
+    References(Vec<Location>),
+    /// Edits of a rename, by file.
+    Rename(Vec<(PathBuf, Vec<TextEdit>)>),
+    /// The diagnostics of the file changed.
+    Diagnostics(PathBuf),
+    Message(String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Request {
+    Initialize,
+    Hover,
+    Definition,
+    References,
+    Rename,
+    Shutdown,
+}
+
+/// A language server, spoken to over channels which threads connect to the
+/// standard input and output of its process, so nothing waits on it.
+struct Client {
+    /// Command of the server, for messages.
+    name: String,
+    child: Option<Child>,
+    outgoing: Sender<Value>,
+    incoming: Receiver<Value>,
+    next_id: u64,
+    /// Requests sent and not answered yet, by id.
+    pending: HashMap<u64, Request>,
+    /// Set once the server answered `initialize`; messages before that are
+    /// held back.
+    ready: bool,
+    held: Vec<Value>,
+    /// Whether columns count UTF-16 code units rather than characters.
+    utf16: bool,
+    /// Whether the server takes changes as ranges rather than whole texts.
+    incremental: bool,
+}
+
+impl Client {
+    /// Starts the server and asks it to initialize.
+    fn spawn(command: &str) -> Result<Client, String> {
+        let mut words = command.split_whitespace();
+        let program = words.next().ok_or_else(|| String::from("Empty server command"))?;
+        let mut child = Command::new(program)
+                            .args(words)
+                            .stdin(Stdio::piped())
+                            .stdout(Stdio::piped())
+                            .stderr(Stdio::null())
+                            .spawn()
+                            .map_err(|why| format!("Couldn't start {}: {}", program, why))?;
+        let mut stdin = child.stdin.take().expect("No stdin!");
+        let stdout = child.stdout.take().expect("No stdout!");
+        let (outgoing, to_server) = mpsc::channel::<Value>();
+        let (from_server, incoming) = mpsc::channel();
+        thread::spawn(move || {
+            for message in to_server {
+                if stdin.write_all(&encode(&message)).and_then(|()| stdin.flush()).is_err() {
+                    break;
+                }
+            }
+        });
+        thread::spawn(move || {
+            let mut reader = BufReader::new(stdout);
+            while let Ok(Some(message)) = read_message(&mut reader) {
+                if from_server.send(message).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Client::new(command, Some(child), outgoing, incoming))
+    }
+    fn new(name: &str,
+           child: Option<Child>,
+           outgoing: Sender<Value>,
+           incoming: Receiver<Value>)
+           -> Client {
+        let mut client = Client {
+            name: String::from(name),
+            child,
+            outgoing,
+            incoming,
+            next_id: 0,
+            pending: HashMap::new(),
+            ready: false,
+            held: Vec::new(),
+            utf16: true,
+            incremental: false,
+        };
+        let root = env::current_dir().ok().map(|dir| path_to_uri(&dir));
+        let params = json!({
+            "processId": std::process::id(),
+            "rootUri": root,
+            "capabilities": {
+                "general": {"positionEncodings": ["utf-32", "utf-16"]},
+                "textDocument": {
+                    "synchronization": {"didSave": true},
+                    "hover": {"contentFormat": ["plaintext", "markdown"]},
+                    "publishDiagnostics": {},
+                    "definition": {},
+                    "references": {},
+                    "rename": {},
+                },
+            },
+        });
+        client.request(Request::Initialize, "initialize", params);
+        client
+    }
+    fn send(&mut self, message: Value) {
+        let _ = self.outgoing.send(message);
+    }
+    fn request(&mut self, request: Request, method: &str, params: Value) {
+        self.next_id += 1;
+        self.pending.insert(self.next_id, request);
+        let message = json!({"jsonrpc": "2.0", "id": self.next_id, "method": method, "params": params});
+        if self.ready || request == Request::Initialize {
+            self.send(message);
+        } else {
+            self.held.push(message);
+        }
+    }
+    fn notify(&mut self, method: &str, params: Value) {
+        let message = json!({"jsonrpc": "2.0", "method": method, "params": params});
+        if self.ready {
+            self.send(message);
+        } else {
+            self.held.push(message);
+        }
+    }
+    /// Takes in the server's answer to `initialize` and sends what was held
+    /// back.
+    fn initialized(&mut self, result: &Value) {
+        let capabilities = &result["capabilities"];
+        self.utf16 = capabilities["positionEncoding"].as_str() != Some("utf-32");
+        let sync = &capabilities["textDocumentSync"];
+        let kind = sync.as_u64().or(sync["change"].as_u64());
+        self.incremental = kind == Some(2) && !self.utf16;
+        self.ready = true;
+        self.notify("initialized", json!({}));
+        for message in mem::take(&mut self.held) {
+            self.send(message);
+        }
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        let mut child = match self.child.take() {
+            Some(child) => child,
+            None => return,
+        };
+        if self.ready {
+            self.request(Request::Shutdown, "shutdown", Value::Null);
+            self.notify("exit", Value::Null);
+        }
+        let started = Instant::now();
+        while started.elapsed() < Duration::from_millis(SHUTDOWN_MS) {
+            match child.try_wait() {
+                Ok(None) => thread::sleep(Duration::from_millis(10)),
+                _ => return,
+            }
+        }
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+}
+
+/// A file open in a buffer, and what the server has yet to hear about it.
+struct Document {
+    filetype: String,
+    version: u64,
+    /// Whether the server was sent the file yet.
+    opened: bool,
+    /// Whole text to send, and the buffer version it was taken at.
+    text: Option<String>,
+    snapshot: usize,
+    /// Changes to send after it, as ranges.
+    changes: Vec<Value>,
+    saved: bool,
+}
+
+struct Inner {
+    /// Server commands by file type.
+    commands: HashMap<String, String>,
+    clients: HashMap<String, Client>,
+    /// File types whose server couldn't be started or quit.
+    failed: HashSet<String>,
+    documents: HashMap<PathBuf, Document>,
+    diagnostics: HashMap<PathBuf, Vec<Diagnostic>>,
+    replies: Vec<Reply>,
+}
+
+/// Language servers for the file types the config names a command for, as
+/// `lsp.TYPE = COMMAND`. A server is started for the first file of its type
+/// and kept in sync with the buffers through their hooks. Servers are only
+/// polled, so the editor never waits on them.
+pub struct Lsp {
+    inner: Rc<RefCell<Inner>>,
+}
+
+impl Lsp {
+    pub fn new(commands: HashMap<String, String>) -> Lsp {
+        Lsp {
+            inner: Rc::new(RefCell::new(Inner {
+                commands,
+                clients: HashMap::new(),
+                failed: HashSet::new(),
+                documents: HashMap::new(),
+                diagnostics: HashMap::new(),
+                replies: Vec::new(),
+            })),
+        }
+    }
+    /// Adds a hook which follows the buffers' files, changes and saves.
+    pub fn attach(&self, hooks: &Hooks) {
+        let inner = self.inner.clone();
+        hooks.add(move |editor, event| inner.borrow_mut().handle_event(editor, event));
+    }
+    /// Whether any server is running, so the view should poll often.
+    pub fn is_running(&self) -> bool {
+        !self.inner.borrow().clients.is_empty()
+    }
+    /// Starts the servers files wait for, sends the changes and takes in what
+    /// the servers sent. Columns are converted with `line`, which gives the
+    /// text of a row of an open file.
+    pub fn update(&mut self, line: &dyn Fn(&Path, usize) -> Option<String>) -> Vec<Reply> {
+        let mut inner = self.inner.borrow_mut();
+        inner.start_servers();
+        inner.flush();
+        inner.receive(line);
+        mem::take(&mut inner.replies)
+    }
+    /// Diagnostics of the buffer's file, by row.
+    pub fn diagnostics(&self, editor: &Editor) -> Vec<Diagnostic> {
+        let inner = self.inner.borrow();
+        if inner.diagnostics.is_empty() {
+            return Vec::new();
+        }
+        match editor.path().map(session::absolute) {
+            Some(path) => inner.diagnostics.get(&path).cloned().unwrap_or_default(),
+            None => Vec::new(),
+        }
+    }
+    pub fn hover(&mut self, editor: &Editor) -> Result<(), String> {
+        self.inner.borrow_mut().request_at(editor, Request::Hover, "textDocument/hover", json!({}))
+    }
+    pub fn definition(&mut self, editor: &Editor) -> Result<(), String> {
+        self.inner
+            .borrow_mut()
+            .request_at(editor, Request::Definition, "textDocument/definition", json!({}))
+    }
+    pub fn references(&mut self, editor: &Editor) -> Result<(), String> {
+        let context = json!({"context": {"includeDeclaration": true}});
+        self.inner
+            .borrow_mut()
+            .request_at(editor, Request::References, "textDocument/references", context)
+    }
+    pub fn rename(&mut self, editor: &Editor, name: &str) -> Result<(), String> {
+        self.inner
+            .borrow_mut()
+            .request_at(editor, Request::Rename, "textDocument/rename", json!({"newName": name}))
+    }
+}
+
+impl Inner {
+    fn handle_event(&mut self, editor: &mut Editor, event: &HookEvent) {
+        let path = match editor.path() {
+            Some(path) if !self.commands.is_empty() => session::absolute(path),
+            _ => return,
+        };
+        match *event {
+            HookEvent::Opened => {
+                let filetype = editor.filetype();
+                if !self.commands.contains_key(filetype) {
+                    return;
+                }
+                let document = Document {
+                    filetype: String::from(filetype),
+                    version: 0,
+                    opened: false,
+                    text: Some(editor.get_all()),
+                    snapshot: editor.version(),
+                    changes: Vec::new(),
+                    saved: false,
+                };
+                // Read again: the server gets the new text as a change.
+                match self.documents.get_mut(&path) {
+                    Some(old) => {
+                        old.text = document.text;
+                        old.snapshot = document.snapshot;
+                        old.changes.clear();
+                    }
+                    None => {
+                        self.documents.insert(path, document);
+                    }
+                }
+            }
+            HookEvent::Changed { start, end, ref text, inserted } => {
+                let document = match self.documents.get_mut(&path) {
+                    Some(document) => document,
+                    None => return,
+                };
+                let incremental = self.clients
+                                      .get(&document.filetype)
+                                      .is_some_and(|client| client.ready && client.incremental);
+                if document.opened && document.text.is_none() && incremental {
+                    let (end, text) = if inserted { (start, &text[..]) } else { (end, "") };
+                    document.changes.push(json!({
+                        "range": {
+                            "start": {"line": start.0, "character": start.1},
+                            "end": {"line": end.0, "character": end.1},
+                        },
+                        "text": text,
+                    }));
+                } else if document.snapshot != editor.version() {
+                    document.text = Some(editor.get_all());
+                    document.snapshot = editor.version();
+                    document.changes.clear();
+                }
+            }
+            HookEvent::AfterSave => {
+                if let Some(document) = self.documents.get_mut(&path) {
+                    document.saved = true;
+                }
+            }
+            _ => {}
+        }
+    }
+    fn start_servers(&mut self) {
+        let filetypes: HashSet<String> = self.documents
+                                             .values()
+                                             .map(|document| document.filetype.clone())
+                                             .collect();
+        for filetype in filetypes {
+            if self.clients.contains_key(&filetype) || self.failed.contains(&filetype) {
+                continue;
+            }
+            let command = self.commands[&filetype].clone();
+            match Client::spawn(&command) {
+                Ok(client) => {
+                    self.clients.insert(filetype, client);
+                }
+                Err(why) => {
+                    self.failed.insert(filetype);
+                    self.replies.push(Reply::Message(why));
+                }
+            }
+        }
+    }
+    /// Sends the files servers haven't seen and the changes since.
+    fn flush(&mut self) {
+        for (path, document) in self.documents.iter_mut() {
+            let client = match self.clients.get_mut(&document.filetype) {
+                Some(client) if client.ready => client,
+                _ => continue,
+            };
+            let uri = path_to_uri(path);
+            if !document.opened {
+                document.opened = true;
+                document.changes.clear();
+                let text = document.text.take().unwrap_or_default();
+                client.notify("textDocument/didOpen",
+                              json!({
+                                  "textDocument": {
+                                      "uri": uri,
+                                      "languageId": document.filetype,
+                                      "version": document.version,
+                                      "text": text,
+                                  },
+                              }));
+            } else if document.text.is_some() || !document.changes.is_empty() {
+                let mut changes = Vec::new();
+                if let Some(text) = document.text.take() {
+                    changes.push(json!({"text": text}));
+                }
+                changes.append(&mut document.changes);
+                document.version += 1;
+                client.notify("textDocument/didChange",
+                              json!({
+                                  "textDocument": {"uri": uri, "version": document.version},
+                                  "contentChanges": changes,
+                              }));
+            }
+            if document.saved {
+                document.saved = false;
+                client.notify("textDocument/didSave", json!({"textDocument": {"uri": uri}}));
+            }
+        }
+    }
+    /// Asks the server of the buffer's file about the main caret's position.
+    fn request_at(&mut self,
+                  editor: &Editor,
+                  request: Request,
+                  method: &str,
+                  mut params: Value)
+                  -> Result<(), String> {
+        let path = editor.path().map(session::absolute);
+        let filetype = match path.as_ref().and_then(|path| self.documents.get(path)) {
+            Some(document) => document.filetype.clone(),
+            None => return Err(String::from("No language server for this file")),
+        };
+        match self.clients.get(&filetype) {
+            Some(client) if client.ready => {}
+            Some(_) => return Err(String::from("Language server is starting")),
+            None => return Err(String::from("No language server for this file")),
+        }
+        self.flush();
+        let client = self.clients.get_mut(&filetype).expect("Client not found!");
+        let caret = editor.carets.first().expect("Caret not found!");
+        let line: String = editor.get(caret.row)
+                                 .map(|line| line.iter().collect())
+                                 .unwrap_or_default();
+        params["textDocument"] = json!({"uri": path_to_uri(path.as_ref().unwrap())});
+        params["position"] = json!({
+            "line": caret.row,
+            "character": lsp_col(&line, caret.col, client.utf16),
+        });
+        client.request(request, method, params);
+        Ok(())
+    }
+    fn receive(&mut self, line: &dyn Fn(&Path, usize) -> Option<String>) {
+        let mut gone = Vec::new();
+        let mut messages = Vec::new();
+        for (filetype, client) in self.clients.iter_mut() {
+            loop {
+                match client.incoming.try_recv() {
+                    Ok(message) => messages.push((filetype.clone(), message)),
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        gone.push(filetype.clone());
+                        break;
+                    }
+                }
+            }
+        }
+        for (filetype, message) in messages {
+            self.handle_message(&filetype, message, line);
+        }
+        for filetype in gone {
+            if let Some(client) = self.clients.remove(&filetype) {
+                self.replies.push(Reply::Message(format!("Language server {} quit", client.name)));
+            }
+            self.failed.insert(filetype);
+        }
+    }
+    fn handle_message(&mut self,
+                      filetype: &str,
+                      message: Value,
+                      line: &dyn Fn(&Path, usize) -> Option<String>) {
+        let client = match self.clients.get_mut(filetype) {
+            Some(client) => client,
+            None => return,
+        };
+        let utf16 = client.utf16;
+        let method = message["method"].as_str().map(String::from);
+        let id = message.get("id").cloned();
+        match (method, id) {
+            // A request of the server's: say nothing is known.
+            (Some(method), Some(id)) => {
+                let result = if method == "workspace/configuration" {
+                    let count = message["params"]["items"].as_array().map_or(0, |items| items.len());
+                    Value::Array(vec![Value::Null; count])
+                } else {
+                    Value::Null
+                };
+                client.send(json!({"jsonrpc": "2.0", "id": id, "result": result}));
+            }
+            (Some(method), None) => {
+                match &method[..] {
+                    "textDocument/publishDiagnostics" => {
+                        let params = &message["params"];
+                        let path = match params["uri"].as_str().and_then(uri_to_path) {
+                            Some(path) => path,
+                            None => return,
+                        };
+                        let diagnostics = params["diagnostics"]
+                                              .as_array()
+                                              .map_or(Vec::new(), |list| {
+                                                  list.iter().map(parse_diagnostic).collect()
+                                              });
+                        self.diagnostics.insert(path.clone(), diagnostics);
+                        self.replies.push(Reply::Diagnostics(path));
+                    }
+                    "window/showMessage" if message["params"]["type"].as_u64() <= Some(2) => {
+                        let text = message["params"]["message"].as_str().unwrap_or("");
+                        self.replies.push(Reply::Message(String::from(text)));
+                    }
+                    _ => {}
+                }
+            }
+            (None, Some(id)) => {
+                let request = match id.as_u64().and_then(|id| client.pending.remove(&id)) {
+                    Some(request) => request,
+                    None => return,
+                };
+                if let Some(error) = message.get("error") {
+                    let text = error["message"].as_str().unwrap_or("request failed");
+                    self.replies.push(Reply::Message(format!("{}: {}", client.name, text)));
+                    return;
+                }
+                let result = &message["result"];
+                let files = RefCell::new(HashMap::new());
+                let line = |path: &Path, row: usize| {
+                    line(path, row).or_else(|| file_line(&files, path, row))
+                };
+                let line = &line;
+                let reply = match request {
+                    Request::Initialize => {
+                        client.initialized(result);
+                        return;
+                    }
+                    Request::Shutdown => return,
+                    Request::Hover => {
+                        match hover_text(&result["contents"]) {
+                            Some(text) => Reply::Hover(text),
+                            None => Reply::Message(String::from("Nothing to show here")),
+                        }
+                    }
+                    Request::Definition => Reply::Definition(parse_locations(result, utf16, line)),
+                    Request::References => Reply::References(parse_locations(result, utf16, line)),
+                    Request::Rename => Reply::Rename(parse_workspace_edit(result, utf16, line)),
+                };
+                self.replies.push(reply);
+            }
+            (None, None) => {}
+        }
+    }
+}
+
+/// A line of a file no buffer shows, from the file on disk, which is read
+/// once for all the ranges in it.
+fn file_line(files: &RefCell<HashMap<PathBuf, Vec<String>>>,
+             path: &Path,
+             row: usize)
+             -> Option<String> {
+    let mut files = files.borrow_mut();
+    let lines = files.entry(path.to_path_buf()).or_insert_with(|| {
+        fs::read_to_string(path).map(|text| text.lines().map(String::from).collect()).unwrap_or_default()
+    });
+    lines.get(row).cloned()
+}
+
+fn parse_diagnostic(diagnostic: &Value) -> Diagnostic {
+    Diagnostic {
+        row: diagnostic["range"]["start"]["line"].as_u64().unwrap_or(0) as usize,
+        severity: Severity::from_lsp(diagnostic["severity"].as_u64()),
+        message: String::from(diagnostic["message"].as_str().unwrap_or("").lines().next().unwrap_or("")),
+    }
+}
+
+fn parse_range(range: &Value,
+               path: &Path,
+               utf16: bool,
+               line: &dyn Fn(&Path, usize) -> Option<String>)
+               -> Range {
+    let position = |position: &Value| {
+        let row = position["line"].as_u64().unwrap_or(0) as usize;
+        let col = position["character"].as_u64().unwrap_or(0) as usize;
+        let col = match line(path, row) {
+            Some(ref text) if utf16 => char_col(text, col, true),
+            _ => col,
+        };
+        (row, col)
+    };
+    (position(&range["start"]), position(&range["end"]))
+}
+
+/// Locations of a reply, which may be a location, a list of them or of
+/// location links.
+fn parse_locations(result: &Value,
+                   utf16: bool,
+                   line: &dyn Fn(&Path, usize) -> Option<String>)
+                   -> Vec<Location> {
+    let list = match *result {
+        Value::Array(ref list) => list.clone(),
+        Value::Object(_) => vec![result.clone()],
+        _ => Vec::new(),
+    };
+    list.iter()
+        .filter_map(|location| {
+            let uri = location["uri"].as_str().or(location["targetUri"].as_str())?;
+            let path = uri_to_path(uri)?;
+            let range = if location.get("targetSelectionRange").is_some() {
+                &location["targetSelectionRange"]
+            } else {
+                &location["range"]
+            };
+            let range = parse_range(range, &path, utf16, line);
+            Some(Location {
+                path,
+                range,
+            })
+        })
+        .collect()
+}
+
+/// Edits of a workspace edit by file, from either its `changes` or its
+/// `documentChanges`.
+fn parse_workspace_edit(result: &Value,
+                        utf16: bool,
+                        line: &dyn Fn(&Path, usize) -> Option<String>)
+                        -> Vec<(PathBuf, Vec<TextEdit>)> {
+    let mut files: Vec<(String, &Value)> = Vec::new();
+    if let Some(changes) = result["changes"].as_object() {
+        files.extend(changes.iter().map(|(uri, edits)| (uri.clone(), edits)));
+    }
+    if let Some(changes) = result["documentChanges"].as_array() {
+        for change in changes.iter() {
+            if let Some(uri) = change["textDocument"]["uri"].as_str() {
+                files.push((String::from(uri), &change["edits"]));
+            }
+        }
+    }
+    files.into_iter()
+         .filter_map(|(uri, edits)| {
+             let path = uri_to_path(&uri)?;
+             let edits = edits.as_array()?
+                              .iter()
+                              .map(|edit| {
+                                  TextEdit {
+                                      range: parse_range(&edit["range"], &path, utf16, line),
+                                      text: String::from(edit["newText"].as_str().unwrap_or("")),
+                                  }
+                              })
+                              .collect();
+             Some((path, edits))
+         })
+         .collect()
+}
+
+/// Hover contents as one line: plain text, markdown without its code fences,
+/// or a list of either.
+fn hover_text(contents: &Value) -> Option<String> {
+    let text = match *contents {
+        Value::String(ref text) => text.clone(),
+        Value::Array(ref parts) => {
+            parts.iter().filter_map(hover_text).collect::<Vec<String>>().join("\n")
+        }
+        Value::Object(_) => String::from(contents["value"].as_str().unwrap_or("")),
+        _ => String::new(),
+    };
+    let lines: Vec<&str> = text.lines()
+                               .map(|line| line.trim())
+                               .filter(|line| !line.is_empty() && !line.starts_with("```"))
+                               .collect();
+    if lines.is_empty() {
+        None
+    } else {
+        Some(lines.join("  "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::Cursor;
+    use std::process;
+    use super::*;
+
+    #[test]
+    fn framing_test() {
+        let message = json!({"jsonrpc": "2.0", "id": 1, "result": "é"});
+        let mut bytes = encode(&message);
+        bytes.extend_from_slice(b"Content-Type: x\r\nContent-Length: 2\r\n\r\n{}");
+        let mut reader = Cursor::new(bytes);
+        assert_eq!(read_message(&mut reader), Ok(Some(message)));
+        assert_eq!(read_message(&mut reader), Ok(Some(json!({}))));
+        assert_eq!(read_message(&mut reader), Ok(None));
+        assert!(read_message(&mut Cursor::new(b"\r\n{}".to_vec())).is_err());
+    }
+
+    #[test]
+    fn position_test() {
+        let path = Path::new("/tmp/a b/ü.rs");
+        assert_eq!(path_to_uri(path), "file:///tmp/a%20b/%C3%BC.rs");
+        assert_eq!(uri_to_path(&path_to_uri(path)), Some(path.to_path_buf()));
+        assert_eq!(uri_to_path("http://x"), None);
+        let line = "a😀b";
+        assert_eq!(lsp_col(line, 2, true), 3);
+        assert_eq!(char_col(line, 3, true), 2);
+        assert_eq!(lsp_col(line, 2, false), 2);
+        assert_eq!(char_col(line, 9, true), 3);
+    }
+
+    /// An Lsp with a server for Rust whose messages the test exchanges.
+    fn stub() -> (Lsp, Receiver<Value>, Sender<Value>) {
+        let mut commands = HashMap::new();
+        commands.insert(String::from("rust"), String::from("stub"));
+        let lsp = Lsp::new(commands);
+        let (outgoing, to_server) = mpsc::channel();
+        let (from_server, incoming) = mpsc::channel();
+        let client = Client::new("stub", None, outgoing, incoming);
+        lsp.inner.borrow_mut().clients.insert(String::from("rust"), client);
+        (lsp, to_server, from_server)
+    }
+
+    fn methods(to_server: &Receiver<Value>) -> Vec<String> {
+        to_server.try_iter()
+                 .map(|message| String::from(message["method"].as_str().unwrap_or("")))
+                 .collect()
+    }
+
+    #[test]
+    fn client_test() {
+        let path = env::temp_dir().join(format!("mal-lsp-test-{}.rs", process::id()));
+        fs::write(&path, "fn main() {}\n").unwrap();
+        let (mut lsp, to_server, from_server) = stub();
+        let hooks = Hooks::new();
+        lsp.attach(&hooks);
+        let mut editor = Editor::new();
+        editor.set_hooks(hooks);
+        editor.read_file(&path).unwrap();
+        let lines = |_: &Path, _: usize| None;
+
+        // Nothing but initialize goes out before the server answers it.
+        assert!(lsp.update(&lines).is_empty());
+        assert_eq!(methods(&to_server), vec!["initialize"]);
+        assert!(lsp.hover(&editor).is_err());
+        editor.insert(String::from("x"));
+        editor.run_hooks();
+        from_server.send(json!({"id": 1, "result": {"capabilities": {
+                             "positionEncoding": "utf-32", "textDocumentSync": 2}}}))
+                   .unwrap();
+        lsp.update(&lines);
+        lsp.update(&lines);
+        let sent: Vec<Value> = to_server.try_iter().collect();
+        assert_eq!(sent[0]["method"], "initialized");
+        assert_eq!(sent[1]["method"], "textDocument/didOpen");
+        assert_eq!(sent[1]["params"]["textDocument"]["text"], "xfn main() {}");
+
+        // Changes go as ranges once the server has the file.
+        editor.move_line_end();
+        editor.backspace();
+        editor.run_hooks();
+        editor.save().unwrap();
+        lsp.update(&lines);
+        let sent: Vec<Value> = to_server.try_iter().collect();
+        assert_eq!(sent[0]["method"], "textDocument/didChange");
+        assert_eq!(sent[0]["params"]["contentChanges"],
+                   json!([{"range": {"start": {"line": 0, "character": 12},
+                                     "end": {"line": 0, "character": 13}},
+                           "text": ""}]));
+        assert_eq!(sent[1]["method"], "textDocument/didSave");
+
+        assert_eq!(lsp.hover(&editor), Ok(()));
+        let sent: Vec<Value> = to_server.try_iter().collect();
+        assert_eq!(sent[0]["params"]["position"], json!({"line": 0, "character": 12}));
+        let id = sent[0]["id"].clone();
+        from_server.send(json!({"id": id, "result": {"contents": {
+                             "kind": "markdown", "value": "```rust\nfn main()\n```"}}}))
+                   .unwrap();
+        let uri = path_to_uri(&session::absolute(&path));
+        from_server.send(json!({"method": "textDocument/publishDiagnostics", "params": {
+                             "uri": uri, "diagnostics": [{
+                                 "range": {"start": {"line": 0, "character": 0},
+                                           "end": {"line": 0, "character": 1}},
+                                 "severity": 2, "message": "unused\nmore"}]}}))
+                   .unwrap();
+        from_server.send(json!({"id": 7, "method": "workspace/configuration",
+                                "params": {"items": [{}, {}]}}))
+                   .unwrap();
+        let replies = lsp.update(&lines);
+        assert_eq!(replies[0], Reply::Hover(String::from("fn main()")));
+        assert_eq!(replies[1], Reply::Diagnostics(session::absolute(&path)));
+        assert_eq!(lsp.diagnostics(&editor),
+                   vec![Diagnostic {
+                            row: 0,
+                            severity: Severity::Warning,
+                            message: String::from("unused"),
+                        }]);
+        assert_eq!(to_server.try_recv().unwrap(),
+                   json!({"jsonrpc": "2.0", "id": 7, "result": [null, null]}));
+
+        lsp.rename(&editor, "start").unwrap();
+        let id = to_server.try_recv().unwrap()["id"].clone();
+        from_server.send(json!({"id": id, "result": {"changes": {uri.clone(): [{
+                             "range": {"start": {"line": 0, "character": 3},
+                                       "end": {"line": 0, "character": 7}},
+                             "newText": "start"}]}}}))
+                   .unwrap();
+        assert_eq!(lsp.update(&lines),
+                   vec![Reply::Rename(vec![(session::absolute(&path),
+                                            vec![TextEdit {
+                                                     range: ((0, 3), (0, 7)),
+                                                     text: String::from("start"),
+                                                 }])])]);
+        drop(from_server);
+        assert_eq!(lsp.update(&lines), vec![Reply::Message(String::from("Language server stub quit"))]);
+        assert!(!lsp.is_running());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn resync_test() {
+        let path = env::temp_dir().join(format!("mal-lsp-resync-{}.rs", process::id()));
+        fs::write(&path, "fn main() {}\n").unwrap();
+        let (mut lsp, to_server, from_server) = stub();
+        let hooks = Hooks::new();
+        lsp.attach(&hooks);
+        let mut editor = Editor::new();
+        editor.set_hooks(hooks);
+        editor.read_file(&path).unwrap();
+        let lines = |_: &Path, _: usize| None;
+        lsp.update(&lines);
+        from_server.send(json!({"id": 1, "result": {"capabilities": {
+                             "positionEncoding": "utf-32", "textDocumentSync": 2}}}))
+                   .unwrap();
+        lsp.update(&lines);
+        lsp.update(&lines);
+        to_server.try_iter().count();
+
+        // A reload replaces the whole text, so the server gets all of it.
+        fs::write(&path, "fn start() {}\n").unwrap();
+        let change = editor.changed_on_disk().unwrap();
+        editor.reload(&change);
+        lsp.update(&lines);
+        let sent: Vec<Value> = to_server.try_iter().collect();
+        assert_eq!(sent[0]["method"], "textDocument/didChange");
+        assert_eq!(sent[0]["params"]["contentChanges"], json!([{"text": "fn start() {}"}]));
+
+        // So does restoring a swap file.
+        editor.insert(String::from("x"));
+        editor.run_hooks();
+        let data = editor.swap_data();
+        lsp.update(&lines);
+        to_server.try_iter().count();
+        editor.restore_swap(&data).unwrap();
+        lsp.update(&lines);
+        let sent: Vec<Value> = to_server.try_iter().collect();
+        assert_eq!(sent[0]["params"]["contentChanges"], json!([{"text": "xfn start() {}"}]));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn unopened_file_test() {
+        let path = env::temp_dir().join(format!("mal-lsp-unopened-{}.rs", process::id()));
+        fs::write(&path, "fn main() {}\n😀ab\n").unwrap();
+        let (mut lsp, _to_server, from_server) = stub();
+        lsp.inner.borrow_mut().clients.get_mut("rust").unwrap().pending.insert(5, Request::References);
+        from_server.send(json!({"id": 5, "result": [{"uri": path_to_uri(&path), "range": {
+                             "start": {"line": 1, "character": 2},
+                             "end": {"line": 1, "character": 3}}}]}))
+                   .unwrap();
+
+        // No buffer has the file, so its UTF-16 columns are counted on disk.
+        assert_eq!(lsp.update(&|_: &Path, _: usize| None),
+                   vec![Reply::References(vec![Location {
+                                                   path: path.clone(),
+                                                   range: ((1, 1), (1, 2)),
+                                               }])]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn parse_test() {
+        let path = PathBuf::from("/a.rs");
+        let line = |_: &Path, _: usize| Some(String::from("😀xy"));
+        let link = json!([{"targetUri": "file:///a.rs",
+                           "targetRange": {"start": {"line": 0, "character": 0},
+                                           "end": {"line": 9, "character": 0}},
+                           "targetSelectionRange": {"start": {"line": 1, "character": 2},
+                                                    "end": {"line": 1, "character": 3}}}]);
+        assert_eq!(parse_locations(&link, true, &line),
+                   vec![Location {
+                            path: path.clone(),
+                            range: ((1, 1), (1, 2)),
+                        }]);
+        assert_eq!(parse_locations(&Value::Null, true, &line), vec![]);
+        assert_eq!(hover_text(&json!(["plain", {"language": "rust", "value": "i32"}])),
+                   Some(String::from("plain  i32")));
+        assert_eq!(hover_text(&json!("")), None);
+    }
+}
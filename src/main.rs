@@ -1,31 +1,212 @@
-#![feature(core)]
 #![allow(dead_code)]
 #![allow(unreachable_code)]
 #![allow(unused_variables)]
 #![allow(non_snake_case)]
 
 extern crate clap;
+extern crate crossterm;
+extern crate rhai;
 extern crate rustbox;
+#[macro_use]
+extern crate serde_json;
 
-use std::default::Default;
-use std::io::{Write, stdout, stdin};
+use std::cmp;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::env;
+use std::fs;
+use std::io::{stdout, stdin};
 use clap::{Arg, App};
-use rustbox::{Color, RustBox, Key, OutputMode};
-use std::path::Path;
-use std::io::{Stdin, Stdout};
+use std::path::{Path, PathBuf};
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::process::{self, Command};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
 mod backend;
+mod batch;
+mod config;
+mod frontend;
+mod lsp;
+mod script;
+mod session;
+mod status;
 use backend::*;
+use config::Config;
+use lsp::{Diagnostic, Location, Lsp, Reply};
+use script::Scripts;
+use session::{BufferState, Session};
+use status::Status;
+use frontend::{Color, Event, Frontend, Key, MemoryFrontend, Mouse, Style, key_name};
 
 struct EditorView {
     pub editor: Editor,
-    pub terminal: RustBox,
+    pub terminal: Box<dyn Frontend>,
+    /// Number of the buffer shown, counting from 0 in the order opened, and
+    /// the other buffers, in the order they come after it.
+    number: usize,
+    hidden: VecDeque<Hidden>,
     x: usize,
     y: usize,
     row: usize,
     col: usize,
     lnum_pad: usize,
+    line_numbers: LineNumbers,
+    sign_column: bool,
+    /// Markers shown in the sign column, by buffer row.
+    signs: BTreeMap<usize, (char, Color)>,
+    status_format: String,
+    /// Whether the buffer shown on exit goes to standard output.
+    pipe: bool,
+    /// Swap file last written and the buffer version and time it was written.
+    swap_file: Option<PathBuf>,
+    swapped_version: usize,
+    swapped_at: Instant,
+    autosave: Autosave,
+    autosave_idle: Duration,
+    /// Buffer version last autosaved and the time of the last input event.
+    autosaved_version: usize,
+    last_input: Instant,
+    /// Note shown in the infobar in place of the status until the next key.
+    message: Option<String>,
+    /// Hooks set on every buffer, the scripts' among them.
+    hooks: Hooks,
+    scripts: Scripts,
+    lsp: Lsp,
+    /// Diagnostics of the buffer shown, the most severe by row.
+    diagnostics: BTreeMap<usize, Diagnostic>,
+    /// Definitions or references last looked up, and the one gone to.
+    locations: Vec<Location>,
+    location: usize,
+    /// Words offered for the one before the caret, and the buffer rows the
+    /// popup was last drawn over.
+    completion: Option<Completion>,
+    completion_rows: (usize, usize),
+    /// Length of a typed prefix which opens the popup by itself; 0 leaves it
+    /// to `Ctrl-n`.
+    complete_after: usize,
+    last_click: Option<(Instant, usize, usize)>,
+    click_count: usize,
+    /// Scroll offset, gutter width, caret row and caret/selection rows of the
+    /// last frame.
+    drawn_y: usize,
+    drawn_pad: usize,
+    drawn_row: usize,
+    drawn_span: (usize, usize),
 }
 
+/// An open buffer which is not on screen, with what the view needs to show it
+/// again.
+struct Hidden {
+    editor: Editor,
+    number: usize,
+    x: usize,
+    y: usize,
+    swap_file: Option<PathBuf>,
+    swapped_version: usize,
+    autosaved_version: usize,
+}
+
+impl Hidden {
+    fn new(editor: Editor, number: usize) -> Hidden {
+        Hidden {
+            editor,
+            number,
+            x: 0,
+            y: 0,
+            swap_file: None,
+            swapped_version: 0,
+            autosaved_version: 0,
+        }
+    }
+}
+
+/// Words which complete the prefix before the caret, and the one picked.
+struct Completion {
+    prefix: String,
+    words: Vec<String>,
+    selected: usize,
+    /// First word shown, when there are more than fit.
+    top: usize,
+}
+
+/// A file read for editing, and where to put the caret in it.
+struct Opened {
+    editor: Editor,
+    target: Option<Goto>,
+    state: Option<BufferState>,
+}
+
+/// How the gutter numbers lines.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum LineNumbers {
+    Off,
+    Absolute,
+    /// Distance from the caret row.
+    Relative,
+    /// Relative, with the absolute number on the caret row.
+    Hybrid,
+}
+
+impl LineNumbers {
+    fn parse(name: &str) -> Option<LineNumbers> {
+        match name {
+            "off" => Some(LineNumbers::Off),
+            "absolute" => Some(LineNumbers::Absolute),
+            "relative" => Some(LineNumbers::Relative),
+            "hybrid" => Some(LineNumbers::Hybrid),
+            _ => None,
+        }
+    }
+    /// The mode to switch to when toggling.
+    fn next(self) -> LineNumbers {
+        match self {
+            LineNumbers::Absolute => LineNumbers::Relative,
+            LineNumbers::Relative => LineNumbers::Hybrid,
+            LineNumbers::Hybrid => LineNumbers::Off,
+            LineNumbers::Off => LineNumbers::Absolute,
+        }
+    }
+}
+
+/// Where modified buffers are written when the editor is left idle or loses
+/// focus.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Autosave {
+    Off,
+    /// Over the file itself.
+    File,
+    /// To a side file next to it, leaving the file alone.
+    Side,
+}
+
+impl Autosave {
+    fn parse(name: &str) -> Option<Autosave> {
+        match name {
+            "off" => Some(Autosave::Off),
+            "file" | "on" => Some(Autosave::File),
+            "side" => Some(Autosave::Side),
+            _ => None,
+        }
+    }
+}
+
+const DOUBLE_CLICK_MS: u64 = 400;
+const WHEEL_LINES: usize = 3;
+/// Least time between swap file writes, and the idle time after which a
+/// pending write happens.
+const SWAP_INTERVAL_MS: u64 = 4000;
+/// Idle time before an autosave when the config sets no `autosave_idle`.
+const AUTOSAVE_IDLE_MS: u64 = 2000;
+const SWAP_KEPT: &str = "Swap file kept; this buffer is not swapped until it is deleted";
+/// How often the language servers are polled while any is running.
+const LSP_POLL_MS: u64 = 50;
+/// Most words the completion popup shows at once.
+const COMPLETION_ROWS: usize = 8;
+
+/// Message of the last panic, printed once the terminal is restored.
+static PANIC_MESSAGE: Mutex<Option<String>> = Mutex::new(None);
+
 fn right_aligned_text(text: &str, width: usize) -> String {
     let len = text.chars().count();
     if width < len {
@@ -39,135 +220,1312 @@ fn right_aligned_text(text: &str, width: usize) -> String {
     aligned
 }
 
+/// Where the buffer is left, when it has a file.
+fn state_of(editor: &Editor, x: usize, y: usize) -> Option<BufferState> {
+    editor.path().map(|path| {
+        BufferState {
+            path: session::absolute(path),
+            carets: editor.carets.iter().map(|caret| (caret.row, caret.col)).collect(),
+            x,
+            y,
+        }
+    })
+}
+
+fn left_aligned_text(text: &str, width: usize) -> String {
+    let mut aligned: String = text.chars().take(width).collect();
+    for idx in aligned.chars().count()..width {
+        aligned.push(' ');
+    }
+    aligned
+}
+
 impl EditorView {
-    fn new() -> EditorView {
-        let mut terminal = match RustBox::init(Default::default()) {
-            Result::Ok(v) => v,
-            Result::Err(e) => panic!("{}", e),
-        };
-        // terminal.set_output_mode(OutputMode::EightBit);
-        let col = terminal.width();
-        let row = terminal.height();
+    fn new(terminal: Box<dyn Frontend>) -> EditorView {
+        let (col, row) = terminal.size();
+        let hooks = Hooks::new();
+        let scripts = Scripts::new();
+        scripts.attach(&hooks);
+        let lsp = Lsp::new(HashMap::new());
+        lsp.attach(&hooks);
+        let mut editor = Editor::new();
+        editor.set_hooks(hooks.clone());
         EditorView {
-            editor: Editor::new(),
-            terminal: terminal,
+            editor,
+            terminal,
+            number: 0,
+            hidden: VecDeque::new(),
             x: 0,
             y: 0,
-            row: row - 1,
-            col: col,
+            row: if row > 1 { row - 1 } else { 1 },
+            col,
             lnum_pad: 1,
+            line_numbers: LineNumbers::Absolute,
+            sign_column: false,
+            signs: BTreeMap::new(),
+            status_format: String::from(status::DEFAULT_FORMAT),
+            pipe: false,
+            swap_file: None,
+            swapped_version: 0,
+            swapped_at: Instant::now(),
+            autosave: Autosave::Off,
+            autosave_idle: Duration::from_millis(AUTOSAVE_IDLE_MS),
+            autosaved_version: 0,
+            last_input: Instant::now(),
+            message: None,
+            hooks,
+            scripts,
+            lsp,
+            diagnostics: BTreeMap::new(),
+            locations: Vec::new(),
+            location: 0,
+            completion: None,
+            completion_rows: (0, 0),
+            complete_after: 0,
+            last_click: None,
+            click_count: 0,
+            drawn_y: 0,
+            drawn_pad: 0,
+            drawn_row: 0,
+            drawn_span: (0, 0),
         }
     }
     fn clear(&mut self) {
         self.terminal.clear();
     }
     fn format_info(&self) -> String {
-        let main_caret = self.editor.carets.get(0).expect("Caret not found!");
-        right_aligned_text(&format!("{}:{}", main_caret.row + 1, main_caret.col + 1),
-                           self.col)
+        let main_caret = self.editor.carets.first().expect("Caret not found!");
+        let home = env::var("HOME").ok();
+        let filename = status::tilde(self.editor.filename(), home.as_ref().map(|home| &home[..]));
+        let status = Status {
+            filename: &filename,
+            modified: self.editor.is_modified(),
+            readonly: self.editor.is_readonly(),
+            buffer: self.number,
+            buffers: self.buffer_count(),
+            mode: if self.editor.is_selecting() { "SELECT" } else { "NORMAL" },
+            line_ending: self.editor.line_ending().name(),
+            encoding: self.editor.encoding().name(),
+            filetype: self.editor.filetype(),
+            selected: self.editor.selection_len(0),
+            carets: self.editor.carets.len(),
+            row: main_caret.row,
+            col: main_caret.col,
+            lines: self.editor.len(),
+        };
+        status::render(&self.status_format, &status, self.col)
+    }
+    /// Rows covered by the main caret and its selection.
+    fn caret_span(&self) -> (usize, usize) {
+        match self.editor.selection(0) {
+            Some((start, end)) => (start.0, end.0),
+            None => {
+                let row = self.editor.carets.first().expect("Caret not found!").row;
+                (row, row)
+            }
+        }
+    }
+    fn remember_frame(&mut self) {
+        self.drawn_y = self.y;
+        self.drawn_pad = self.gutter_width();
+        self.drawn_row = self.editor.carets.first().expect("Caret not found!").row;
+        self.drawn_span = self.caret_span();
+    }
+    /// Columns taken by the sign column and line numbers, including the space
+    /// before the text.
+    fn gutter_width(&self) -> usize {
+        let signs = if self.sign_column { 1 } else { 0 };
+        match self.line_numbers {
+            LineNumbers::Off => signs,
+            _ => signs + self.lnum_pad + 1,
+        }
+    }
+    pub fn set_sign(&mut self, row: usize, sign: char, color: Color) {
+        self.signs.insert(row, (sign, color));
+        self.editor.mark_dirty(row);
+    }
+    pub fn clear_signs(&mut self) {
+        let rows: Vec<usize> = self.signs.keys().cloned().collect();
+        for row in rows {
+            self.editor.mark_dirty(row);
+        }
+        self.signs.clear();
+    }
+    /// Marks the hunks changed since the committed version in the sign column,
+    /// and over them the rows with diagnostics.
+    fn update_signs(&mut self) {
+        let mut diagnostics: BTreeMap<usize, Diagnostic> = BTreeMap::new();
+        for diagnostic in self.lsp.diagnostics(&self.editor) {
+            let worse = match diagnostics.get(&diagnostic.row) {
+                Some(shown) => diagnostic.severity < shown.severity,
+                None => true,
+            };
+            if worse {
+                diagnostics.insert(diagnostic.row, diagnostic);
+            }
+        }
+        if !self.editor.has_base() && diagnostics.is_empty() && self.diagnostics.is_empty() {
+            return;
+        }
+        let mut signs = BTreeMap::new();
+        for hunk in self.editor.hunks() {
+            match hunk.kind() {
+                HunkKind::Added => {
+                    for row in hunk.new_start..hunk.new_start + hunk.new_len {
+                        signs.insert(row, ('+', Color::Green));
+                    }
+                }
+                HunkKind::Modified => {
+                    for row in hunk.new_start..hunk.new_start + hunk.new_len {
+                        signs.insert(row, ('~', Color::Yellow));
+                    }
+                }
+                HunkKind::Deleted => {
+                    let sign = if hunk.new_start > 0 { '_' } else { '‾' };
+                    signs.insert(hunk.row(), (sign, Color::Red));
+                }
+            }
+        }
+        for (&row, diagnostic) in diagnostics.iter() {
+            signs.insert(row, (diagnostic.severity.sign(), diagnostic.severity.color()));
+        }
+        let rows: Vec<usize> = self.signs.keys().chain(signs.keys()).cloned().collect();
+        for row in rows {
+            if self.signs.get(&row) != signs.get(&row) ||
+               self.diagnostics.get(&row) != diagnostics.get(&row) {
+                self.editor.mark_dirty(row);
+            }
+        }
+        if !diagnostics.is_empty() {
+            self.sign_column = true;
+        }
+        self.signs = signs;
+        self.diagnostics = diagnostics;
+    }
+    fn gutter_text(&self, index: usize) -> String {
+        let caret_row = self.editor.carets.first().expect("Caret not found!").row;
+        let distance = index.abs_diff(caret_row);
+        match self.line_numbers {
+            LineNumbers::Off => String::new(),
+            LineNumbers::Absolute => right_aligned_text(&(index + 1).to_string(), self.lnum_pad),
+            LineNumbers::Relative => right_aligned_text(&distance.to_string(), self.lnum_pad),
+            LineNumbers::Hybrid if distance == 0 => {
+                left_aligned_text(&(index + 1).to_string(), self.lnum_pad)
+            }
+            LineNumbers::Hybrid => right_aligned_text(&distance.to_string(), self.lnum_pad),
+        }
     }
+    /// Repaints the whole screen.
     fn redraw(&mut self) {
+        self.lnum_pad = self.editor.len().to_string().chars().count();
+        self.update_signs();
         self.clear();
+        self.editor.take_dirty();
         let start = self.y;
-        let end = self.editor.len() - start;
         let height = self.row;
         for idx in start..start + height {
             self.redraw_line(idx);
         }
+        self.draw_completion();
+        self.redraw_infobar();
+        self.remember_frame();
+    }
+    /// Repaints the rows changed since the last frame and the old and new caret
+    /// rows, or everything after scrolling.
+    fn refresh(&mut self) {
+        if self.editor.is_dirty() {
+            self.update_signs();
+        }
+        self.lnum_pad = self.editor.len().to_string().chars().count();
+        let relative = matches!(self.line_numbers, LineNumbers::Relative | LineNumbers::Hybrid);
+        // Relative numbers change on every row when the caret changes rows.
+        let moved = relative &&
+                    self.editor.carets.first().expect("Caret not found!").row != self.drawn_row;
+        if self.y != self.drawn_y || self.gutter_width() != self.drawn_pad || moved {
+            self.redraw();
+            return;
+        }
+        let dirty = self.editor.take_dirty();
+        let old = self.drawn_span;
+        let new = self.caret_span();
+        for idx in self.y..self.y + self.row {
+            if dirty.contains(idx) || (old.0 <= idx && idx <= old.1) ||
+               (new.0 <= idx && idx <= new.1) {
+                self.redraw_line(idx);
+            }
+        }
+        self.draw_completion();
         self.redraw_infobar();
+        self.remember_frame();
     }
     fn redraw_infobar(&mut self) {
-        let info = self.format_info();
+        let info = match self.message {
+            Some(ref message) => left_aligned_text(message, self.col),
+            None => self.format_info(),
+        };
         self.terminal.print(0,
                             self.row,
-                            rustbox::RB_NORMAL,
+                            Style::Normal,
                             Color::White,
                             Color::Blue,
                             &info);
     }
     fn redraw_line(&mut self, index: usize) {
-        let main_caret = self.editor.carets.get(0).expect("Caret not found!");
+        if index < self.y || index >= self.y + self.row {
+            return;
+        }
         let dy = index - self.y;
-        // write!(self.stdout,
-        //     "{}{}",
-        //   cursor::Goto(1, dy + 1),
-        // clear::CurrentLine);
-        if self.editor.len() > index && index >= self.y && self.y + self.row >= index {
-            let line = self.editor.get(index).unwrap();
-            self.terminal.print(0,
+        self.terminal.print(0,
+                            dy,
+                            Style::Normal,
+                            Color::Default,
+                            Color::Default,
+                            &left_aligned_text("", self.col));
+        if index >= self.editor.len() {
+            return;
+        }
+        if let Some(&(sign, color)) = self.signs.get(&index) {
+            self.terminal.print_char(0, dy, Style::Bold, color, Color::Default, sign);
+        }
+        let numbers = if self.sign_column { 1 } else { 0 };
+        let lnum = self.gutter_text(index);
+        self.terminal.print(numbers, dy, Style::Bold, Color::Yellow, Color::Default, &lnum);
+        let gutter = self.gutter_width();
+        let main_caret = self.editor.carets.first().expect("Caret not found!");
+        let line = self.editor.get(index).unwrap();
+        let selection = self.editor.selection(0);
+        let reversed = |col: usize| {
+            let selected = match selection {
+                Some((start, end)) => start <= (index, col) && (index, col) < end,
+                None => false,
+            };
+            selected || (main_caret.row == index && main_caret.col == col)
+        };
+        // Print runs of equally styled characters rather than single cells,
+        // from the first column not scrolled out of view.
+        let mut start = cmp::min(self.x, line.len());
+        while start < line.len() {
+            let style = reversed(start);
+            let mut end = start + 1;
+            while end < line.len() && reversed(end) == style {
+                end += 1;
+            }
+            let run: String = line.range(start..end).cloned().collect();
+            self.terminal.print(gutter + start - self.x,
                                 dy,
-                                rustbox::RB_BOLD,
-                                Color::Yellow,
+                                if style { Style::Reverse } else { Style::Normal },
+                                Color::White,
                                 Color::Default,
-                                &right_aligned_text(&(index + 1).to_string(), self.lnum_pad));
-            if main_caret.row == index {
-                let col = main_caret.col;
-                let count = line.len();
-                if col == count {
-                    self.terminal.print(self.lnum_pad + 1,
-                                        dy,
-                                        rustbox::RB_NORMAL,
-                                        Color::White,
-                                        Color::Default,
-                                        &line.extract());
-                    self.terminal.print_char(self.lnum_pad + count + 1,
-                                             dy,
-                                             rustbox::RB_REVERSE,
-                                             Color::White,
-                                             Color::Default,
-                                             ' ');
-                } else {
-                    for idx in 0..col {
-                        self.terminal.print_char(self.lnum_pad + idx + 1,
-                                                 dy,
-                                                 rustbox::RB_NORMAL,
-                                                 Color::White,
-                                                 Color::Default,
-                                                 line[idx]);
+                                &run);
+            start = end;
+        }
+        if main_caret.row == index && main_caret.col == line.len() && line.len() >= self.x {
+            self.terminal.print_char(gutter + line.len() - self.x,
+                                     dy,
+                                     Style::Reverse,
+                                     Color::White,
+                                     Color::Default,
+                                     ' ');
+        }
+        // The diagnostic of the row trails it, as far as it fits, in the same
+        // columns as the text.
+        if let Some(diagnostic) = self.diagnostics.get(&index) {
+            let col = line.len() + 2;
+            let x = gutter + cmp::max(col, self.x) - self.x;
+            if x < self.col {
+                let text: String = diagnostic.message
+                                             .chars()
+                                             .skip(self.x.saturating_sub(col))
+                                             .take(self.col - x)
+                                             .collect();
+                self.terminal.print(x,
+                                    dy,
+                                    Style::Normal,
+                                    diagnostic.severity.color(),
+                                    Color::Default,
+                                    &text);
+            }
+        }
+    }
+    /// Where the completion popup goes on screen, below the caret or above it
+    /// when there is more room, as left column, top row, width and height.
+    fn completion_area(&self) -> Option<(usize, usize, usize, usize)> {
+        let completion = match self.completion {
+            Some(ref completion) => completion,
+            None => return None,
+        };
+        let caret = self.editor.carets.first().expect("Caret not found!");
+        if caret.row < self.y || caret.row >= self.y + self.row {
+            return None;
+        }
+        let dy = caret.row - self.y;
+        let below = self.row - dy - 1;
+        let wanted = cmp::min(completion.words.len(), COMPLETION_ROWS);
+        let (top, height) = if wanted <= below || below >= dy {
+            (dy + 1, cmp::min(wanted, below))
+        } else {
+            (dy - cmp::min(wanted, dy), cmp::min(wanted, dy))
+        };
+        if height == 0 {
+            return None;
+        }
+        let longest = completion.words.iter().map(|word| word.chars().count()).max().unwrap_or(0);
+        let width = cmp::min(longest + 2, self.col);
+        let prefix = completion.prefix.chars().count();
+        let start = self.gutter_width() + caret.col.saturating_sub(prefix);
+        Some((cmp::min(start, self.col - width), top, width, height))
+    }
+    fn draw_completion(&mut self) {
+        let (x, top, width, height) = match self.completion_area() {
+            Some(area) => area,
+            None => return,
+        };
+        self.completion_rows = (self.y + top, self.y + top + height);
+        let completion = self.completion.as_ref().expect("Completion not found!");
+        for idx in 0..height {
+            let item = completion.top + idx;
+            let word = completion.words.get(item).map_or("", |word| &word[..]);
+            let (fg, bg) = if item == completion.selected {
+                (Color::White, Color::Blue)
+            } else {
+                (Color::Black, Color::White)
+            };
+            self.terminal.print(x,
+                                top + idx,
+                                Style::Normal,
+                                fg,
+                                bg,
+                                &left_aligned_text(&format!(" {}", word), width));
+        }
+    }
+    /// Replaces the completion popup, repainting the rows the old one hid.
+    fn set_completion(&mut self, completion: Option<Completion>) {
+        let (start, end) = mem::take(&mut self.completion_rows);
+        for row in start..end {
+            self.editor.mark_dirty(row);
+        }
+        self.completion = completion;
+    }
+    /// Offers the words of all buffers which complete the one before the
+    /// caret. Returns `false` when there are none.
+    fn open_completion(&mut self) -> bool {
+        let prefix = self.editor.word_prefix();
+        let words = {
+            let others: Vec<&Editor> = self.hidden.iter().map(|buffer| &buffer.editor).collect();
+            self.editor.completions(&prefix, &others)
+        };
+        if words.is_empty() {
+            self.set_completion(None);
+            return false;
+        }
+        self.set_completion(Some(Completion {
+            prefix,
+            words,
+            selected: 0,
+            top: 0,
+        }));
+        true
+    }
+    /// Moves through the completion popup, or takes or dismisses the word
+    /// picked. Returns `false` for keys left to the editor.
+    fn handle_completion_key(&mut self, key: Key) -> bool {
+        let (count, selected) = match self.completion {
+            Some(ref completion) => (completion.words.len(), completion.selected),
+            None => return false,
+        };
+        let selected = match key {
+            Key::Down | Key::Ctrl('n') => (selected + 1) % count,
+            Key::Up | Key::Ctrl('p') => (selected + count - 1) % count,
+            Key::Enter | Key::Tab => {
+                let completion = self.completion.take().expect("Completion not found!");
+                self.set_completion(None);
+                self.editor.complete(&completion.prefix, &completion.words[selected]);
+                self.scroll_to_caret();
+                return true;
+            }
+            Key::Esc => {
+                self.set_completion(None);
+                return true;
+            }
+            _ => return false,
+        };
+        let rows = self.completion_area().map_or(1, |area| area.3);
+        if let Some(ref mut completion) = self.completion {
+            completion.selected = selected;
+            if selected < completion.top {
+                completion.top = selected;
+            } else if selected >= completion.top + rows {
+                completion.top = selected + 1 - rows;
+            }
+        }
+        true
+    }
+    /// Opens the completion popup on `Ctrl-n` or once the prefix typed is long
+    /// enough, and follows the word as it is typed. Other keys close it.
+    fn update_completion(&mut self, key: Key) {
+        let typing = match key {
+            Key::Ctrl('n') => {
+                if !self.open_completion() {
+                    self.message = Some(String::from("No completions"));
+                }
+                return;
+            }
+            Key::Char(c) => self.editor.is_word_char(c),
+            Key::Backspace => self.completion.is_some(),
+            _ => false,
+        };
+        let long_enough = self.complete_after > 0 &&
+                          self.editor.word_prefix().chars().count() >= self.complete_after;
+        if typing && (self.completion.is_some() || long_enough) {
+            self.open_completion();
+        } else if self.completion.is_some() {
+            self.set_completion(None);
+        }
+    }
+    fn scroll_to_caret(&mut self) {
+        let row = self.editor.carets.first().expect("Caret not found!").row;
+        if row < self.y {
+            self.y = row;
+        } else if row >= self.y + self.row {
+            self.y = row + 1 - self.row;
+        }
+    }
+    /// Moves the main caret to the nearest row on screen after scrolling,
+    /// keeping its column where the row allows.
+    fn caret_into_view(&mut self) {
+        let (row, col) = {
+            let main_caret = self.editor.carets.first().expect("Caret not found!");
+            (main_caret.row, main_caret.col)
+        };
+        let shown = cmp::max(self.y, cmp::min(row, self.y + self.row - 1));
+        if shown != row {
+            self.editor.place_caret(shown, col);
+        }
+    }
+    fn draw_caret(&mut self) {
+        let main_caret = self.editor.carets.first().expect("Caret not found!");
+        let position = (main_caret.col - self.x + self.gutter_width(), main_caret.row - self.y);
+        self.terminal.set_cursor(Some(position));
+    }
+    /// Adapts the viewport to a new terminal size, keeping the caret visible.
+    fn resize(&mut self, width: usize, height: usize) {
+        self.col = width;
+        self.row = if height > 1 { height - 1 } else { 1 };
+        self.scroll_to_caret();
+        self.clear();
+        self.redraw();
+    }
+    /// Maps a screen cell to the nearest buffer position. Returns `None` for the
+    /// infobar.
+    fn screen_to_buffer(&self, x: usize, y: usize) -> Option<(usize, usize)> {
+        if y >= self.row {
+            return None;
+        }
+        let row = y + self.y;
+        let last = self.editor.len() - 1;
+        let row = if row > last { last } else { row };
+        let col = (x + self.x).saturating_sub(self.gutter_width());
+        let len = self.editor.get(row).expect("Line out of bounds!").len();
+        Some((row, if col > len { len } else { col }))
+    }
+    fn handle_mouse(&mut self, mouse: Mouse, x: usize, y: usize) {
+        match mouse {
+            Mouse::WheelUp => {
+                self.y = self.y.saturating_sub(WHEEL_LINES);
+                self.caret_into_view();
+            }
+            Mouse::WheelDown => {
+                let last = self.editor.len() - 1;
+                self.y = if self.y + WHEEL_LINES > last { last } else { self.y + WHEEL_LINES };
+                self.caret_into_view();
+            }
+            Mouse::Left => {
+                let (row, col) = match self.screen_to_buffer(x, y) {
+                    Some(pos) => pos,
+                    None => return,
+                };
+                let now = Instant::now();
+                self.click_count = match self.last_click {
+                    Some((at, last_x, last_y)) if last_x == x && last_y == y &&
+                                                  now.duration_since(at) <
+                                                  Duration::from_millis(DOUBLE_CLICK_MS) => {
+                        self.click_count % 3 + 1
                     }
-                    let c = match line.get(col) {
-                        Some(ch) => *ch,
-                        None => ' ',
-                    };
-                    self.terminal.print_char(self.lnum_pad + col + 1,
-                                             dy,
-                                             rustbox::RB_REVERSE,
-                                             Color::White,
-                                             Color::Default,
-                                             c);
-                    let ranged_col = col + main_caret.range as usize;
-                    for idx in col + 1..ranged_col + 1 {
-                        self.terminal.print_char(self.lnum_pad + idx + 1,
-                                                 dy,
-                                                 rustbox::RB_REVERSE,
-                                                 Color::White,
-                                                 Color::Default,
-                                                 line[idx]);
+                    _ => 1,
+                };
+                self.last_click = Some((now, x, y));
+                match self.click_count {
+                    1 => self.editor.place_caret(row, col),
+                    2 => self.editor.select_word_at(row, col),
+                    _ => self.editor.select_line(row),
+                }
+            }
+            Mouse::Drag | Mouse::Release => {
+                // Termbox reports no motion, so there a drag is a press and a
+                // release at different cells.
+                let pressed = match self.last_click {
+                    Some((_, last_x, last_y)) => (last_x, last_y),
+                    None => return,
+                };
+                if self.click_count == 1 && pressed != (x, y) {
+                    if let Some((row, col)) = self.screen_to_buffer(x, y) {
+                        self.editor.select_to(row, col);
                     }
-                    for idx in ranged_col + 1..count {
-                        self.terminal.print_char(self.lnum_pad + idx + 1,
-                                                 dy,
-                                                 rustbox::RB_NORMAL,
-                                                 Color::White,
-                                                 Color::Default,
-                                                 line[idx]);
+                }
+            }
+            _ => {}
+        }
+    }
+    /// Handles an input event. Returns `false` when the editor should quit.
+    fn handle_event(&mut self, event: Event) -> bool {
+        match event {
+            Event::FocusGained => {
+                self.check_disk();
+                return true;
+            }
+            Event::FocusLost => {
+                self.autosave();
+                return true;
+            }
+            Event::Closed => return false,
+            _ => {}
+        }
+        self.last_input = Instant::now();
+        match event {
+            Event::Key(key) => {
+                self.message = None;
+                let running = self.handle_key(key);
+                self.run_hooks();
+                return running;
+            }
+            Event::Resize(width, height) => {
+                self.set_completion(None);
+                self.resize(width, height);
+            }
+            Event::Mouse(mouse, x, y) => {
+                self.set_completion(None);
+                self.handle_mouse(mouse, x, y);
+            }
+            Event::FocusGained | Event::FocusLost | Event::Closed => {}
+        }
+        self.run_hooks();
+        true
+    }
+    /// Runs the hooks for what the buffer went through, and shows the message
+    /// the scripts among them left.
+    fn run_hooks(&mut self) {
+        self.editor.run_hooks();
+        if let Some(message) = self.scripts.take_message() {
+            self.message = Some(message);
+        }
+    }
+    /// How long the event loop may wait for input before it has work to do.
+    fn idle_timeout(&self) -> Duration {
+        let swap = Duration::from_millis(SWAP_INTERVAL_MS);
+        let idle = if self.autosave == Autosave::Off {
+            swap
+        } else {
+            cmp::min(swap, self.autosave_idle)
+        };
+        if self.lsp.is_running() {
+            cmp::min(idle, Duration::from_millis(LSP_POLL_MS))
+        } else {
+            idle
+        }
+    }
+    /// Sends the language servers what changed and shows what they sent.
+    fn update_lsp(&mut self) {
+        let replies = {
+            let editors: Vec<&Editor> = Some(&self.editor)
+                                            .into_iter()
+                                            .chain(self.hidden.iter().map(|buffer| &buffer.editor))
+                                            .collect();
+            let line = |path: &Path, row: usize| {
+                editors.iter()
+                       .find(|editor| {
+                           editor.path().map(session::absolute).is_some_and(|file| file == path)
+                       })
+                       .and_then(|editor| editor.get(row))
+                       .map(|line| line.iter().collect())
+            };
+            self.lsp.update(&line)
+        };
+        for reply in replies {
+            self.handle_reply(reply);
+        }
+    }
+    fn handle_reply(&mut self, reply: Reply) {
+        match reply {
+            Reply::Hover(text) | Reply::Message(text) => self.message = Some(text),
+            Reply::Diagnostics(_) => self.update_signs(),
+            Reply::Definition(locations) | Reply::References(locations) => {
+                if locations.is_empty() {
+                    self.message = Some(String::from("Nothing found"));
+                    return;
+                }
+                self.locations = locations;
+                self.location = 0;
+                self.goto_location();
+            }
+            Reply::Rename(files) => {
+                let shown = self.editor.path().map(session::absolute);
+                let mut count = 0;
+                for (path, edits) in files {
+                    if let Err(why) = self.show_file(&path) {
+                        self.message = Some(why);
+                        continue;
                     }
+                    let edits: Vec<RangeEdit> =
+                        edits.into_iter()
+                             .map(|edit| (edit.range.0, edit.range.1, edit.text))
+                             .collect();
+                    self.editor.replace_ranges(&edits);
+                    self.run_hooks();
+                    count += 1;
                 }
-
+                if let Some(path) = shown {
+                    let _ = self.show_file(&path);
+                }
+                if self.message.is_none() {
+                    let files = if count == 1 { "file" } else { "files" };
+                    self.message = Some(format!("Renamed in {} {}", count, files));
+                }
+            }
+        }
+    }
+    /// Shows the definition or reference picked in the locations.
+    fn goto_location(&mut self) {
+        let location = self.locations[self.location].clone();
+        if let Err(why) = self.show_file(&location.path) {
+            self.message = Some(why);
+            return;
+        }
+        let (row, col) = location.range.0;
+        let row = cmp::min(row, self.editor.len() - 1);
+        let col = cmp::min(col, self.editor.get(row).map_or(0, |line| line.len()));
+        self.editor.mode_normal();
+        self.editor.place_caret(row, col);
+        self.center_caret();
+        if self.locations.len() > 1 {
+            self.message = Some(format!("{} of {} (F7/F8 for the others)",
+                                        self.location + 1,
+                                        self.locations.len()));
+        }
+    }
+    /// Goes to the next of the locations looked up, or the previous one.
+    fn step_location(&mut self, forward: bool) {
+        let count = self.locations.len();
+        if count == 0 {
+            return;
+        }
+        self.location = if forward {
+            (self.location + 1) % count
+        } else {
+            (self.location + count - 1) % count
+        };
+        self.goto_location();
+    }
+    /// Shows the buffer of the file, reading it into a new one if it isn't
+    /// open.
+    fn show_file(&mut self, path: &Path) -> Result<(), String> {
+        let path = session::absolute(path);
+        if self.editor.path().map(session::absolute).as_ref() == Some(&path) {
+            return Ok(());
+        }
+        let found = self.hidden
+                        .iter()
+                        .position(|buffer| {
+                            buffer.editor.path().map(session::absolute).as_ref() == Some(&path)
+                        });
+        let shown = match found {
+            Some(idx) => {
+                // Keep the order the buffers are cycled in.
+                let mut after = self.hidden.split_off(idx);
+                let buffer = after.pop_front().expect("Buffer not found!");
+                let before = mem::replace(&mut self.hidden, after);
+                let shown = self.swap_shown(buffer);
+                self.hidden.push_back(shown);
+                self.hidden.extend(before);
+                self.redraw();
+                self.check_disk();
+                return Ok(());
+            }
+            None => {
+                let mut editor = Editor::new();
+                editor.set_hooks(self.hooks.clone());
+                editor.read_file(&path)?;
+                let number = self.buffer_count();
+                self.swap_shown(Hidden::new(editor, number))
+            }
+        };
+        self.hidden.push_back(shown);
+        let base = self.editor.path().and_then(git::committed_text);
+        self.editor.set_base(base.as_ref().map(|text| &text[..]));
+        self.redraw();
+        Ok(())
+    }
+    /// Asks the language server of the buffer about the main caret, for the
+    /// key: hover, definition, references or rename.
+    fn request_lsp(&mut self, key: Key) {
+        let result = match key {
+            Key::Alt('k') => self.lsp.hover(&self.editor),
+            Key::Alt('g') => self.lsp.definition(&self.editor),
+            Key::Alt('f') => self.lsp.references(&self.editor),
+            _ => {
+                match self.prompt("Rename to: ") {
+                    Some(ref name) if !name.is_empty() => self.lsp.rename(&self.editor, name),
+                    _ => Ok(()),
+                }
+            }
+        };
+        if let Err(why) = result {
+            self.message = Some(why);
+        }
+    }
+    /// Autosaves once no input came for the idle interval.
+    fn update_autosave(&mut self) {
+        if self.last_input.elapsed() >= self.autosave_idle {
+            self.autosave();
+        }
+    }
+    /// Writes the modified buffers, hidden ones too, to their files or side
+    /// files, as configured, and notes it in the infobar. Buffers without a
+    /// file are left alone.
+    fn autosave(&mut self) {
+        if self.autosave == Autosave::Off {
+            return;
+        }
+        let mode = self.autosave;
+        let mut saved = Vec::new();
+        let mut failed = Vec::new();
+        match autosave_buffer(mode, &mut self.editor, &mut self.autosaved_version) {
+            Some(Ok(target)) => saved.push(target),
+            Some(Err(why)) => failed.push(why),
+            None => {}
+        }
+        for buffer in self.hidden.iter_mut() {
+            match autosave_buffer(mode, &mut buffer.editor, &mut buffer.autosaved_version) {
+                Some(Ok(target)) => saved.push(target),
+                Some(Err(why)) => failed.push(format!("{}: {}", buffer.editor.filename(), why)),
+                None => {}
+            }
+        }
+        if let Some(why) = failed.into_iter().next() {
+            self.message = Some(why);
+        } else if saved.len() > 1 {
+            self.message = Some(format!("Autosaved {} buffers", saved.len()));
+        } else if let Some(target) = saved.pop() {
+            self.message = Some(format!("Autosaved to {}", target.display()));
+        }
+    }
+    /// Saves the buffer, asking for a file name if it has none. Returns `false`
+    /// when it was not saved.
+    fn save(&mut self) -> bool {
+        let result = if self.editor.path().is_some() {
+            if !self.check_disk() {
+                return false;
+            }
+            self.editor.save()
+        } else {
+            match self.prompt("Save as: ") {
+                Some(ref name) if !name.is_empty() => self.editor.save_as(name),
+                _ => return false,
+            }
+        };
+        match result {
+            Ok(()) => true,
+            Err(why) => {
+                self.ask(&format!("{} (press any key)", why), "");
+                false
+            }
+        }
+    }
+    /// Checks whether someone else changed the file since it was read or
+    /// saved. An unmodified buffer is reloaded right away; otherwise the user
+    /// picks between reloading, keeping the buffer and diffing it against the
+    /// file. Returns `false` when the buffer should not be written over the
+    /// file.
+    fn check_disk(&mut self) -> bool {
+        let change = match self.editor.changed_on_disk() {
+            Some(change) => change,
+            None => return true,
+        };
+        let reloaded = format!("Reloaded {}", self.editor.filename());
+        if !self.editor.is_modified() {
+            self.editor.reload(&change);
+            self.message = Some(reloaded);
+            return true;
+        }
+        self.redraw();
+        match self.ask("File changed on disk: (r)eload, (k)eep ours, (d)iff", "rkd") {
+            Some('r') => {
+                self.editor.reload(&change);
+                self.message = Some(reloaded);
+                false
+            }
+            Some('k') => {
+                self.editor.accept_disk(&change);
+                true
+            }
+            Some('d') => {
+                // Mark what the buffer changes; reverting a hunk takes the
+                // file's version.
+                self.editor.accept_disk(&change);
+                self.editor.set_base(Some(&change.text));
+                self.sign_column = true;
+                false
+            }
+            _ => false,
+        }
+    }
+    /// Writes the swap file if the buffer changed since the last write, at most
+    /// once per interval, and removes it once the buffer is saved.
+    fn update_swap(&mut self) {
+        if !self.editor.is_modified() {
+            self.remove_swap();
+            return;
+        }
+        if self.editor.version() == self.swapped_version {
+            return;
+        }
+        if self.swap_file.is_some() &&
+           self.swapped_at.elapsed() < Duration::from_millis(SWAP_INTERVAL_MS) {
+            return;
+        }
+        self.write_swap();
+    }
+    /// Writes the swap file now, replacing the one of an earlier file name.
+    fn write_swap(&mut self) {
+        let path = swap::swap_path(self.editor.path());
+        if self.swap_file.as_ref().is_some_and(|old| *old != path) {
+            self.remove_swap();
+        }
+        // Failing to write the swap file must not get in the way of editing.
+        if may_write_swap(&path, self.swap_file.as_ref()) &&
+           swap::write_swap(&path, &self.editor.swap_data()).is_ok() {
+            self.swap_file = Some(path);
+        }
+        self.swapped_version = self.editor.version();
+        self.swapped_at = Instant::now();
+    }
+    fn remove_swap(&mut self) {
+        if let Some(path) = self.swap_file.take() {
+            swap::remove_swap(&path);
+        }
+    }
+    /// Writes modified buffers to recovery files after a crash, along with a
+    /// swap file. Returns the recovery files written.
+    fn dump_buffers(&mut self) -> Vec<PathBuf> {
+        let mut dumps = Vec::new();
+        let editors = Some((&self.editor, &self.swap_file))
+                          .into_iter()
+                          .chain(self.hidden.iter().map(|b| (&b.editor, &b.swap_file)));
+        for (editor, own) in editors.filter(|&(editor, _)| editor.is_modified()) {
+            let path = swap::swap_path(editor.path());
+            if may_write_swap(&path, own.as_ref()) {
+                let _ = swap::write_swap(&path, &editor.swap_data());
+            }
+            for path in swap::recovery_paths(editor.path()) {
+                // Written as a save would be, or as UTF-8 when the encoding
+                // can't hold the text.
+                if editor.save_copy(&path).is_ok() ||
+                   swap::write_swap(&path, &editor.get_all()).is_ok() {
+                    dumps.push(path);
+                    break;
+                }
+            }
+        }
+        dumps
+    }
+    /// Removes the swap files of all buffers, once the editor quits.
+    fn remove_swaps(&mut self) {
+        self.remove_swap();
+        for buffer in self.hidden.iter_mut() {
+            if let Some(path) = buffer.swap_file.take() {
+                swap::remove_swap(&path);
+            }
+        }
+    }
+    /// Number of buffers open, the one shown included.
+    fn buffer_count(&self) -> usize {
+        self.hidden.len() + 1
+    }
+    /// Shows the buffer instead of the current one, which is returned. The
+    /// swap file of the buffer put away is brought up to date first.
+    fn swap_shown(&mut self, buffer: Hidden) -> Hidden {
+        if !self.editor.is_modified() {
+            self.remove_swap();
+        } else if self.editor.version() != self.swapped_version {
+            self.write_swap();
+        }
+        Hidden {
+            editor: mem::replace(&mut self.editor, buffer.editor),
+            number: mem::replace(&mut self.number, buffer.number),
+            x: mem::replace(&mut self.x, buffer.x),
+            y: mem::replace(&mut self.y, buffer.y),
+            swap_file: mem::replace(&mut self.swap_file, buffer.swap_file),
+            swapped_version: mem::replace(&mut self.swapped_version, buffer.swapped_version),
+            autosaved_version: mem::replace(&mut self.autosaved_version,
+                                            buffer.autosaved_version),
+        }
+    }
+    /// Shows the next buffer, or the previous one, wrapping around.
+    fn switch_buffer(&mut self, forward: bool) {
+        let next = if forward { self.hidden.pop_front() } else { self.hidden.pop_back() };
+        if let Some(next) = next {
+            let shown = self.swap_shown(next);
+            if forward {
+                self.hidden.push_back(shown);
             } else {
-                self.terminal.print(self.lnum_pad + 1,
-                                    dy,
-                                    rustbox::RB_NORMAL,
-                                    Color::White,
-                                    Color::Default,
-                                    &line.extract());
+                self.hidden.push_front(shown);
             }
+            self.redraw();
+            self.check_disk();
         }
     }
-    fn draw_caret(&mut self) {
-        let main_caret = self.editor.carets.get(0).expect("Caret not found!");
-        self.terminal.set_cursor((main_caret.col - self.x + 1 + self.lnum_pad) as isize,
-                                 (main_caret.row - self.y) as isize);
+    /// Offers to recover the swap file left behind by an earlier session.
+    fn recover_swap(&mut self) {
+        let path = swap::swap_path(self.editor.path());
+        let data = match fs::read_to_string(&path) {
+            Ok(data) => data,
+            Err(_) => return,
+        };
+        self.redraw();
+        let message = "Swap file found: (r)estore, (d)iff with file, (x) delete, (i)gnore";
+        match self.ask(message, "rdxi") {
+            Some(answer) if answer == 'r' || answer == 'd' => {
+                let disk = self.editor.get_all();
+                match self.editor.restore_swap(&data) {
+                    Ok(()) if answer == 'd' => {
+                        // Mark what the swap file changes; reverting a hunk
+                        // takes the file's version.
+                        self.editor.set_base(Some(&disk));
+                        self.sign_column = true;
+                    }
+                    Ok(()) => {}
+                    Err(why) => {
+                        self.ask(&format!("{} (press any key)", why), "");
+                        self.message = Some(String::from(SWAP_KEPT));
+                        return;
+                    }
+                }
+                // The swap file is in the buffer now; it is the buffer's own.
+                self.swap_file = Some(path);
+            }
+            Some('x') => swap::remove_swap(&path),
+            _ => self.message = Some(String::from(SWAP_KEPT)),
+        }
+    }
+    /// Asks what to do with unsaved changes before quitting. Returns `true`
+    /// when the editor may quit.
+    fn confirm_quit(&mut self) -> bool {
+        // Go round all buffers, ending up on the one shown. In pipe mode that
+        // one goes to standard output, so its changes are not lost.
+        for step in 0..self.buffer_count() {
+            if self.editor.is_modified() && !(self.pipe && step == 0) {
+                self.redraw();
+                match self.ask("Save changes? (y)es, (n)o, (c)ancel", "ync") {
+                    Some('y') => {
+                        if !self.save() {
+                            return false;
+                        }
+                    }
+                    Some('n') => {}
+                    _ => return false,
+                }
+            }
+            self.switch_buffer(true);
+        }
+        true
+    }
+    /// Runs the command bound to the key. Returns `false` when the key quits
+    /// the editor.
+    fn handle_key(&mut self, key: Key) -> bool {
+        self.editor.notify(HookEvent::KeyPressed(key_name(key)));
+        self.run_hooks();
+        if self.handle_completion_key(key) {
+            return true;
+        }
+        if let Some(name) = self.scripts.binding(key) {
+            self.set_completion(None);
+            self.run_scripts(|scripts| scripts.run_command(&name));
+            self.scroll_to_caret();
+            return true;
+        }
+        match key {
+            Key::Left | Key::Right | Key::Up | Key::Down | Key::Home | Key::End |
+            Key::PageUp | Key::PageDown | Key::Ctrl('a') | Key::Ctrl('e') |
+            Key::CtrlLeft | Key::CtrlRight | Key::CtrlUp | Key::CtrlDown => {
+                self.editor.mode_normal();
+            }
+            Key::ShiftLeft | Key::ShiftRight | Key::ShiftUp | Key::ShiftDown => {
+                self.editor.mode_select();
+            }
+            _ => {}
+        }
+        match key {
+            Key::Enter => {
+                self.editor.insert_line();
+                {
+                    let main_caret = self.editor
+                                         .carets
+                                         .first()
+                                         .expect("Caret not found!");
+                    if main_caret.row >= self.y + self.row {
+                        self.y += 1;
+                    }
+                }
+            }
+            Key::Backspace => {
+                self.editor.backspace();
+                if self.editor.carets.first().expect("Caret not found!").row < self.y {
+                    self.y -= 1;
+                }
+            }
+            Key::Home => {
+                self.y = 0;
+                self.editor.move_top();
+            }
+            Key::End => {
+                let row = self.editor.len() - 1;
+                self.y = row;
+                self.editor.move_end();
+            }
+            Key::PageUp => {
+                self.editor.move_pageup(self.row - 1);
+                let row = self.editor.carets.first().expect("Caret not found!").row;
+                self.y = row;
+            }
+            Key::PageDown => {
+                self.editor.move_pagedown(self.row - 1);
+                let row = self.editor.carets.first().expect("Caret not found!").row + 1;
+                let len = self.editor.len();
+                if row > self.row {
+                    self.y = row - self.row;
+                } else {
+                    self.y = 0;
+                }
+            }
+            Key::Left => {
+                self.editor.move_left();
+            }
+            Key::Right => {
+                self.editor.move_right();
+            }
+            Key::Up => {
+                self.editor.move_up();
+                {
+                    let main_caret = self.editor
+                                         .carets
+                                         .first()
+                                         .expect("Caret not found!");
+                    if main_caret.row < self.y {
+                        self.y -= 1;
+                    }
+                }
+            }
+            Key::Down => {
+                self.editor.move_down();
+                {
+                    let main_caret = self.editor
+                                         .carets
+                                         .first()
+                                         .expect("Caret not found!");
+                    if main_caret.row >= self.y + self.row {
+                        self.y += 1;
+                    }
+                }
+            }
+            Key::Esc => {
+                self.editor.mode_normal();
+            }
+            Key::Ctrl('s') => {
+                self.save();
+            }
+            Key::Ctrl('q') => {
+                return !self.confirm_quit();
+            }
+            Key::Alt('x') => {
+                if let Some(name) = self.prompt("Command: ") {
+                    self.run_scripts(|scripts| scripts.run_command(&name));
+                    self.scroll_to_caret();
+                }
+            }
+            Key::Alt('k') | Key::Alt('g') | Key::Alt('f') | Key::Alt('e') => self.request_lsp(key),
+            Key::CtrlLeft | Key::CtrlRight | Key::CtrlUp | Key::CtrlDown |
+            Key::CtrlDelete | Key::ShiftLeft | Key::ShiftRight | Key::ShiftUp |
+            Key::ShiftDown | Key::AltUp | Key::AltDown | Key::Alt(_) => {
+                match key {
+                    Key::CtrlLeft => self.editor.move_word_left(),
+                    Key::CtrlRight => self.editor.move_word_right(),
+                    Key::CtrlUp => self.editor.move_paragraph_up(),
+                    Key::CtrlDown => self.editor.move_paragraph_down(),
+                    Key::CtrlDelete => self.editor.delete_word_forward(),
+                    Key::ShiftLeft => self.editor.move_left(),
+                    Key::ShiftRight => self.editor.move_right(),
+                    Key::ShiftUp => self.editor.move_up(),
+                    Key::ShiftDown => self.editor.move_down(),
+                    Key::AltUp => self.editor.move_lines_up(),
+                    Key::AltDown => self.editor.move_lines_down(),
+                    Key::Alt('d') => self.editor.duplicate(),
+                    Key::Alt('j') => self.editor.join_lines(),
+                    Key::Alt('s') => self.editor.sort_lines(false, false),
+                    Key::Alt('i') => self.editor.sort_lines(false, true),
+                    Key::Alt('n') => self.editor.sort_lines(true, false),
+                    Key::Alt('u') => self.editor.unique_lines(false),
+                    Key::Alt('r') => self.editor.reverse_lines(),
+                    Key::Alt('.') => {
+                        self.editor.next_hunk();
+                    }
+                    Key::Alt(',') => {
+                        self.editor.prev_hunk();
+                    }
+                    Key::Alt('h') => {
+                        self.editor.revert_hunk();
+                    }
+                    _ => {}
+                }
+                self.scroll_to_caret();
+            }
+            Key::Ctrl('a') => {
+                let col = self.editor.carets.first().expect("Caret not found!").col;
+                self.editor.move_line_first_nonblank();
+                if self.editor.carets.first().expect("Caret not found!").col == col {
+                    self.editor.move_line_start();
+                }
+            }
+            Key::Ctrl('e') => {
+                self.editor.move_line_end();
+            }
+            Key::Ctrl('g') => {
+                let target = self.prompt("Go to: ").and_then(|spec| Goto::parse(&spec));
+                if let Some(target) = target {
+                    self.editor.goto(&target);
+                    self.center_caret();
+                }
+            }
+            Key::Delete | Key::Ctrl('w') | Key::Ctrl('u') | Key::Ctrl('k') |
+            Key::Ctrl('d') | Key::Ctrl('z') | Key::Ctrl('y') => {
+                match key {
+                    Key::Delete => self.editor.delete_forward(),
+                    Key::Ctrl('w') => self.editor.delete_word_backward(),
+                    Key::Ctrl('u') => self.editor.delete_to_line_start(),
+                    Key::Ctrl('k') => self.editor.delete_to_line_end(),
+                    Key::Ctrl('d') => self.editor.delete_line(),
+                    Key::Ctrl('z') => {
+                        self.editor.undo();
+                    }
+                    _ => {
+                        self.editor.redo();
+                    }
+                }
+                self.scroll_to_caret();
+            }
+            Key::F(2) => {
+                self.line_numbers = self.line_numbers.next();
+            }
+            Key::F(7) => self.step_location(false),
+            Key::F(8) => self.step_location(true),
+            Key::CtrlPageDown => self.switch_buffer(true),
+            Key::CtrlPageUp => self.switch_buffer(false),
+            Key::Char(c) => {
+                self.editor.insert_char(c);
+            }
+            _ => {}
+        }
+        self.update_completion(key);
+        true
+    }
+    /// Lends the buffer shown to the scripts for the call, then shows the
+    /// message they left or the error they failed with.
+    fn run_scripts<F>(&mut self, call: F)
+        where F: FnOnce(&Scripts) -> Result<(), String>
+    {
+        self.scripts.lend(&mut self.editor);
+        let result = call(&self.scripts);
+        self.scripts.lend(&mut self.editor);
+        if let Some(message) = self.scripts.take_message() {
+            self.message = Some(message);
+        }
+        if let Err(why) = result {
+            self.message = Some(format!("Script error: {}", why));
+        }
+    }
+    /// Where the buffer is left, for session and positions files. `None` for
+    /// a buffer without a file.
+    fn buffer_state(&self) -> Option<BufferState> {
+        state_of(&self.editor, self.x, self.y)
+    }
+    /// States of all buffers with a file, the one shown first.
+    fn buffer_states(&self) -> Vec<BufferState> {
+        let mut states: Vec<BufferState> = self.buffer_state().into_iter().collect();
+        states.extend(self.hidden
+                          .iter()
+                          .filter_map(|buffer| state_of(&buffer.editor, buffer.x, buffer.y)));
+        states
+    }
+    /// Puts the carets and scroll offset back where the buffer was left.
+    fn restore_state(&mut self, state: &BufferState) {
+        self.editor.set_carets(&state.carets);
+        self.x = state.x;
+        self.y = cmp::min(state.y, self.editor.len() - 1);
+    }
+    /// Scrolls so that the main caret is in the middle of the screen.
+    fn center_caret(&mut self) {
+        let row = self.editor.carets.first().expect("Caret not found!").row;
+        self.y = row.saturating_sub(self.row / 2);
+    }
+    /// Shows the message in the infobar and waits for one of the characters in
+    /// `choices`, or any key if it is empty. Returns `None` when cancelled with
+    /// `Esc` or when the input ends.
+    fn ask(&mut self, message: &str, choices: &str) -> Option<char> {
+        self.terminal.print(0,
+                            self.row,
+                            Style::Normal,
+                            Color::White,
+                            Color::Blue,
+                            &left_aligned_text(message, self.col));
+        self.flush();
+        let answer = loop {
+            match self.terminal.poll_event(None) {
+                Some(Event::Key(Key::Esc)) | Some(Event::Closed) => break None,
+                Some(Event::Key(Key::Char(c))) if choices.contains(c) => break Some(c),
+                Some(Event::Key(_)) if choices.is_empty() => break None,
+                _ => {}
+            }
+        };
+        self.redraw_infobar();
+        answer
+    }
+    /// Reads a line of input in the infobar. Returns `None` when cancelled
+    /// with `Esc` or when the input ends.
+    fn prompt(&mut self, message: &str) -> Option<String> {
+        let mut input = String::new();
+        loop {
+            let text = format!("{}{}", message, input);
+            self.terminal.print(0,
+                                self.row,
+                                Style::Normal,
+                                Color::White,
+                                Color::Blue,
+                                &left_aligned_text(&text, self.col));
+            self.terminal.set_cursor(Some((text.chars().count(), self.row)));
+            self.flush();
+            match self.terminal.poll_event(None) {
+                Some(Event::Key(Key::Enter)) => break,
+                Some(Event::Key(Key::Esc)) | Some(Event::Closed) => {
+                    self.terminal.set_cursor(None);
+                    return None;
+                }
+                Some(Event::Key(Key::Backspace)) => {
+                    input.pop();
+                }
+                Some(Event::Key(Key::Char(c))) => input.push(c),
+                _ => {}
+            }
+        }
+        self.terminal.set_cursor(None);
+        Some(input)
     }
     fn flush(&mut self) {
         self.terminal.present();
@@ -184,133 +1542,1151 @@ fn main() {
                                .long("open")
                                .value_name("FILE")
                                .help("Sets the file to edit"))
+                      .arg(Arg::with_name("TARGETS")
+                               .multiple(true)
+                               .help("Files to edit as FILE[:LINE[:COL]], or - for standard \
+                                      input; +LINE goes to the line in the file after it. \
+                                      Arguments after -- are file names."))
+                      .arg(Arg::with_name("BATCH")
+                               .long("batch")
+                               .value_name("SCRIPT")
+                               .help("Runs the editor commands in the script on each file, \
+                                      without a terminal"))
+                      .arg(Arg::with_name("PIPE")
+                               .long("pipe")
+                               .help("Writes the buffer shown on exit to standard output"))
+                      .arg(Arg::with_name("READONLY")
+                               .short("R")
+                               .long("readonly")
+                               .help("Refuses to save over the files"))
+                      .arg(Arg::with_name("ENCODING")
+                               .long("encoding")
+                               .value_name("NAME")
+                               .help("Reads and writes the files in the encoding, utf-8 or latin1"))
+                      .arg(Arg::with_name("CONFIG")
+                               .long("config")
+                               .value_name("FILE")
+                               .help("Reads settings from the file instead of the default config"))
+                      .arg(Arg::with_name("NEW_WINDOW")
+                               .long("new-window")
+                               .help("Opens the editor in a new terminal window"))
+                      .arg(Arg::with_name("FRONTEND")
+                               .long("frontend")
+                               .value_name("NAME")
+                               .possible_values(&["rustbox", "crossterm"])
+                               .default_value("rustbox")
+                               .help("Sets the terminal frontend"))
+                      .arg(Arg::with_name("LINE_NUMBERS")
+                               .long("line-numbers")
+                               .value_name("MODE")
+                               .possible_values(&["off", "absolute", "relative", "hybrid"])
+                               .help("Sets how the gutter numbers lines"))
+                      .arg(Arg::with_name("SIGN_COLUMN")
+                               .long("sign-column")
+                               .help("Shows a column for markers left of the line numbers"))
+                      .arg(Arg::with_name("SESSION")
+                               .long("session")
+                               .value_name("NAME")
+                               .help("Restores and saves the named session instead of the \
+                                      working directory's"))
                       .get_matches();
 
-    let mut view = EditorView::new();
+    let args: Vec<String> = env::args().collect();
+    // Arguments after `--` name files, even when they look like targets.
+    let literal = args.iter().position(|arg| arg == "--").map_or(0, |pos| args.len() - pos - 1);
+    let mut files: Vec<(String, Option<Goto>)> = Vec::new();
+    if let Some(file) = matches.value_of("FILE") {
+        files.push((String::from(file), None));
+    }
+    let mut target = None;
+    if let Some(values) = matches.values_of("TARGETS") {
+        let values: Vec<&str> = values.collect();
+        let plain = values.len().saturating_sub(literal);
+        for (idx, arg) in values.into_iter().enumerate() {
+            if idx >= plain {
+                let file = if arg == "-" { "./-" } else { arg };
+                files.push((String::from(file), target.take()));
+            } else if let Some(spec) = arg.strip_prefix('+') {
+                target = Goto::parse(spec);
+            } else if Path::new(arg).exists() {
+                files.push((String::from(arg), target.take()));
+            } else {
+                let (file, location) = split_location(arg);
+                files.push((String::from(file), location.or(target.take())));
+            }
+        }
+    }
+    // A target after the last file still goes to it.
+    if let Some(last) = files.last_mut() {
+        if target.is_some() {
+            last.1 = target.take();
+        }
+    }
+
+    let config = match matches.value_of("CONFIG") {
+        Some(file) => Config::load(file),
+        None => Config::load_default(),
+    };
+    let config = config.unwrap_or_else(|why| fail(&why));
+    let encoding = match matches.value_of("ENCODING").or(config.get("encoding")) {
+        Some(name) => {
+            Encoding::parse(name).unwrap_or_else(|| fail(&format!("Unknown encoding {}", name)))
+        }
+        None => Encoding::Utf8,
+    };
+    // Scripts are loaded before the files, so they see them opened.
+    let hooks = Hooks::new();
+    let mut scripts = Scripts::new();
+    let script_errors = match config::config_dir() {
+        Some(dir) => scripts.load_dir(dir.join("scripts")),
+        None => Vec::new(),
+    };
+    scripts.attach(&hooks);
+    let lsp = Lsp::new(config.prefixed("lsp."));
+    lsp.attach(&hooks);
+    let open = |file: &str| -> Result<Editor, String> {
+        let mut editor = Editor::new();
+        editor.set_hooks(hooks.clone());
+        editor.set_encoding(encoding);
+        editor.set_readonly(matches.is_present("READONLY"));
+        if file == "-" {
+            // The frontends read keys from the terminal itself, not stdin.
+            editor.read_from(stdin()).map(|()| editor)
+        } else {
+            editor.read_file(file).map(|()| editor)
+        }
+    };
+    let mut opened = Vec::new();
+    for (file, target) in files.into_iter() {
+        let editor = open(&file).unwrap_or_else(|why| fail(&why));
+        opened.push(Opened {
+            editor,
+            target,
+            state: None,
+        });
+    }
+
+    if let Some(script) = matches.value_of("BATCH") {
+        let script = fs::read_to_string(script)
+                         .unwrap_or_else(|why| fail(&format!("Couldn't read {}: {}", script, why)));
+        for why in script_errors.iter() {
+            eprintln!("mal: Script error: {}", why);
+        }
+        let mut view = EditorView::new(Box::new(MemoryFrontend::new(80, 24)));
+        view.hooks = hooks;
+        view.scripts = scripts;
+        process::exit(run_batch(view, &script, opened, target, matches.is_present("PIPE")));
+    }
+
+    if matches.is_present("NEW_WINDOW") {
+        let end = args.len() - literal;
+        let rest: Vec<String> = args[1..]
+                                    .iter()
+                                    .enumerate()
+                                    .filter(|&(idx, arg)| idx + 1 >= end || arg != "--new-window")
+                                    .map(|(_, arg)| arg.clone())
+                                    .collect();
+        match new_window(&config, &rest) {
+            Ok(()) => return,
+            Err(why) => fail(&why),
+        }
+    }
+
+    let session_file = match matches.value_of("SESSION") {
+        Some(name) => session::session_path(name),
+        None if config.get_bool("auto_session").unwrap_or(true) => {
+            env::current_dir().ok().and_then(|dir| session::directory_session_path(&dir))
+        }
+        None => None,
+    };
+    if opened.is_empty() {
+        // Reopen what the session left open, skipping files gone since.
+        let session = session_file.as_ref().and_then(|file| Session::load(file).ok());
+        for buffer in session.map_or(Vec::new(), |session| session.buffers) {
+            if let Ok(editor) = open(&buffer.path.display().to_string()) {
+                opened.push(Opened {
+                    editor,
+                    target: None,
+                    state: Some(buffer),
+                });
+            }
+        }
+    }
+
+    let terminal = match frontend::open(matches.value_of("FRONTEND").unwrap()) {
+        Ok(terminal) => terminal,
+        Err(why) => fail(&why),
+    };
+    let mut view = EditorView::new(terminal);
+    let line_numbers = matches.value_of("LINE_NUMBERS").or(config.get("line_numbers"));
+    if let Some(mode) = line_numbers.and_then(LineNumbers::parse) {
+        view.line_numbers = mode;
+    }
+    view.sign_column = matches.is_present("SIGN_COLUMN") ||
+                       config.get_bool("sign_column").unwrap_or(false);
+    view.status_format = String::from(config.get_or("status_format", status::DEFAULT_FORMAT));
+    view.pipe = matches.is_present("PIPE");
+    if let Some(mode) = config.get("autosave").and_then(Autosave::parse) {
+        view.autosave = mode;
+    }
+    if let Some(ms) = config.get("autosave_idle").and_then(|ms| ms.parse().ok()) {
+        view.autosave_idle = Duration::from_millis(ms);
+    }
+    if let Some(len) = config.get("complete_after").and_then(|len| len.parse().ok()) {
+        view.complete_after = len;
+    }
+    view.editor.set_hooks(hooks.clone());
+    view.hooks = hooks;
+    view.scripts = scripts;
+    view.lsp = lsp;
+    view.message = view.scripts.take_message();
+    if !script_errors.is_empty() {
+        view.message = Some(format!("Script error: {}", script_errors.join("; ")));
+    }
     // view.editor.insert(String::from("Hello world!!"));
 
-    if let Some(path) = matches.value_of("FILE") {
-        view.editor.read_file(Path::new(path))
+    // The default hook would print into the editor screen; keep the message
+    // until the frontend has restored the terminal instead. Panics of other
+    // threads, which don't end the editor, are reported as usual.
+    let main_thread = thread::current().id();
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        if thread::current().id() != main_thread {
+            default_hook(info);
+        } else if let Ok(mut message) = PANIC_MESSAGE.lock() {
+            *message = Some(info.to_string());
+        }
+    }));
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        edit(&mut view, opened, target, session_file)
+    }));
+    let errors = match result {
+        Ok(errors) => errors,
+        Err(_) => {
+            let dumps = view.dump_buffers();
+            // Dropping the frontend shuts it down and restores the terminal.
+            drop(view);
+            let message = PANIC_MESSAGE.lock().ok().and_then(|mut message| message.take());
+            eprintln!("mal crashed: {}", message.unwrap_or_default());
+            for dump in dumps {
+                eprintln!("Unsaved changes written to {}", dump.display());
+            }
+            process::exit(101);
+        }
+    };
+    let piped = if view.pipe {
+        Some(mem::replace(&mut view.editor, Editor::new()))
+    } else {
+        None
+    };
+    // Restore the terminal before writing, in case the output is on it.
+    drop(view);
+    for why in errors {
+        eprintln!("mal: {}", why);
     }
+    if let Some(editor) = piped {
+        editor.write_to(stdout()).unwrap_or_else(|why| fail(&why));
+    }
+}
 
-    // view.editor.mode_select();
+/// Runs the script on each file in turn in the view, or on a new buffer when
+/// there are none, and writes the results to standard output in pipe mode.
+/// Returns the exit status, which is 1 when the script failed on any file.
+fn run_batch(mut view: EditorView,
+             script: &str,
+             opened: Vec<Opened>,
+             target: Option<Goto>,
+             pipe: bool)
+             -> i32 {
+    let mut buffers: Vec<(Editor, Option<Goto>)> = opened.into_iter()
+                                                         .map(|buffer| (buffer.editor, buffer.target))
+                                                         .collect();
+    if buffers.is_empty() {
+        let mut editor = Editor::new();
+        editor.set_hooks(view.hooks.clone());
+        buffers.push((editor, target));
+    }
+    let mut status = 0;
+    for (editor, target) in buffers.into_iter() {
+        view.editor = editor;
+        if let Some(target) = target {
+            view.editor.goto(&target);
+        }
+        if let Err(why) = batch::run(&mut view, script) {
+            eprintln!("mal: {}: {}", view.editor.filename(), why);
+            status = 1;
+        }
+        if pipe {
+            if let Err(why) = view.editor.write_to(stdout()) {
+                eprintln!("mal: {}", why);
+                status = 1;
+            }
+        }
+    }
+    status
+}
+
+/// Autosaves the buffer if it changed since the version last autosaved.
+/// Returns where it went, why it didn't, or `None` when there was nothing to
+/// do.
+fn autosave_buffer(mode: Autosave,
+                   editor: &mut Editor,
+                   autosaved_version: &mut usize)
+                   -> Option<Result<PathBuf, String>> {
+    if !editor.is_modified() || editor.version() == *autosaved_version {
+        return None;
+    }
+    let path = editor.path()?.to_path_buf();
+    if mode == Autosave::File && editor.is_readonly() {
+        return None;
+    }
+    *autosaved_version = editor.version();
+    let (result, target) = match mode {
+        Autosave::Side => {
+            let side = swap::autosave_path(&path);
+            (editor.save_copy(&side), side)
+        }
+        _ => {
+            // Leave a file someone else changed for the user to sort out.
+            if editor.changed_on_disk().is_some() {
+                return Some(Err(String::from("File changed on disk; not autosaved")));
+            }
+            (editor.save(), path)
+        }
+    };
+    Some(result.map(|()| target).map_err(|why| format!("Autosave failed: {}", why)))
+}
+
+/// Whether the swap file may be written for a buffer which last wrote `own`:
+/// one left by another session or editor is never written over, as it may
+/// hold the only copy of its changes.
+fn may_write_swap(path: &Path, own: Option<&PathBuf>) -> bool {
+    own.is_some_and(|own| own == path) || !path.exists()
+}
 
-    view.lnum_pad = view.editor.len().to_string().chars().count();
+/// Reports the error and exits, before the terminal is taken over.
+fn fail(message: &str) -> ! {
+    eprintln!("mal: {}", message);
+    process::exit(1);
+}
+
+/// Runs the editor with the arguments in a new window of the terminal set by
+/// the `terminal` config or `$TERMINAL`, which must take `-e COMMAND`.
+fn new_window(config: &Config, args: &[String]) -> Result<(), String> {
+    let terminal = match config.get("terminal").map(String::from).or(env::var("TERMINAL").ok()) {
+        Some(terminal) => terminal,
+        None => return Err(String::from("Set $TERMINAL or the terminal config to open a window")),
+    };
+    let exe = env::current_exe().map_err(|why| format!("Couldn't find mal: {}", why))?;
+    Command::new(&terminal)
+        .arg("-e")
+        .arg(exe)
+        .args(args)
+        .spawn()
+        .map(|_| ())
+        .map_err(|why| format!("Couldn't run {}: {}", terminal, why))
+}
+
+/// Shows the files, or a new buffer when there are none, and runs the editor
+/// until it quits. Where each file was left is saved for the next time, along
+/// with the session. The target is for the new buffer. Returns why those
+/// couldn't be saved, to report once the terminal is restored.
+fn edit(view: &mut EditorView,
+        opened: Vec<Opened>,
+        target: Option<Goto>,
+        session_file: Option<PathBuf>)
+        -> Vec<String> {
+    // Broken session or positions files are started over rather than fatal.
+    let positions_file = session::positions_path();
+    let mut positions = positions_file.as_ref()
+                                      .and_then(|file| Session::load(file).ok())
+                                      .unwrap_or_default();
+    let count = opened.len();
+    for (idx, buffer) in opened.into_iter().enumerate() {
+        if idx == 0 {
+            view.editor = buffer.editor;
+        } else {
+            let shown = view.swap_shown(Hidden::new(buffer.editor, idx));
+            view.hidden.push_back(shown);
+        }
+        let base = view.editor.path().and_then(git::committed_text);
+        view.editor.set_base(base.as_ref().map(|text| &text[..]));
+        if view.editor.has_base() {
+            view.sign_column = true;
+        }
+        let state = buffer.state.or_else(|| {
+            view.buffer_state().and_then(|state| positions.find(&state.path).cloned())
+        });
+        if let Some(state) = state {
+            view.restore_state(&state);
+        }
+        if let Some(target) = buffer.target {
+            view.editor.goto(&target);
+            view.center_caret();
+        }
+        view.recover_swap();
+    }
+    // Start on the first file.
+    if count > 1 {
+        view.switch_buffer(true);
+    }
+    if let Some(target) = target {
+        view.editor.goto(&target);
+        view.center_caret();
+    }
+
+    // view.editor.mode_select();
 
     view.clear();
     view.redraw();
     // view.draw_caret();
     view.flush();
+    // Start the language servers of the files, so they poll from the start.
+    view.update_lsp();
     loop {
-        match view.terminal.poll_event(false) {
-            Ok(rustbox::Event::KeyEvent(key)) => {
-                match key {
-                    Key::Enter => {
-                        view.editor.insert_line();
-                        {
-                            let main_caret = view.editor
-                                                 .carets
-                                                 .get(0)
-                                                 .expect("Caret not found!");
-                            if main_caret.row >= view.y + view.row {
-                                view.y += 1;
-                            }
-                        }
-                        view.lnum_pad = view.editor.len().to_string().chars().count();
-                        view.redraw();
-                    }
-                    Key::Backspace => {
-                        view.editor.backspace();
-                        if view.editor.carets.get(0).expect("Caret not found!").row < view.y {
-                            view.y -= 1;
-                            view.redraw();
-                        }
-                        view.redraw();
-                    }
-                    Key::Home => {
-                        view.y = 0;
-                        view.editor.move_top();
-                        view.redraw();
-                    }
-                    Key::End => {
-                        let row = view.editor.len() - 1;
-                        view.y = row;
-                        view.editor.move_end();
-                        view.redraw();
-                    }
-                    Key::PageUp => {
-                        view.editor.move_pageup(view.row - 1);
-                        let row = view.editor.carets.get(0).expect("Caret not found!").row;
-                        view.y = row;
-                        view.redraw();
-                    }
-                    Key::PageDown => {
-                        view.editor.move_pagedown(view.row - 1);
-                        let row = view.editor.carets.get(0).expect("Caret not found!").row + 1;
-                        let len = view.editor.len();
-                        if row > view.row {
-                            view.y = row - view.row;
-                        } else {
-                            view.y = 0;
-                        }
-                        view.redraw();
-                    }
-                    Key::Left => {
-                        view.editor.move_left();
-                        view.redraw();
-                    }
-                    Key::Right => {
-                        view.editor.move_right();
-                        view.redraw();
-                    }
-                    Key::Up => {
-                        view.editor.move_up();
-                        {
-                            let main_caret = view.editor
-                                                 .carets
-                                                 .get(0)
-                                                 .expect("Caret not found!");
-                            if main_caret.row < view.y {
-                                view.y -= 1;
-                            }
-                        }
-                        view.redraw();
-                    }
-                    Key::Down => {
-                        view.editor.move_down();
-                        {
-                            let main_caret = view.editor
-                                                 .carets
-                                                 .get(0)
-                                                 .expect("Caret not found!");
-                            if main_caret.row >= view.y + view.row {
-                                view.y += 1;
-                            }
-                        }
-                        view.redraw();
-                    }
-                    Key::Esc => {
-                        break;
-                    }
-                    Key::Char(c) => {
-                        view.editor.insert_char(c);
-                        view.redraw();
-                    }
-                    _ => {}
-                }
+        let idle = view.idle_timeout();
+        if let Some(event) = view.terminal.poll_event(Some(idle)) {
+            if !view.handle_event(event) {
+                break;
             }
-            Ok(rustbox::Event::MouseEvent(mouse, x, y)) => {
-                let main_caret = view.editor
-                                     .carets
-                                     .get_mut(0)
-                                     .expect("Caret not found!");
-                main_caret.col = x as usize;
-                main_caret.row = y as usize + view.row;
-            }
-            _ => {}
         }
-        view.redraw_infobar();
+        view.update_lsp();
+        view.update_autosave();
+        view.update_swap();
+        view.refresh();
         // view.draw_caret();
         view.flush();
     }
+    view.remove_swaps();
+    let states = view.buffer_states();
+    let mut errors = Vec::new();
+    if !states.is_empty() {
+        if let Some(file) = positions_file {
+            for state in states.iter().rev() {
+                positions.remember(state.clone());
+            }
+            errors.extend(positions.save(&file).err());
+        }
+        if let Some(file) = session_file {
+            errors.extend(Session { buffers: states }.save(&file).err());
+        }
+    }
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use frontend::{MemoryFrontend, parse_keys};
+    use lsp::Severity;
+
+    /// A 40x10 view on the text, with the caret at the top, and its screen.
+    fn screen_with(text: &str) -> (EditorView, MemoryFrontend) {
+        let screen = MemoryFrontend::new(40, 10);
+        let mut view = EditorView::new(Box::new(screen.clone()));
+        view.editor.set_text(text);
+        view.redraw();
+        (view, screen)
+    }
+
+    /// Feeds the key script through the same dispatch as the event loop,
+    /// stopping at a key which quits. Prompts read their answers from the
+    /// script too.
+    fn play(view: &mut EditorView, screen: &MemoryFrontend, script: &str) -> bool {
+        for key in parse_keys(script).expect("Bad key script!") {
+            screen.push_event(Event::Key(key));
+        }
+        while let Some(event) = view.terminal.poll_event(None) {
+            if event == Event::Closed {
+                break;
+            }
+            if !view.handle_event(event) {
+                return false;
+            }
+            view.refresh();
+        }
+        true
+    }
+
+    fn run(text: &str, script: &str) -> EditorView {
+        let (mut view, screen) = screen_with(text);
+        play(&mut view, &screen, script);
+        view
+    }
+
+    fn caret(view: &EditorView) -> (usize, usize) {
+        let caret = view.editor.carets.first().unwrap();
+        (caret.row, caret.col)
+    }
+
+    fn assert_state(view: &EditorView, text: &str, pos: (usize, usize)) {
+        assert_eq!(view.editor.get_all(), text);
+        assert_eq!(caret(view), pos);
+    }
+
+    #[test]
+    fn typing_test() {
+        assert_state(&run("", "abc<Left><Enter><BS>"), "abc", (0, 2));
+        assert_state(&run("", "ab<Enter>cd<Enter><Enter>e"), "ab\ncd\n\ne", (3, 1));
+        assert_state(&run("xy", "<Right>a<lt>>"), "xa<>y", (0, 4));
+        assert_state(&run("", "<space><Tab>"), " ", (0, 1));
+    }
+
+    #[test]
+    fn empty_buffer_test() {
+        let script = "<Left><Right><Up><Down><Home><End><PageUp><PageDown><C-Left><C-Right>\
+                      <C-Up><C-Down><C-a><C-e>";
+        assert_state(&run("", script), "", (0, 0));
+        assert_state(&run("", "<BS><Del><C-w><C-u><C-k><C-Del>"), "", (0, 0));
+        assert_state(&run("", "<C-d>"), "", (0, 0));
+        assert_state(&run("", "<S-Left><S-Right><S-Up><S-Down>"), "", (0, 0));
+        assert_state(&run("", "<C-z><C-y>"), "", (0, 0));
+        assert_eq!(run("", "").editor.len(), 1);
+    }
+
+    #[test]
+    fn horizontal_motion_test() {
+        assert_state(&run("ab\ncd", "<Right><Right>"), "ab\ncd", (0, 2));
+        // Moving right past the end of a line wraps to the next one.
+        assert_state(&run("ab\ncd", "<Right><Right><Right>"), "ab\ncd", (1, 0));
+        // Moving left from column 0 wraps to the end of the previous line.
+        assert_state(&run("ab\ncd", "<Down><Left><Left>"), "ab\ncd", (0, 1));
+        // Neither wraps past the ends of the buffer.
+        assert_state(&run("ab\ncd", "<Left>"), "ab\ncd", (0, 0));
+        assert_state(&run("ab\ncd", "<End><Right><Right>"), "ab\ncd", (1, 2));
+    }
+
+    #[test]
+    fn vertical_motion_test() {
+        // Up on the first line goes to its start, down on the last to its end.
+        assert_state(&run("abc\nde", "<Right><Right><Up>"), "abc\nde", (0, 0));
+        assert_state(&run("abc\nde", "<Down><Down>"), "abc\nde", (1, 2));
+        // The column is clamped to shorter lines and restored on longer ones.
+        assert_state(&run("abcd\nx\nabcd", "<C-e><Down>"), "abcd\nx\nabcd", (1, 1));
+        assert_state(&run("abcd\nx\nabcd", "<C-e><Down><Down>"), "abcd\nx\nabcd", (2, 4));
+        assert_state(&run("abcd\n\nabcd", "<Right><Right><Down><Down>"),
+                     "abcd\n\nabcd",
+                     (2, 2));
+        // A horizontal motion forgets the remembered column.
+        assert_state(&run("abcd\nx\nabcd", "<C-e><Down><Left><Right><Down>"),
+                     "abcd\nx\nabcd",
+                     (2, 1));
+    }
+
+    #[test]
+    fn buffer_motion_test() {
+        let text = "one\ntwo\nthree";
+        assert_state(&run(text, "<End>"), text, (2, 5));
+        assert_state(&run(text, "<End><Home>"), text, (0, 0));
+        assert_state(&run(text, "<PageDown>"), text, (2, 5));
+        assert_state(&run(text, "<End><PageUp>"), text, (0, 0));
+        let long: Vec<String> = (0..30).map(|idx| idx.to_string()).collect();
+        let long = long.join("\n");
+        // The view has nine text rows, so a page is eight lines.
+        assert_state(&run(&long, "<PageDown>"), &long, (8, 0));
+        assert_state(&run(&long, "<PageDown><PageDown><PageUp>"), &long, (8, 0));
+    }
+
+    #[test]
+    fn line_motion_test() {
+        let text = "  foo bar";
+        assert_state(&run(text, "<C-e>"), text, (0, 9));
+        assert_state(&run(text, "<C-e><C-a>"), text, (0, 2));
+        assert_state(&run(text, "<C-e><C-a><C-a>"), text, (0, 0));
+        assert_state(&run(text, "<C-a>"), text, (0, 2));
+        assert_state(&run(text, "<C-Right><C-Right>"), text, (0, 6));
+        assert_state(&run(text, "<C-e><C-Left>"), text, (0, 6));
+        // Blank lines next to the caret are skipped along with the paragraph.
+        assert_state(&run("a\n\nb\nc\n\nd", "<C-Down>"), "a\n\nb\nc\n\nd", (4, 0));
+        assert_state(&run("a\n\nb\nc\n\nd", "<C-Down><C-Down>"), "a\n\nb\nc\n\nd", (5, 1));
+        assert_state(&run("a\n\nb\nc\n\nd", "<End><C-Up>"), "a\n\nb\nc\n\nd", (1, 0));
+    }
+
+    #[test]
+    fn insert_test() {
+        assert_state(&run("ac", "<Right>b"), "abc", (0, 2));
+        // Enter splits the line at the caret.
+        assert_state(&run("abcd", "<Right><Right><Enter>"), "ab\ncd", (1, 0));
+        assert_state(&run("abcd", "<Enter>"), "\nabcd", (1, 0));
+        // Enter at the end of the last line appends an empty line.
+        assert_state(&run("ab\ncd", "<End><Enter>"), "ab\ncd\n", (2, 0));
+        assert_state(&run("ab\ncd", "<End><Enter>x"), "ab\ncd\nx", (2, 1));
+        assert_state(&run("", "<Enter><Up>x"), "x\n", (0, 1));
+    }
+
+    #[test]
+    fn backspace_test() {
+        assert_state(&run("abc", "<C-e><BS>"), "ab", (0, 2));
+        assert_state(&run("abc", "<Right><BS>"), "bc", (0, 0));
+        // Backspace at the start of the buffer does nothing.
+        assert_state(&run("abc", "<BS>"), "abc", (0, 0));
+        // Backspace at the start of a line joins it to the previous one.
+        assert_state(&run("ab\ncd", "<Down><C-a><BS>"), "abcd", (0, 2));
+        assert_state(&run("ab\n\ncd", "<End><C-a><BS><BS>"), "abcd", (0, 2));
+        // Backspacing the last line away keeps the caret on the new last line.
+        assert_state(&run("ab\nc", "<End><BS><BS>"), "ab", (0, 2));
+    }
+
+    #[test]
+    fn delete_test() {
+        assert_state(&run("abc", "<Del>"), "bc", (0, 0));
+        assert_state(&run("ab\ncd", "<C-e><Del>"), "abcd", (0, 2));
+        // Delete at the end of the buffer does nothing.
+        assert_state(&run("ab\ncd", "<End><Del>"), "ab\ncd", (1, 2));
+        assert_state(&run("foo bar", "<C-e><C-w>"), "foo ", (0, 4));
+        assert_state(&run("foo bar", "<C-Del>"), "bar", (0, 0));
+        assert_state(&run("foo bar", "<C-Right><C-u>"), "bar", (0, 0));
+        assert_state(&run("foo\nbar", "<C-k>"), "\nbar", (0, 0));
+        assert_state(&run("foo\nbar", "<C-k><C-k>"), "bar", (0, 0));
+        assert_state(&run("foo\nbar\nbaz", "<Down><C-d>"), "foo\nbaz", (1, 0));
+        assert_state(&run("foo\nbar", "<End><C-d>"), "foo", (0, 3));
+    }
+
+    #[test]
+    fn selection_test() {
+        assert_state(&run("foo bar", "<S-Right><S-Right>x"), "xo bar", (0, 1));
+        assert_state(&run("ab\ncd", "<Right><S-Down><BS>"), "ad", (0, 1));
+        // A plain motion drops the selection.
+        let view = run("foo", "<S-Right><Right>");
+        assert_eq!(view.editor.selection(0), None);
+        assert_state(&view, "foo", (0, 1));
+    }
+
+    #[test]
+    fn undo_test() {
+        assert_state(&run("", "ab<Enter>c<C-z>"), "ab\n", (1, 0));
+        assert_state(&run("abc", "<C-e><BS><BS><C-z><C-z>"), "abc", (0, 3));
+        assert_state(&run("abc", "<C-e><BS><C-z><C-y>"), "ab", (0, 2));
+    }
+
+    #[test]
+    fn line_command_test() {
+        assert_state(&run("a\nb", "<A-d>"), "a\na\nb", (1, 0));
+        assert_state(&run("a\nb", "<A-Down>"), "b\na", (1, 0));
+        assert_state(&run("a\nb", "<A-j>"), "a b", (0, 0));
+        assert_state(&run("b\nc\na", "<A-s>"), "a\nb\nc", (0, 0));
+    }
+
+    #[test]
+    fn quit_test() {
+        let (mut view, screen) = screen_with("abc");
+        // Esc only drops the selection.
+        assert!(play(&mut view, &screen, "<S-Right><Esc>"));
+        assert_eq!(view.editor.selection(0), None);
+        assert!(!view.editor.is_modified());
+        assert!(!play(&mut view, &screen, "<C-q>"));
+        assert!(play(&mut view, &screen, "x<C-q>c"));
+        assert!(view.editor.is_modified());
+        assert!(play(&mut view, &screen, "<C-q><Esc>"));
+        assert!(play(&mut view, &screen, "<C-q>zc"));
+        assert!(!play(&mut view, &screen, "<C-q>n"));
+        assert_state(&view, "xabc", (0, 1));
+    }
+
+    #[test]
+    fn save_test() {
+        let path = env::temp_dir().join(format!("mal-save-test-{}", std::process::id()));
+        let (mut view, screen) = screen_with("abc");
+        // A new buffer asks for a file name; an empty one cancels.
+        assert!(play(&mut view, &screen, "x<C-s><CR>"));
+        assert!(view.editor.is_modified());
+        let script = format!("<C-q>y{}<CR>", path.display());
+        assert!(!play(&mut view, &screen, &script));
+        assert!(!view.editor.is_modified());
+        assert_eq!(view.editor.path(), Some(path.as_path()));
+        let mut saved = Editor::new();
+        saved.read_file(&path).unwrap();
+        assert_eq!(saved.get_all(), "xabc");
+        // Undo counts as a change too.
+        assert!(play(&mut view, &screen, "<C-z>"));
+        assert!(view.editor.is_modified());
+        assert!(play(&mut view, &screen, "<C-s>"));
+        assert!(!play(&mut view, &screen, "<C-q>"));
+        saved.read_file(&path).unwrap();
+        assert_eq!(saved.get_all(), "abc");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn scroll_test() {
+        let long: Vec<String> = (0..30).map(|idx| idx.to_string()).collect();
+        let view = run(&long.join("\n"), "<Down><Down><Down><Down><Down><Down><Down><Down><Down>");
+        assert_eq!(caret(&view), (9, 0));
+        assert_eq!(view.y, 1);
+        let view = run(&long.join("\n"), "<End><Up>");
+        assert_eq!(view.y, 28);
+        assert_eq!(caret(&view), (28, 2));
+    }
+
+    #[test]
+    fn render_test() {
+        let (mut view, screen) = screen_with("");
+        assert_eq!(screen.line(9), " Untitled NORMAL text utf-8 LF 1:1 100%");
+        play(&mut view, &screen, "ab<Enter>cd<Left>");
+        assert_eq!(screen.line(0), "1 ab");
+        assert_eq!(screen.line(1), "2 cd");
+        assert_eq!(screen.line(2), "");
+        assert_eq!(screen.cell(3, 1).style, Style::Reverse);
+        assert_eq!(screen.cell(2, 1).style, Style::Normal);
+        // The file name is shortened to make room for the modified flag.
+        assert_eq!(screen.line(9), " …led [+] NORMAL text utf-8 LF 2:2 100%");
+    }
+
+    #[test]
+    fn gutter_test() {
+        let (mut view, screen) = screen_with("a\nb\nc");
+        play(&mut view, &screen, "<Down>");
+        view.line_numbers = LineNumbers::Relative;
+        view.refresh();
+        assert_eq!(screen.line(0), "1 a");
+        assert_eq!(screen.line(1), "0 b");
+        play(&mut view, &screen, "<Down>");
+        assert_eq!(screen.line(0), "2 a");
+        assert_eq!(screen.line(2), "0 c");
+        play(&mut view, &screen, "<F2>");
+        assert_eq!(view.line_numbers, LineNumbers::Hybrid);
+        assert_eq!(screen.line(1), "1 b");
+        assert_eq!(screen.line(2), "3 c");
+        play(&mut view, &screen, "<F2>");
+        assert_eq!(view.line_numbers, LineNumbers::Off);
+        assert_eq!(screen.line(0), "a");
+        view.sign_column = true;
+        view.set_sign(1, '+', Color::Green);
+        view.refresh();
+        assert_eq!(screen.line(0), " a");
+        assert_eq!(screen.line(1), "+b");
+        view.clear_signs();
+        view.refresh();
+        assert_eq!(screen.line(1), " b");
+        play(&mut view, &screen, "<F2>");
+        assert_eq!(screen.line(2), " 3 c");
+        view.handle_mouse(Mouse::Left, 3, 0);
+        assert_eq!(caret(&view), (0, 0));
+        view.handle_mouse(Mouse::WheelDown, 0, 0);
+        view.refresh();
+        assert_eq!(view.y, 2);
+        assert_eq!(screen.line(0), " 3 c");
+        assert_eq!(screen.line(1), "");
+    }
+
+    #[test]
+    fn diagnostic_text_test() {
+        let (mut view, screen) = screen_with("\tx\n😀\ny");
+        let diagnostic = |row: usize, message: &str| {
+            Diagnostic {
+                row,
+                severity: Severity::Error,
+                message: String::from(message),
+            }
+        };
+        view.diagnostics.insert(0, diagnostic(0, "oops"));
+        view.diagnostics.insert(1, diagnostic(1, "bad"));
+        view.diagnostics.insert(2, diagnostic(2, &"0123456789".repeat(4)));
+        for row in 0..3 {
+            view.redraw_line(row);
+        }
+        // The text starts two columns after the line, as the line is drawn.
+        assert_eq!(screen.line(0), "1 \tx  oops");
+        assert_eq!(screen.line(1), "2 😀  bad");
+        assert_eq!(screen.line(2), format!("3 y  {}", &"0123456789".repeat(4)[..35]));
+        view.x = 3;
+        view.redraw_line(0);
+        view.redraw_line(1);
+        // Both scroll out of view together.
+        assert_eq!(screen.line(0), "1  oops");
+        assert_eq!(screen.line(1), "2 bad");
+        view.x = 5;
+        view.redraw_line(0);
+        assert_eq!(screen.line(0), "1 ops");
+    }
+
+    #[test]
+    fn gutter_width_test() {
+        let nine: Vec<String> = (0..9).map(|idx| idx.to_string()).collect();
+        let (mut view, screen) = screen_with(&nine.join("\n"));
+        assert_eq!(screen.line(0), "1 0");
+        // Every edit which adds a line widens the gutter, not only Enter.
+        play(&mut view, &screen, "<A-d>");
+        assert_eq!(view.lnum_pad, 2);
+        assert_eq!(screen.line(0), " 1 0");
+        play(&mut view, &screen, "<C-d>");
+        assert_eq!(view.lnum_pad, 1);
+        assert_eq!(screen.line(0), "1 0");
+        play(&mut view, &screen, "<End><Enter>");
+        assert_eq!(screen.line(0), " 9 8");
+        play(&mut view, &screen, "<C-z>");
+        assert_eq!(screen.line(0), "9 8");
+    }
+
+    #[test]
+    fn change_marker_test() {
+        let (mut view, screen) = screen_with("a\nb\nc");
+        view.editor.set_base(Some("a\nb\nc"));
+        view.sign_column = true;
+        view.redraw();
+        assert_eq!(screen.line(0), " 1 a");
+        play(&mut view, &screen, "<Down>x<Down><C-e><Enter>d");
+        assert_eq!(screen.line(1), "~2 xb");
+        assert_eq!(screen.line(3), "+4 d");
+        play(&mut view, &screen, "<Up><Up><C-d>");
+        assert_eq!(screen.line(0), "_1 a");
+        assert_eq!(screen.line(1), " 2 c");
+        play(&mut view, &screen, "<A-h>");
+        assert_eq!(screen.line(1), " 2 b");
+        assert_eq!(view.editor.get_all(), "a\nb\nc\nd");
+    }
+
+    #[test]
+    fn swap_test() {
+        let path = env::temp_dir().join(format!("mal-swap-test-{}", std::process::id()));
+        let swap_file = swap::swap_path(Some(&path));
+        fs::write(&path, "abc\n").unwrap();
+        let (mut view, screen) = screen_with("");
+        view.editor.read_file(&path).unwrap();
+        play(&mut view, &screen, "<C-e>d");
+        view.update_swap();
+        assert!(swap_file.exists());
+        // Further changes wait for the interval.
+        play(&mut view, &screen, "e");
+        view.update_swap();
+        assert_eq!(view.swapped_version + 1, view.editor.version());
+
+        let (mut other, other_screen) = screen_with("");
+        other.editor.read_file(&path).unwrap();
+        other_screen.push_event(Event::Key(Key::Char('r')));
+        other.recover_swap();
+        assert_eq!(other.editor.get_all(), "abcd");
+        assert!(other.editor.is_modified());
+        play(&mut other, &other_screen, "<C-z>");
+        assert_eq!(other.editor.get_all(), "abc");
+
+        let (mut other, other_screen) = screen_with("");
+        other.editor.read_file(&path).unwrap();
+        other_screen.push_event(Event::Key(Key::Char('d')));
+        other.recover_swap();
+        other.redraw();
+        assert_eq!(other.signs.get(&0), Some(&('~', Color::Yellow)));
+        play(&mut other, &other_screen, "<A-h>");
+        assert_eq!(other.editor.get_all(), "abc");
+
+        let (mut other, other_screen) = screen_with("");
+        other.editor.read_file(&path).unwrap();
+        other_screen.push_event(Event::Key(Key::Char('i')));
+        other.recover_swap();
+        assert_eq!(other.message, Some(String::from(SWAP_KEPT)));
+        let kept = fs::read_to_string(&swap_file).unwrap();
+        play(&mut other, &other_screen, "x");
+        other.update_swap();
+        assert_eq!(fs::read_to_string(&swap_file).unwrap(), kept);
+        fs::write(&swap_file, "broken").unwrap();
+        let (mut other, other_screen) = screen_with("");
+        other.editor.read_file(&path).unwrap();
+        other_screen.push_event(Event::Key(Key::Char('r')));
+        other_screen.push_event(Event::Key(Key::Char(' ')));
+        other.recover_swap();
+        other.update_swap();
+        assert_eq!(fs::read_to_string(&swap_file).unwrap(), "broken");
+        fs::write(&swap_file, kept).unwrap();
+
+        let (mut other, other_screen) = screen_with("");
+        other.editor.read_file(&path).unwrap();
+        other_screen.push_event(Event::Key(Key::Char('x')));
+        other.recover_swap();
+        assert!(!swap_file.exists());
+        assert_eq!(other.editor.get_all(), "abc");
+
+        // Saving removes the swap file.
+        view.swapped_at = Instant::now() - Duration::from_millis(SWAP_INTERVAL_MS);
+        view.update_swap();
+        assert!(swap_file.exists());
+        play(&mut view, &screen, "<C-s>");
+        view.update_swap();
+        assert!(!swap_file.exists());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn autosave_test() {
+        let path = env::temp_dir().join(format!("mal-autosave-test-{}", std::process::id()));
+        let side = swap::autosave_path(&path);
+        fs::write(&path, "abc\n").unwrap();
+        let (mut view, screen) = screen_with("");
+        view.editor.read_file(&path).unwrap();
+        view.autosave = Autosave::Side;
+        play(&mut view, &screen, "x");
+        // Nothing happens until the editor has been idle long enough.
+        view.update_autosave();
+        assert!(!side.exists());
+        view.last_input = Instant::now() - view.autosave_idle;
+        view.update_autosave();
+        assert_eq!(fs::read_to_string(&side).unwrap(), "xabc\n");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "abc\n");
+        assert!(view.editor.is_modified());
+        view.refresh();
+        assert!(screen.line(9).starts_with("Autosaved to "));
+        // The next key brings the status back.
+        play(&mut view, &screen, "<Right>");
+        assert!(!screen.line(9).starts_with("Autosaved"));
+
+        view.autosave = Autosave::File;
+        play(&mut view, &screen, "y");
+        // Losing focus saves right away.
+        assert!(view.handle_event(Event::FocusLost));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "xaybc\n");
+        assert!(!view.editor.is_modified());
+        for file in [&path, &side].iter() {
+            fs::remove_file(file).unwrap();
+        }
+    }
+
+    #[test]
+    fn autosave_hidden_test() {
+        let paths: Vec<PathBuf> = (0..2).map(|idx| {
+                                            env::temp_dir().join(format!("mal-autosave-hidden-{}-{}",
+                                                                         idx,
+                                                                         std::process::id()))
+                                        })
+                                        .collect();
+        let (mut view, screen) = screen_with("");
+        for (idx, path) in paths.iter().enumerate() {
+            fs::write(path, format!("file {}\n", idx)).unwrap();
+            let mut editor = Editor::new();
+            editor.read_file(path).unwrap();
+            if idx == 0 {
+                view.editor = editor;
+            } else {
+                view.hidden.push_back(Hidden::new(editor, idx));
+            }
+        }
+        view.autosave = Autosave::Side;
+        // A buffer put away before the idle interval is still autosaved.
+        play(&mut view, &screen, "x<C-PageDown>");
+        view.last_input = Instant::now() - view.autosave_idle;
+        view.update_autosave();
+        assert_eq!(fs::read_to_string(swap::autosave_path(&paths[0])).unwrap(), "xfile 0\n");
+
+        // Losing focus saves every modified buffer, not only the one shown.
+        view.autosave = Autosave::File;
+        play(&mut view, &screen, "y<C-PageDown>z");
+        assert!(view.handle_event(Event::FocusLost));
+        assert_eq!(fs::read_to_string(&paths[0]).unwrap(), "xzfile 0\n");
+        assert_eq!(fs::read_to_string(&paths[1]).unwrap(), "yfile 1\n");
+        assert_eq!(view.message, Some(String::from("Autosaved 2 buffers")));
+        view.remove_swaps();
+        for path in paths.iter() {
+            fs::remove_file(path).unwrap();
+            fs::remove_file(swap::autosave_path(path)).ok();
+        }
+    }
+
+    #[test]
+    fn disk_change_test() {
+        let path = env::temp_dir().join(format!("mal-disk-change-test-{}", std::process::id()));
+        fs::write(&path, "abc\ndef\n").unwrap();
+        let (mut view, screen) = screen_with("");
+        view.editor.read_file(&path).unwrap();
+        play(&mut view, &screen, "<Down><End>");
+        // An unmodified buffer follows the file.
+        fs::write(&path, "abc\nde\nghi\n").unwrap();
+        assert!(view.handle_event(Event::FocusGained));
+        assert_state(&view, "abc\nde\nghi", (1, 2));
+        assert_eq!(view.message, Some(format!("Reloaded {}", path.display())));
+
+        // Saving over someone else's changes asks first.
+        play(&mut view, &screen, "x");
+        fs::write(&path, "new\n").unwrap();
+        assert!(play(&mut view, &screen, "<C-s><Esc>"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new\n");
+        play(&mut view, &screen, "<C-s>d");
+        assert!(view.editor.is_modified());
+        view.redraw();
+        assert_eq!(view.signs.get(&0), Some(&('~', Color::Yellow)));
+        // Once seen, the change is not reported again.
+        play(&mut view, &screen, "<C-s>");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "abc\ndex\nghi\n");
+
+        fs::write(&path, "other\n").unwrap();
+        play(&mut view, &screen, "y<C-s>k");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "abc\ndexy\nghi\n");
+        fs::write(&path, "other\n").unwrap();
+        play(&mut view, &screen, "z<C-s>r");
+        assert_state(&view, "other", (0, 5));
+        assert!(!view.editor.is_modified());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn session_test() {
+        let path = env::temp_dir().join(format!("mal-session-test-{}", std::process::id()));
+        let long: Vec<String> = (0..30).map(|idx| idx.to_string()).collect();
+        fs::write(&path, long.join("\n")).unwrap();
+        let (mut view, screen) = screen_with("");
+        assert_eq!(view.buffer_state(), None);
+        view.editor.read_file(&path).unwrap();
+        play(&mut view, &screen, "<End><Up><Left>");
+        let state = view.buffer_state().unwrap();
+        assert_eq!(state.path, session::absolute(&path));
+        let session = Session { buffers: vec![state] };
+        let session = Session::parse(&session.to_text()).unwrap();
+
+        let (mut other, other_screen) = screen_with("");
+        other.editor.read_file(&path).unwrap();
+        other.restore_state(&session.buffers[0]);
+        other.redraw();
+        assert_eq!((caret(&other), other.y), ((28, 1), 28));
+        assert_eq!(other_screen.line(0), screen.line(0));
+        // Positions past the end of a file which shrank stay inside it.
+        other.editor.set_text("a\nb");
+        other.restore_state(&session.buffers[0]);
+        assert_eq!((caret(&other), other.y), ((1, 1), 1));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn buffers_test() {
+        let paths: Vec<PathBuf> = (0..3)
+                                      .map(|idx| {
+                                          env::temp_dir().join(format!("mal-buffers-test-{}-{}",
+                                                                       std::process::id(),
+                                                                       idx))
+                                      })
+                                      .collect();
+        let (mut view, screen) = screen_with("");
+        for (idx, path) in paths.iter().enumerate() {
+            fs::write(path, format!("file {}\n", idx)).unwrap();
+            let mut editor = Editor::new();
+            editor.read_file(path).unwrap();
+            if idx == 0 {
+                view.editor = editor;
+            } else {
+                view.hidden.push_back(Hidden::new(editor, idx));
+            }
+        }
+        view.redraw();
+        assert!(view.format_info().starts_with(" [1/3] "));
+        play(&mut view, &screen, "<End>x<C-PageDown>");
+        assert_eq!(view.editor.get_all(), "file 1");
+        assert!(screen.line(0).contains("file 1"));
+        play(&mut view, &screen, "<C-PageUp><C-PageUp>");
+        assert_eq!(view.editor.get_all(), "file 2");
+        assert!(view.format_info().starts_with(" [3/3] "));
+        play(&mut view, &screen, "<C-PageDown>");
+        assert_state(&view, "file 0x", (0, 7));
+        assert_eq!(view.buffer_states().len(), 3);
+        assert_eq!(view.buffer_states()[0].path, session::absolute(&paths[0]));
+
+        // Quitting asks about each modified buffer, wherever it is.
+        play(&mut view, &screen, "<C-PageDown>y");
+        assert!(play(&mut view, &screen, "<C-q>c"));
+        assert_eq!(view.editor.get_all(), "yfile 1");
+        assert!(play(&mut view, &screen, "<C-q>nc"));
+        assert_eq!(view.editor.get_all(), "file 0x");
+        assert_eq!(fs::read_to_string(&paths[0]).unwrap(), "file 0\n");
+        assert!(!play(&mut view, &screen, "<C-q>yy"));
+        assert_eq!(fs::read_to_string(&paths[0]).unwrap(), "file 0x\n");
+        assert_eq!(fs::read_to_string(&paths[1]).unwrap(), "yfile 1\n");
+        view.remove_swaps();
+        for path in paths.iter() {
+            assert!(!swap::swap_path(Some(path)).exists());
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn pipe_test() {
+        let (mut view, screen) = screen_with("abc");
+        view.pipe = true;
+        assert!(!play(&mut view, &screen, "x<C-q>"));
+        let mut out = Vec::new();
+        view.editor.write_to(&mut out).unwrap();
+        assert_eq!(out, b"xabc".to_vec());
+        view.pipe = false;
+        assert!(play(&mut view, &screen, "<C-q>c"));
+    }
+
+    #[test]
+    fn wheel_test() {
+        let lines: Vec<String> = (0..30).map(|idx| format!("line {}", idx)).collect();
+        let (mut view, screen) = screen_with(&lines.join("\n"));
+        play(&mut view, &screen, "<Right><Right>");
+        for _ in 0..4 {
+            view.handle_mouse(Mouse::WheelDown, 0, 0);
+        }
+        assert_eq!(view.y, 12);
+        assert_eq!(caret(&view), (12, 2));
+        play(&mut view, &screen, "<Down>");
+        assert_eq!((view.y, caret(&view)), (12, (13, 2)));
+        view.handle_mouse(Mouse::WheelUp, 0, 0);
+        view.handle_mouse(Mouse::WheelUp, 0, 0);
+        assert_eq!(view.y, 6);
+        assert_eq!(caret(&view), (13, 2));
+        for _ in 0..3 {
+            view.handle_mouse(Mouse::WheelUp, 0, 0);
+        }
+        assert_eq!((view.y, caret(&view)), (0, (8, 2)));
+    }
+
+    #[test]
+    fn completion_test() {
+        let (mut view, screen) = screen_with("forever format\nforge");
+        play(&mut view, &screen, "<Down><C-e><CR>fo<C-n>");
+        assert_eq!(screen.line(3), "   forge");
+        assert_eq!(screen.line(4), "   forever");
+        assert_eq!(screen.line(5), "   format");
+        play(&mut view, &screen, "<Down><CR>");
+        assert_state(&view, "forever format\nforge\nforever", (2, 7));
+        assert_eq!(screen.line(3), "");
+
+        // Typing a long enough prefix opens it, and typing on narrows it.
+        view.complete_after = 3;
+        play(&mut view, &screen, "<CR>fo");
+        assert!(view.completion.is_none());
+        play(&mut view, &screen, "rm");
+        assert_eq!(screen.line(4), "   format");
+        assert_eq!(screen.line(5), "");
+        play(&mut view, &screen, "<Esc><CR>");
+        assert_state(&view, "forever format\nforge\nforever\nform\n", (4, 0));
+        assert_eq!(screen.line(5), "");
+        play(&mut view, &screen, "qu<C-n>");
+        assert_eq!(view.message, Some(String::from("No completions")));
+    }
+
+    #[test]
+    fn scripts_test() {
+        let (mut view, screen) = screen_with("abc");
+        let script = r#"
+            command("wrap", || { move_line_start(); insert("("); move_line_end(); insert(")"); });
+            command("fail", || undefined_function());
+            bind("<C-t>", "wrap");
+        "#;
+        assert_eq!(view.scripts.load_text(script), Ok(()));
+        assert!(play(&mut view, &screen, "<C-t>"));
+        assert_state(&view, "(abc)", (0, 5));
+        assert!(play(&mut view, &screen, "<A-x>fail<CR>"));
+        assert!(view.message.as_ref().unwrap().starts_with("Script error: "));
+        // Undoing takes back the whole command one insert at a time.
+        assert!(play(&mut view, &screen, "<C-z><C-z>"));
+        assert_state(&view, "abc", (0, 0));
+        assert_eq!(view.message, None);
+    }
+
+    #[test]
+    fn dump_buffers_test() {
+        let path = env::temp_dir().join(format!("mal-dump-test-{}", std::process::id()));
+        fs::write(&path, "abc\r\n").unwrap();
+        let (mut view, screen) = screen_with("");
+        view.editor.read_file(&path).unwrap();
+        assert!(view.dump_buffers().is_empty());
+        play(&mut view, &screen, "x");
+        let dumps = view.dump_buffers();
+        assert_eq!(dumps, vec![swap::recovery_paths(Some(&path))[0].clone()]);
+        // With the file's line endings, as a save would write it.
+        assert_eq!(fs::read_to_string(&dumps[0]).unwrap(), "xabc\r\n");
+        // The swap file keeps the undo history for the next session.
+        let swap_file = swap::swap_path(Some(&path));
+        assert!(swap_file.exists());
+        for file in [&path, &dumps[0], &swap_file].iter() {
+            fs::remove_file(file).unwrap();
+        }
+    }
 }
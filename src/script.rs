@@ -0,0 +1,385 @@
+use std::cell::RefCell;
+use std::cmp;
+use std::fs;
+use std::mem;
+use std::path::Path;
+use std::rc::Rc;
+
+use rhai::{Array, Dynamic, Engine, FnPtr, Map, AST};
+
+use backend::{Editor, EditType, HookEvent, Hooks};
+use frontend::{Key, parse_keys};
+
+/// Events scripts can handle with `on`.
+/// Most operations one call into the scripts may take, and how deep their
+/// functions may nest, so a runaway script fails rather than hangs the
+/// editor.
+const MAX_OPERATIONS: u64 = 1_000_000;
+const MAX_CALL_LEVELS: usize = 64;
+
+const EVENTS: &[&str] = &["open", "change", "before_save", "save", "mode", "key"];
+
+/// What the functions scripts call work on. The buffer shown is lent to it for
+/// the length of each call into a script.
+struct State {
+    editor: Editor,
+    /// Note for the infobar left by `message`.
+    message: Option<String>,
+    commands: Vec<(String, FnPtr)>,
+    bindings: Vec<(Key, String)>,
+    handlers: Vec<(String, FnPtr)>,
+}
+
+/// User extensions written in Rhai. Scripts move the carets and edit the
+/// buffer through functions like `insert(text)`, `line(row)` or `find(text)`,
+/// and hook into the editor with:
+///
+/// * `command(name, fn)` defines a command, run with `Alt-x`
+/// * `bind(key, name)` runs the command on the key, written as for key
+///   scripts, like `"<C-t>"`
+/// * `on(event, fn)` calls the function on the buffer's events once attached
+///   to its hooks: with the file name on `"open"`, `"before_save"` and
+///   `"save"`, with the range as `#{start: [row, col], end: [row, col], text,
+///   inserted}` on `"change"`, with `"normal"` or `"select"` on `"mode"` and
+///   with the key's name, like `"<C-s>"`, on `"key"`
+///
+/// Rows and columns count from 0. Clones share the scripts.
+#[derive(Clone)]
+pub struct Scripts {
+    engine: Rc<Engine>,
+    /// Every script loaded, merged, for the functions commands and handlers
+    /// call.
+    ast: Rc<RefCell<AST>>,
+    state: Rc<RefCell<State>>,
+}
+
+fn index(n: i64) -> usize {
+    cmp::max(n, 0) as usize
+}
+
+impl Scripts {
+    pub fn new() -> Scripts {
+        let state = Rc::new(RefCell::new(State {
+            editor: Editor::new(),
+            message: None,
+            commands: Vec::new(),
+            bindings: Vec::new(),
+            handlers: Vec::new(),
+        }));
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        engine.set_max_call_levels(MAX_CALL_LEVELS);
+        register_editing(&mut engine, &state);
+        register_hooks(&mut engine, &state);
+        Scripts {
+            engine: Rc::new(engine),
+            ast: Rc::new(RefCell::new(AST::empty())),
+            state,
+        }
+    }
+    /// Runs the script, which defines its commands, bindings and handlers.
+    pub fn load_text(&mut self, text: &str) -> Result<(), String> {
+        let ast = self.engine.compile(text).map_err(|why| why.to_string())?;
+        // Keep the functions even if running fails half way, as it may have
+        // registered some of them.
+        let result = self.engine.run_ast(&ast);
+        let merged = self.ast.borrow().merge(&ast);
+        *self.ast.borrow_mut() = merged;
+        result.map_err(|why| why.to_string())
+    }
+    pub fn load<P: AsRef<Path>>(&mut self, path: P) -> Result<(), String> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)
+                       .map_err(|why| format!("Couldn't read {}: {}", path.display(), why))?;
+        self.load_text(&text).map_err(|why| format!("{}: {}", path.display(), why))
+    }
+    /// Loads the `.rhai` files in the directory, in name order. Returns the
+    /// errors of the scripts which failed; the others are loaded all the same.
+    pub fn load_dir<P: AsRef<Path>>(&mut self, dir: P) -> Vec<String> {
+        let mut files: Vec<_> = match fs::read_dir(dir) {
+            Ok(entries) => {
+                entries.filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                       .filter(|path| path.extension().is_some_and(|ext| ext == "rhai"))
+                       .collect()
+            }
+            Err(_) => return Vec::new(),
+        };
+        files.sort();
+        files.iter().filter_map(|file| self.load(file).err()).collect()
+    }
+    /// Swaps the buffer with the one the scripts work on. Lend the buffer
+    /// shown before calling into the scripts, and take it back after.
+    pub fn lend(&self, editor: &mut Editor) {
+        mem::swap(&mut self.state.borrow_mut().editor, editor);
+    }
+    pub fn take_message(&self) -> Option<String> {
+        self.state.borrow_mut().message.take()
+    }
+    /// The command bound to the key, the latest binding winning.
+    pub fn binding(&self, key: Key) -> Option<String> {
+        self.state
+            .borrow()
+            .bindings
+            .iter()
+            .rev()
+            .find(|&&(bound, _)| bound == key)
+            .map(|(_, name)| name.clone())
+    }
+    pub fn run_command(&self, name: &str) -> Result<(), String> {
+        let command = self.state
+                          .borrow()
+                          .commands
+                          .iter()
+                          .rev()
+                          .find(|(defined, _)| defined == name)
+                          .map(|(_, command)| command.clone());
+        match command {
+            Some(command) => self.call(&command, ()),
+            None => Err(format!("No command \"{}\"", name)),
+        }
+    }
+    /// Adds a hook which runs the handlers of each event, lending them the
+    /// buffer. Their errors are left as the message.
+    pub fn attach(&self, hooks: &Hooks) {
+        let scripts = self.clone();
+        hooks.add(move |editor, event| {
+            let (name, arg) = event_arg(editor, event);
+            let handlers: Vec<FnPtr> = scripts.state
+                                              .borrow()
+                                              .handlers
+                                              .iter()
+                                              .filter(|(event, _)| event == name)
+                                              .map(|(_, handler)| handler.clone())
+                                              .collect();
+            if handlers.is_empty() {
+                return;
+            }
+            scripts.lend(editor);
+            let result = handlers.iter()
+                                 .try_for_each(|handler| scripts.call(handler, (arg.clone(),)));
+            scripts.lend(editor);
+            if let Err(why) = result {
+                scripts.state.borrow_mut().message = Some(format!("Script error: {}", why));
+            }
+        });
+    }
+    fn call<A: rhai::FuncArgs>(&self, function: &FnPtr, args: A) -> Result<(), String> {
+        // The state must not stay borrowed here, as the function calls back
+        // into it.
+        function.call::<Dynamic>(&self.engine, &self.ast.borrow(), args)
+                .map(|_| ())
+                .map_err(|why| why.to_string())
+    }
+}
+
+/// The name scripts know the event by, and what their handlers are passed.
+fn event_arg(editor: &Editor, event: &HookEvent) -> (&'static str, Dynamic) {
+    let position = |(row, col): (usize, usize)| -> Dynamic {
+        Dynamic::from(vec![Dynamic::from(row as i64), Dynamic::from(col as i64)])
+    };
+    let filename = || Dynamic::from(String::from(editor.filename()));
+    match *event {
+        HookEvent::Opened => ("open", filename()),
+        HookEvent::Changed { start, end, ref text, inserted } => {
+            let mut range = Map::new();
+            range.insert("start".into(), position(start));
+            range.insert("end".into(), position(end));
+            range.insert("text".into(), Dynamic::from(text.clone()));
+            range.insert("inserted".into(), Dynamic::from(inserted));
+            ("change", Dynamic::from(range))
+        }
+        HookEvent::BeforeSave => ("before_save", filename()),
+        HookEvent::AfterSave => ("save", filename()),
+        HookEvent::ModeChanged(EditType::Normal) => ("mode", Dynamic::from(String::from("normal"))),
+        HookEvent::ModeChanged(EditType::Select) => ("mode", Dynamic::from(String::from("select"))),
+        HookEvent::KeyPressed(ref key) => ("key", Dynamic::from(key.clone())),
+    }
+}
+
+/// Registers the functions which read and change the buffer.
+fn register_editing(engine: &mut Engine, state: &Rc<RefCell<State>>) {
+    macro_rules! editor_fn {
+        ($name:expr, $method:ident) => {
+            let state = state.clone();
+            engine.register_fn($name, move || {
+                state.borrow_mut().editor.$method();
+            });
+        }
+    }
+    editor_fn!("move_left", move_left);
+    editor_fn!("move_right", move_right);
+    editor_fn!("move_up", move_up);
+    editor_fn!("move_down", move_down);
+    editor_fn!("move_word_left", move_word_left);
+    editor_fn!("move_word_right", move_word_right);
+    editor_fn!("move_line_start", move_line_start);
+    editor_fn!("move_line_end", move_line_end);
+    editor_fn!("move_top", move_top);
+    editor_fn!("move_end", move_end);
+    editor_fn!("backspace", backspace);
+    editor_fn!("delete", delete_forward);
+    editor_fn!("delete_line", delete_line);
+
+    let shared = state.clone();
+    engine.register_fn("move_to", move |row: i64, col: i64| {
+        shared.borrow_mut().editor.place_caret(index(row), index(col));
+    });
+    let shared = state.clone();
+    engine.register_fn("move_to", move |row: i64| {
+        shared.borrow_mut().editor.place_caret(index(row), 0);
+    });
+    let shared = state.clone();
+    engine.register_fn("caret", move || -> Array {
+        let state = shared.borrow();
+        let caret = state.editor.carets.first().expect("Caret not found!");
+        vec![Dynamic::from(caret.row as i64), Dynamic::from(caret.col as i64)]
+    });
+    let shared = state.clone();
+    engine.register_fn("insert", move |text: &str| {
+        shared.borrow_mut().editor.insert(String::from(text));
+    });
+    let shared = state.clone();
+    engine.register_fn("undo", move || shared.borrow_mut().editor.undo());
+    let shared = state.clone();
+    engine.register_fn("redo", move || shared.borrow_mut().editor.redo());
+    let shared = state.clone();
+    engine.register_fn("line", move |row: i64| -> String {
+        match shared.borrow().editor.get(index(row)) {
+            Some(line) => line.iter().collect(),
+            None => String::new(),
+        }
+    });
+    let shared = state.clone();
+    engine.register_fn("line_count", move || shared.borrow().editor.len() as i64);
+    let shared = state.clone();
+    engine.register_fn("text", move || shared.borrow().editor.get_all());
+    let shared = state.clone();
+    engine.register_fn("find", move |text: &str| shared.borrow_mut().editor.find(text));
+    let shared = state.clone();
+    engine.register_fn("replace_all", move |pattern: &str, with: &str| {
+        shared.borrow_mut().editor.replace_all(pattern, with) as i64
+    });
+    let shared = state.clone();
+    engine.register_fn("filename", move || String::from(shared.borrow().editor.filename()));
+}
+
+/// Registers the functions which extend the editor.
+fn register_hooks(engine: &mut Engine, state: &Rc<RefCell<State>>) {
+    let shared = state.clone();
+    engine.register_fn("message", move |text: &str| {
+        shared.borrow_mut().message = Some(String::from(text));
+    });
+    let shared = state.clone();
+    engine.register_fn("command", move |name: &str, function: FnPtr| {
+        shared.borrow_mut().commands.push((String::from(name), function));
+    });
+    let shared = state.clone();
+    engine.register_fn("bind",
+                       move |keys: &str, name: &str| -> Result<(), Box<rhai::EvalAltResult>> {
+        let key = match parse_keys(keys)?.as_slice() {
+            &[key] => key,
+            _ => return Err(format!("Expected one key, got \"{}\"", keys).into()),
+        };
+        shared.borrow_mut().bindings.push((key, String::from(name)));
+        Ok(())
+    });
+    let shared = state.clone();
+    engine.register_fn("on",
+                       move |event: &str, function: FnPtr| -> Result<(), Box<rhai::EvalAltResult>> {
+        if !EVENTS.contains(&event) {
+            return Err(format!("Unknown event \"{}\"", event).into());
+        }
+        shared.borrow_mut().handlers.push((String::from(event), function));
+        Ok(())
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scripts_test() {
+        let mut scripts = Scripts::new();
+        let script = r#"
+            fn shout() {
+                let row = caret()[0];
+                let upper = line(row);
+                upper.make_upper();
+                move_to(row);
+                move_line_end();
+                insert(" " + upper + "!");
+                message("shouted " + filename());
+            }
+            command("shout", Fn("shout"));
+            bind("<C-t>", "shout");
+        "#;
+        assert_eq!(scripts.load_text(script), Ok(()));
+        assert_eq!(scripts.binding(Key::Ctrl('t')), Some(String::from("shout")));
+        assert_eq!(scripts.binding(Key::Ctrl('x')), None);
+
+        let mut editor = Editor::new();
+        editor.set_text("one\ntwo");
+        editor.place_caret(1, 1);
+        scripts.lend(&mut editor);
+        assert_eq!(scripts.run_command("shout"), Ok(()));
+        assert!(scripts.run_command("whisper").is_err());
+        scripts.lend(&mut editor);
+        assert_eq!(editor.get_all(), "one\ntwo TWO!");
+        assert_eq!(scripts.take_message(), Some(String::from("shouted Untitled")));
+        assert_eq!(scripts.take_message(), None);
+
+        assert!(scripts.load_text("bind(\"<C-t><C-t>\", \"shout\");").is_err());
+        assert!(scripts.load_text("on(\"frobnicate\", || 1);").is_err());
+        assert!(scripts.load_text("let x = ;").is_err());
+    }
+
+    #[test]
+    fn runaway_test() {
+        let mut scripts = Scripts::new();
+        let script = r#"
+            fn spin() { loop { } }
+            fn dig(depth) { dig(depth + 1) }
+            command("spin", Fn("spin"));
+            command("dig", || dig(0));
+        "#;
+        assert_eq!(scripts.load_text(script), Ok(()));
+        let spun = scripts.run_command("spin").unwrap_err();
+        assert!(spun.contains("Too many operations"), "{}", spun);
+        assert!(scripts.run_command("dig").is_err());
+        assert!(scripts.load_text("while true { }").is_err());
+    }
+
+    #[test]
+    fn events_test() {
+        let mut scripts = Scripts::new();
+        let script = r#"
+            on("key", |key| message("pressed " + key));
+            on("change", |range| if range.inserted && range.text == "(" {
+                move_line_end();
+                insert(")");
+            });
+            on("mode", |mode| message(mode));
+            on("save", |name| undefined_function());
+        "#;
+        assert_eq!(scripts.load_text(script), Ok(()));
+        let hooks = Hooks::new();
+        scripts.attach(&hooks);
+        let mut editor = Editor::new();
+        editor.set_hooks(hooks);
+        editor.set_text("ab");
+
+        editor.notify(HookEvent::KeyPressed(String::from("<C-s>")));
+        editor.run_hooks();
+        assert_eq!(scripts.take_message(), Some(String::from("pressed <C-s>")));
+        editor.insert(String::from("("));
+        editor.run_hooks();
+        assert_eq!(editor.get_all(), "(ab)");
+        editor.mode_select();
+        editor.run_hooks();
+        assert_eq!(scripts.take_message(), Some(String::from("select")));
+        editor.notify(HookEvent::AfterSave);
+        editor.run_hooks();
+        assert!(scripts.take_message().unwrap().starts_with("Script error: "));
+    }
+}
@@ -0,0 +1,242 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const MAGIC: &str = "MAL_SESSION 1";
+/// Files the positions file remembers, the most recently closed first.
+const MAX_POSITIONS: usize = 500;
+/// Longest file name most file systems take, in bytes.
+const MAX_NAME: usize = 255;
+/// Bytes of a long directory name kept in front of its hash.
+const KEPT_NAME: usize = 200;
+
+/// Where a buffer was left: its file, carets and scroll offset.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BufferState {
+    pub path: PathBuf,
+    /// Row and column of each caret, the main caret first.
+    pub carets: Vec<(usize, usize)>,
+    pub x: usize,
+    pub y: usize,
+}
+
+/// Buffers saved in a session file, one per line as
+/// `buffer X Y CARETS (ROW COL)* PATH`. The positions file uses the same
+/// format to remember where each file was last left. The view has no split
+/// windows and no search prompt, so there is no layout or search history to
+/// keep beside the buffers.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Session {
+    pub buffers: Vec<BufferState>,
+}
+
+impl Session {
+    pub fn new() -> Session {
+        Session { buffers: Vec::new() }
+    }
+    pub fn parse(text: &str) -> Result<Session, String> {
+        let mut lines = text.lines();
+        if lines.next() != Some(MAGIC) {
+            return Err(String::from("Not a session file"));
+        }
+        let mut session = Session::new();
+        for (idx, line) in lines.enumerate() {
+            if line.is_empty() {
+                continue;
+            }
+            match parse_buffer(line) {
+                Some(buffer) => session.buffers.push(buffer),
+                None => return Err(format!("line {}: bad buffer", idx + 2)),
+            }
+        }
+        Ok(session)
+    }
+    /// Loads the session file, or an empty session if there is none yet.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Session, String> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(Session::new());
+        }
+        match fs::read_to_string(path) {
+            Ok(text) => Session::parse(&text).map_err(|why| format!("{}: {}", path.display(), why)),
+            Err(why) => Err(format!("Couldn't read {}: {}", path.display(), why)),
+        }
+    }
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            if !dir.as_os_str().is_empty() {
+                fs::create_dir_all(dir)
+                    .map_err(|why| format!("Couldn't create {}: {}", dir.display(), why))?;
+            }
+        }
+        fs::write(path, self.to_text())
+            .map_err(|why| format!("Couldn't write {}: {}", path.display(), why))
+    }
+    pub fn to_text(&self) -> String {
+        let mut text = format!("{}\n", MAGIC);
+        for buffer in self.buffers.iter() {
+            text.push_str(&format!("buffer {} {} {}", buffer.x, buffer.y, buffer.carets.len()));
+            for &(row, col) in buffer.carets.iter() {
+                text.push_str(&format!(" {} {}", row, col));
+            }
+            text.push_str(&format!(" {}\n", buffer.path.display()));
+        }
+        text
+    }
+    pub fn find(&self, path: &Path) -> Option<&BufferState> {
+        self.buffers.iter().find(|buffer| buffer.path == path)
+    }
+    /// Puts the buffer first, replacing an older entry for its file, and
+    /// forgets the oldest files beyond the limit.
+    pub fn remember(&mut self, buffer: BufferState) {
+        self.buffers.retain(|old| old.path != buffer.path);
+        self.buffers.insert(0, buffer);
+        self.buffers.truncate(MAX_POSITIONS);
+    }
+}
+
+fn parse_buffer(line: &str) -> Option<BufferState> {
+    let mut head = line.splitn(5, ' ');
+    if head.next() != Some("buffer") {
+        return None;
+    }
+    let mut numbers = || head.next().and_then(|word| word.parse::<usize>().ok());
+    let (x, y, count) = (numbers()?, numbers()?, numbers()?);
+    let mut rest = head.next()?.splitn(2 * count + 1, ' ');
+    let mut carets = Vec::new();
+    for _ in 0..count {
+        let row = rest.next()?.parse().ok()?;
+        let col = rest.next()?.parse().ok()?;
+        carets.push((row, col));
+    }
+    match rest.next() {
+        Some(path) if !path.is_empty() => {
+            Some(BufferState {
+                path: PathBuf::from(path),
+                carets,
+                x,
+                y,
+            })
+        }
+        _ => None,
+    }
+}
+
+/// `$XDG_DATA_HOME/mal`, or `~/.local/share/mal`.
+fn data_dir() -> Option<PathBuf> {
+    match env::var_os("XDG_DATA_HOME") {
+        Some(dir) => Some(PathBuf::from(dir)),
+        None => env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("share")),
+    }
+    .map(|dir| dir.join("mal"))
+}
+
+/// File of the named session. A name with a slash is a path of its own.
+pub fn session_path(name: &str) -> Option<PathBuf> {
+    if name.contains('/') {
+        Some(PathBuf::from(name))
+    } else {
+        data_dir().map(|dir| dir.join("sessions").join(name))
+    }
+}
+
+/// File of the session kept automatically for the working directory. The
+/// directory is percent-escaped into the name, so no two share a file. A
+/// name too long for the file system is cut short and ends in `%h` and a
+/// hash of the whole, which no escaped name does.
+pub fn directory_session_path(dir: &Path) -> Option<PathBuf> {
+    let mut name = format!("dir{}", dir.display().to_string().replace('%', "%25").replace('/', "%2F"));
+    if name.len() > MAX_NAME {
+        let hash = fnv1a(name.as_bytes());
+        let mut end = KEPT_NAME;
+        while !name.is_char_boundary(end) {
+            end -= 1;
+        }
+        name.truncate(end);
+        name.push_str(&format!("%h{:016x}", hash));
+    }
+    data_dir().map(|data| data.join("sessions").join(name))
+}
+
+/// 64-bit FNV-1a hash, which unlike the standard hasher stays the same from
+/// one Rust release to the next.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// File remembering where each file was last left.
+pub fn positions_path() -> Option<PathBuf> {
+    data_dir().map(|dir| dir.join("positions"))
+}
+
+/// The path made absolute, so it names the same file from any directory.
+pub fn absolute(path: &Path) -> PathBuf {
+    match fs::canonicalize(path) {
+        Ok(path) => path,
+        Err(_) => {
+            match env::current_dir() {
+                Ok(dir) => dir.join(path),
+                Err(_) => path.to_path_buf(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffer(path: &str, y: usize) -> BufferState {
+        BufferState {
+            path: PathBuf::from(path),
+            carets: vec![(y + 2, 4), (y + 3, 0)],
+            x: 0,
+            y,
+        }
+    }
+
+    #[test]
+    fn parse_test() {
+        let session = Session { buffers: vec![buffer("/tmp/a file.txt", 10), buffer("/b", 0)] };
+        let text = session.to_text();
+        assert_eq!(text,
+                   "MAL_SESSION 1\nbuffer 0 10 2 12 4 13 0 /tmp/a file.txt\nbuffer 0 0 2 2 4 3 0 \
+                    /b\n");
+        assert_eq!(Session::parse(&text), Ok(session));
+        assert!(Session::parse("garbage").is_err());
+        assert!(Session::parse("MAL_SESSION 1\nbuffer 0 0 1 2\n").is_err());
+        assert_eq!(directory_session_path(Path::new("/home/user/src"))
+                       .map(|path| path.file_name().unwrap().to_string_lossy().into_owned()),
+                   Some(String::from("dir%2Fhome%2Fuser%2Fsrc")));
+        assert_ne!(directory_session_path(Path::new("/a%/b")),
+                   directory_session_path(Path::new("/a/%b")));
+        assert_eq!(session_path("./work"), Some(PathBuf::from("./work")));
+        let deep = |leaf: &str| {
+            let dir = format!("{}/{}", "/directory".repeat(30), leaf);
+            directory_session_path(Path::new(&dir)).unwrap()
+        };
+        let name = deep("a").file_name().unwrap().to_string_lossy().into_owned();
+        assert!(name.len() <= MAX_NAME);
+        assert!(name.contains("%h"));
+        assert_ne!(deep("a"), deep("b"));
+    }
+
+    #[test]
+    fn remember_test() {
+        let mut positions = Session::new();
+        positions.remember(buffer("/a", 1));
+        positions.remember(buffer("/b", 2));
+        positions.remember(buffer("/a", 3));
+        assert_eq!(positions.buffers.len(), 2);
+        assert_eq!(positions.buffers[0], buffer("/a", 3));
+        assert_eq!(positions.find(Path::new("/b")), Some(&buffer("/b", 2)));
+        assert_eq!(positions.find(Path::new("/c")), None);
+        for idx in 0..MAX_POSITIONS + 1 {
+            positions.remember(buffer(&format!("/{}", idx), 0));
+        }
+        assert_eq!(positions.buffers.len(), MAX_POSITIONS);
+    }
+}
@@ -0,0 +1,200 @@
+use std::cmp;
+
+/// Layout of the status line when the config sets no `status_format`.
+pub const DEFAULT_FORMAT: &str = " %b %f %R %m %M%=%s %c %t %e %l %r:%C %p%% ";
+
+/// What the status line can show.
+pub struct Status<'a> {
+    pub filename: &'a str,
+    pub modified: bool,
+    pub readonly: bool,
+    /// 0-based index of the buffer shown, and the number of buffers open.
+    pub buffer: usize,
+    pub buffers: usize,
+    pub mode: &'a str,
+    pub line_ending: &'a str,
+    pub encoding: &'a str,
+    pub filetype: &'a str,
+    /// Characters selected by the main caret.
+    pub selected: usize,
+    pub carets: usize,
+    /// 0-based position of the main caret.
+    pub row: usize,
+    pub col: usize,
+    pub lines: usize,
+}
+
+/// Renders the status line `width` cells wide. The format expands
+///
+/// * `%f` file name, shortened to fit, and `%F` the full name
+/// * `%m` `[+]` when modified and `%R` `[RO]` when read-only
+/// * `%b` buffer number and count, when there is more than one
+/// * `%M` edit mode, `%t` file type, `%e` encoding, `%l` line ending
+/// * `%s` selection size and `%c` caret count, when there is more than one
+/// * `%r`, `%C`, `%L` and `%p` line, column, line count and percentage
+/// * `%%` a literal `%`
+///
+/// and `%=` right-aligns everything after it, at least a space away. A
+/// placeholder expanding to nothing also swallows the space following it.
+pub fn render(format: &str, status: &Status, width: usize) -> String {
+    let (left, right) = expand(format, status, status.filename);
+    let gap = if right.is_empty() { 0 } else { 1 };
+    let len = left.chars().count() + gap + right.chars().count();
+    let (left, right) = if len > width {
+        let budget = status.filename.chars().count().saturating_sub(len - width);
+        expand(format, status, &shorten_path(status.filename, budget))
+    } else {
+        (left, right)
+    };
+    let len = left.chars().count() + right.chars().count();
+    let mut line = left;
+    for _ in 0..cmp::max(gap, width.saturating_sub(len)) {
+        line.push(' ');
+    }
+    line.push_str(&right);
+    line.chars().take(width).collect()
+}
+
+fn expand(format: &str, status: &Status, filename: &str) -> (String, String) {
+    let mut left = String::new();
+    let mut right = None;
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        let out = match right {
+            Some(ref mut right) => right,
+            None => &mut left,
+        };
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        let text = match chars.next() {
+            Some('f') => String::from(filename),
+            Some('F') => String::from(status.filename),
+            Some('m') => String::from(if status.modified { "[+]" } else { "" }),
+            Some('R') => String::from(if status.readonly { "[RO]" } else { "" }),
+            Some('b') if status.buffers > 1 => format!("[{}/{}]", status.buffer + 1, status.buffers),
+            Some('b') => String::new(),
+            Some('M') => String::from(status.mode),
+            Some('t') => String::from(status.filetype),
+            Some('e') => String::from(status.encoding),
+            Some('l') => String::from(status.line_ending),
+            Some('s') if status.selected > 0 => format!("{} selected", status.selected),
+            Some('s') => String::new(),
+            Some('c') if status.carets > 1 => format!("{} carets", status.carets),
+            Some('c') => String::new(),
+            Some('r') => (status.row + 1).to_string(),
+            Some('C') => (status.col + 1).to_string(),
+            Some('L') => status.lines.to_string(),
+            Some('p') => ((status.row + 1) * 100 / status.lines).to_string(),
+            Some('=') => {
+                if right.is_none() {
+                    right = Some(String::new());
+                }
+                continue;
+            }
+            Some(other) => {
+                out.push(other);
+                continue;
+            }
+            None => break,
+        };
+        if text.is_empty() && chars.peek() == Some(&' ') {
+            chars.next();
+        }
+        out.push_str(&text);
+    }
+    (left, right.unwrap_or_default())
+}
+
+/// Replaces the home directory at the start of the path with `~`.
+pub fn tilde(path: &str, home: Option<&str>) -> String {
+    match home {
+        Some(home) if !home.is_empty() && path.starts_with(home) &&
+                      path[home.len()..].starts_with('/') => format!("~{}", &path[home.len()..]),
+        _ => String::from(path),
+    }
+}
+
+/// Shortens the path to at most `max` characters by cutting directories down
+/// to their first letter, from the left, then by cutting the front off.
+pub fn shorten_path(path: &str, max: usize) -> String {
+    let mut parts: Vec<String> = path.split('/').map(String::from).collect();
+    let last = parts.len() - 1;
+    for idx in 0..last {
+        if parts.join("/").chars().count() <= max {
+            break;
+        }
+        let part = parts[idx].clone();
+        let keep = if part.starts_with('.') { 2 } else { 1 };
+        parts[idx] = part.chars().take(keep).collect();
+    }
+    let short = parts.join("/");
+    let len = short.chars().count();
+    if len <= max {
+        short
+    } else if max == 0 {
+        String::new()
+    } else {
+        let mut cut = String::from("…");
+        cut.extend(short.chars().skip(len - max + 1));
+        cut
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status() -> Status<'static> {
+        Status {
+            filename: "src/main.rs",
+            modified: false,
+            readonly: false,
+            buffer: 0,
+            buffers: 1,
+            mode: "NORMAL",
+            line_ending: "LF",
+            encoding: "utf-8",
+            filetype: "rust",
+            selected: 0,
+            carets: 1,
+            row: 9,
+            col: 4,
+            lines: 40,
+        }
+    }
+
+    #[test]
+    fn render_test() {
+        assert_eq!(render("%f %m %M%=%r:%C %p%%", &status(), 30),
+                   "src/main.rs NORMAL    10:5 25%");
+        let mut modified = status();
+        modified.modified = true;
+        modified.selected = 3;
+        modified.carets = 2;
+        assert_eq!(render("%f %m %s %c|%t %e %l %L", &modified, 52),
+                   "src/main.rs [+] 3 selected 2 carets|rust utf-8 LF 40");
+        assert_eq!(render("%s %c|%q", &status(), 10), "|q        ");
+        modified.readonly = true;
+        modified.buffer = 1;
+        modified.buffers = 3;
+        assert_eq!(render("%b %f %R|", &modified, 24), "[2/3] src/main.rs [RO]| ");
+        assert_eq!(render("%b %f %R|", &status(), 13), "src/main.rs |");
+        // The file name gives way first when the line is too narrow.
+        assert_eq!(render("%f%=%r:%C", &status(), 12), "…ain.rs 10:5");
+        assert_eq!(render("%f%=%r:%C", &status(), 3), " 10");
+    }
+
+    #[test]
+    fn shorten_path_test() {
+        assert_eq!(shorten_path("src/main.rs", 20), "src/main.rs");
+        assert_eq!(shorten_path("/home/user/.config/mal/config", 20), "/h/u/.c/mal/config");
+        assert_eq!(shorten_path("/home/user/.config/mal/config", 16), "/h/u/.c/m/config");
+        assert_eq!(shorten_path("/home/user/.config/mal/config", 14), "…u/.c/m/config");
+        assert_eq!(shorten_path("/a/very_long_file_name", 8), "…le_name");
+        assert_eq!(tilde("/home/user/a.txt", Some("/home/user")), "~/a.txt");
+        assert_eq!(tilde("/home/username", Some("/home/user")), "/home/username");
+        assert_eq!(tilde("a.txt", None), "a.txt");
+    }
+}